@@ -0,0 +1,54 @@
+//! 掷骰子机器人示例。
+//!
+//! ```text
+//! cargo run --example dice_bot -- [机器人ID]
+//! ```
+//!
+//! 连接设置与 `t3xt run` 相同，读取 t3xt.toml 和 T3XT_* 环境变量。
+//! 支持 `/roll [NdM]`、`/ping` 和 `/secret`，提到 deploy 的消息会收到 🚀
+
+use anyhow::Result;
+use regex::Regex;
+use t3xt::{bot::Bot, config::Config, logging};
+
+/// 一次最多掷的骰子数
+const MAX_DICE: u32 = 20;
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let mut config = Config::load(None)?;
+    config.client.id = std::env::args().nth(1).unwrap_or_else(|| "dicebot".to_string());
+    config.validate()?;
+    let _log_guard = logging::init(&config.log, &config.telemetry, &config.client.id)?;
+
+    Bot::new(config)
+        .command("roll", |ctx| async move {
+            let Some((count, sides)) = parse_dice(ctx.args()) else {
+                return ctx.reply("用法: /roll [NdM]，如 /roll 2d6").await;
+            };
+            let rolls: Vec<u32> = (0..count).map(|_| rand::random_range(1..=sides)).collect();
+            let total: u32 = rolls.iter().sum();
+            let rolls: Vec<String> = rolls.iter().map(u32::to_string).collect();
+            ctx.reply(format!("🎲 {}d{}: {} = {}", count, sides, rolls.join(" + "), total)).await
+        })
+        .command("ping", |ctx| async move { ctx.reply("pong").await })
+        .command("secret", |ctx| async move {
+            let number: u32 = rand::random_range(1..=100);
+            ctx.dm(ctx.sender(), format!("你的幸运数字是 {}", number)).await
+        })
+        .pattern(Regex::new(r"(?i)\bdeploy(ed|ing)?\b")?, |ctx| async move { ctx.react("🚀").await })
+        .run()
+        .await;
+    Ok(())
+}
+
+/// 解析 "NdM"，省略时为 1d6
+fn parse_dice(spec: &str) -> Option<(u32, u32)> {
+    if spec.is_empty() {
+        return Some((1, 6));
+    }
+    let (count, sides) = spec.split_once('d')?;
+    let count = if count.is_empty() { 1 } else { count.parse().ok()? };
+    let sides = sides.parse().ok()?;
+    ((1..=MAX_DICE).contains(&count) && sides >= 2).then_some((count, sides))
+}
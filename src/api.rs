@@ -0,0 +1,147 @@
+use crate::{hooks::PeerInfo, message::*, server::ServerState};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{ConnectInfo, Path, Query, Request, State},
+    http::{header, StatusCode},
+    middleware::{self, Next},
+    response::{IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
+use tracing::info;
+
+/// GET 历史消息时默认返回的条数
+const DEFAULT_HISTORY_LIMIT: usize = 50;
+
+/// `POST /rooms/{room}/messages` 的请求体
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct PostMessage {
+    content: String,
+    /// 发送者ID，默认为 api.sender_id，其他ID需要在 api.senders 中
+    #[serde(default)]
+    sender: Option<String>,
+    /// 回复的消息ID
+    #[serde(default)]
+    reply_to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct HistoryQuery {
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct PeersQuery {
+    room: Option<String>,
+}
+
+/// 带 JSON 错误信息的响应
+struct ApiError(StatusCode, &'static str);
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        let mut response = (self.0, Json(json!({ "error": self.1 }))).into_response();
+        if self.0 == StatusCode::UNAUTHORIZED {
+            response.headers_mut().insert(header::WWW_AUTHENTICATE, header::HeaderValue::from_static("Bearer"));
+        }
+        response
+    }
+}
+
+/// 在 `listen` 上提供 HTTP API，所有请求都需要 Bearer 令牌
+pub async fn serve(listen: SocketAddr, state: Arc<ServerState>) -> Result<()> {
+    let app = Router::new()
+        .route("/rooms/{room}/messages", get(history).post(post_message))
+        .route("/peers", get(peers))
+        .layer(middleware::from_fn_with_state(Arc::clone(&state), authorize))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(listen).await
+        .with_context(|| format!("Failed to bind API listener {}", listen))?;
+    info!("HTTP API 监听地址: http://{}", listen);
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .context("API listener failed")?;
+    Ok(())
+}
+
+/// 按当前配置检查令牌，重新加载后立即生效
+async fn authorize(State(state): State<Arc<ServerState>>, request: Request, next: Next) -> Response {
+    let token = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim);
+    let Some(token) = token.filter(|token| !token.is_empty()) else {
+        return ApiError(StatusCode::UNAUTHORIZED, "missing bearer token").into_response();
+    };
+    let authorized = state.config.read().await.api.tokens.iter().any(|allowed| {
+        // 逐字节比较耗时与内容无关，避免通过响应时间猜测令牌
+        !allowed.is_empty() && ring::constant_time::verify_slices_are_equal(allowed.as_bytes(), token.as_bytes()).is_ok()
+    });
+    if !authorized {
+        return ApiError(StatusCode::UNAUTHORIZED, "invalid bearer token").into_response();
+    }
+    next.run(request).await
+}
+
+async fn post_message(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    Path(room): Path<String>,
+    Json(body): Json<PostMessage>,
+) -> Result<(StatusCode, Json<Message>), ApiError> {
+    let room = room.trim_start_matches('#').to_string();
+    if room.is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "room must not be empty"));
+    }
+    if body.content.trim().is_empty() {
+        return Err(ApiError(StatusCode::BAD_REQUEST, "content must not be empty"));
+    }
+    let (sender_id, limit) = {
+        let config = state.config.read().await;
+        // 只能以配置的机器人ID发送，不能冒充用户
+        let sender_id = match body.sender {
+            Some(sender) if sender != config.api.sender_id && !config.api.senders.contains(&sender) => {
+                return Err(ApiError(StatusCode::FORBIDDEN, "sender is not allowed"));
+            }
+            Some(sender) => sender,
+            None => config.api.sender_id.clone(),
+        };
+        (sender_id, config.transport.max_message_size)
+    };
+    let sender = PeerInfo {
+        addr: remote.to_string(),
+        client_id: sender_id,
+        room,
+        bot: true,
+    };
+    let mut message = Message::new_text(sender.client_id.clone(), body.content);
+    message.reply_to = body.reply_to;
+    if message.to_bytes().map_or(true, |bytes| bytes.len() > limit) {
+        return Err(ApiError(StatusCode::PAYLOAD_TOO_LARGE, "message exceeds transport.max_message_size"));
+    }
+    info!(sender = %sender.client_id, room = %sender.room, "HTTP API 发送消息");
+    match state.publish(&sender, message).await {
+        Some(message) => Ok((StatusCode::CREATED, Json(message))),
+        None => Err(ApiError(StatusCode::FORBIDDEN, "message rejected")),
+    }
+}
+
+async fn history(
+    State(state): State<Arc<ServerState>>,
+    Path(room): Path<String>,
+    Query(query): Query<HistoryQuery>,
+) -> Json<Vec<Message>> {
+    let room = room.trim_start_matches('#');
+    Json(state.recent(room, query.limit.unwrap_or(DEFAULT_HISTORY_LIMIT)).await)
+}
+
+async fn peers(State(state): State<Arc<ServerState>>, Query(query): Query<PeersQuery>) -> Json<Vec<MemberInfo>> {
+    let room = query.room.as_deref().map(|room| room.trim_start_matches('#'));
+    Json(state.members(room).await)
+}
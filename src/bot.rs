@@ -0,0 +1,284 @@
+use crate::{
+    client::{Client, Incoming, SessionCache},
+    config::Config,
+    message::*,
+    routing::SeenMessages,
+};
+use anyhow::Result;
+use chrono::{DateTime, Utc};
+use regex::Regex;
+use std::{future::Future, pin::Pin, sync::Arc, time::Duration};
+use tokio::{
+    sync::Mutex,
+    time::{self, Instant},
+};
+use tokio_stream::StreamExt;
+use tracing::{info, info_span, warn, Instrument};
+
+/// 断线后第一次重连前的等待时间，之后每次加倍
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+
+/// 重连等待时间的上限
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// 默认每秒最多发送的消息数，低于服务器默认的限流突发值
+const DEFAULT_RATE_LIMIT: u32 = 5;
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+type Handler = Arc<dyn Fn(Context) -> HandlerFuture + Send + Sync>;
+
+/// 触发处理函数的条件
+enum Trigger {
+    /// `/name 参数`
+    Command(String),
+    /// 匹配消息正文的正则表达式
+    Pattern(Regex),
+}
+
+impl Trigger {
+    /// 匹配时返回命令参数和正则捕获组
+    fn matches(&self, content: &str) -> Option<(String, Vec<String>)> {
+        match self {
+            Trigger::Command(name) => {
+                let rest = content.strip_prefix('/')?;
+                let (command, args) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+                (command == name).then(|| (args.trim().to_string(), Vec::new()))
+            }
+            Trigger::Pattern(regex) => {
+                let captures = regex.captures(content)?;
+                let groups = captures
+                    .iter()
+                    .map(|group| group.map_or_else(String::new, |m| m.as_str().to_string()))
+                    .collect();
+                Some((content.to_string(), groups))
+            }
+        }
+    }
+}
+
+/// 按间隔发送，避免触发服务器限流
+struct Limiter {
+    interval: Duration,
+    next: Mutex<Instant>,
+}
+
+impl Limiter {
+    fn new(per_sec: u32) -> Self {
+        let interval = match per_sec {
+            0 => Duration::ZERO,
+            n => Duration::from_secs(1) / n,
+        };
+        Self {
+            interval,
+            next: Mutex::new(Instant::now()),
+        }
+    }
+
+    async fn wait(&self) {
+        let at = {
+            let mut next = self.next.lock().await;
+            let at = (*next).max(Instant::now());
+            *next = at + self.interval;
+            at
+        };
+        time::sleep_until(at).await;
+    }
+}
+
+/// 处理函数收到的上下文：触发的消息以及回复、私信和回应的方法
+pub struct Context {
+    client: Client,
+    message: Message,
+    args: String,
+    captures: Vec<String>,
+    limiter: Arc<Limiter>,
+}
+
+impl Context {
+    /// 触发处理函数的消息
+    pub fn message(&self) -> &Message {
+        &self.message
+    }
+
+    pub fn sender(&self) -> &str {
+        &self.message.sender_id
+    }
+
+    /// 命令后面的参数，已去掉首尾空白；正则触发时为整条正文
+    pub fn args(&self) -> &str {
+        &self.args
+    }
+
+    /// 正则捕获组，第 0 项为整个匹配，未参与匹配的组为空字符串；命令触发时为空
+    pub fn captures(&self) -> &[String] {
+        &self.captures
+    }
+
+    pub fn client(&self) -> &Client {
+        &self.client
+    }
+
+    /// 回复触发的消息：房间消息以讨论串回复，私信以私信回复
+    pub async fn reply(&self, content: impl Into<String>) -> Result<()> {
+        if matches!(self.message.message_type, MessageType::Direct { .. }) {
+            return self.dm(&self.message.sender_id, content).await;
+        }
+        let reply = Message::new_reply(self.client.client_id().to_string(), content.into(), self.message.id.clone());
+        self.limiter.wait().await;
+        self.client.send(reply).await
+    }
+
+    /// 在当前房间发言
+    pub async fn say(&self, content: impl Into<String>) -> Result<()> {
+        self.limiter.wait().await;
+        self.client.send_text(content).await.map(drop)
+    }
+
+    /// 给某个客户端发私信
+    pub async fn dm(&self, recipient: &str, content: impl Into<String>) -> Result<()> {
+        self.limiter.wait().await;
+        self.client.send_direct(recipient, content).await.map(drop)
+    }
+
+    /// 对触发的消息添加表情回应，私信不支持回应
+    pub async fn react(&self, emoji: &str) -> Result<()> {
+        let reaction = Message::new(self.client.client_id().to_string(), MessageType::Reaction {
+            target_id: self.message.id.clone(),
+            emoji: emoji.to_string(),
+        });
+        self.limiter.wait().await;
+        self.client.send(reaction).await
+    }
+}
+
+/// 机器人：注册命令和正则处理函数后调用 [`Bot::run`]。
+///
+/// 只处理其他用户发来的文本消息和私信，忽略机器人自己和其他机器人的消息，
+/// 也忽略启动前的历史消息。每条消息的处理函数在单独的任务中运行
+pub struct Bot {
+    config: Config,
+    /// 重连时恢复 TLS 会话，登录随 0-RTT 数据发出
+    sessions: SessionCache,
+    handlers: Vec<(Trigger, Handler)>,
+    rate_limit: u32,
+}
+
+impl Bot {
+    /// 按 `config.client` 连接服务器，登录时声明为机器人
+    pub fn new(mut config: Config) -> Self {
+        config.client.bot = true;
+        Self {
+            config,
+            sessions: SessionCache::default(),
+            handlers: Vec::new(),
+            rate_limit: DEFAULT_RATE_LIMIT,
+        }
+    }
+
+    /// 注册斜杠命令，`name` 不含斜杠
+    pub fn command<F, Fut>(mut self, name: &str, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        let name = name.trim_start_matches('/').to_string();
+        self.handlers.push((Trigger::Command(name), boxed(handler)));
+        self
+    }
+
+    /// 注册匹配消息正文的正则表达式
+    pub fn pattern<F, Fut>(mut self, regex: Regex, handler: F) -> Self
+    where
+        F: Fn(Context) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<()>> + Send + 'static,
+    {
+        self.handlers.push((Trigger::Pattern(regex), boxed(handler)));
+        self
+    }
+
+    /// 每秒最多发送的消息数，0 表示不限制
+    pub fn rate_limit(mut self, per_sec: u32) -> Self {
+        self.rate_limit = per_sec;
+        self
+    }
+
+    /// 连接服务器并处理消息，断线或连接失败后按指数退避重连，不会返回
+    pub async fn run(self) {
+        let limiter = Arc::new(Limiter::new(self.rate_limit));
+        let started_at = Utc::now();
+        // 重连后服务器会回放历史，已处理过的消息不再触发
+        let mut seen = SeenMessages::default();
+        let mut backoff = INITIAL_BACKOFF;
+        loop {
+            match Client::connect_with_sessions(&self.config, &self.sessions).await {
+                Ok((client, incoming)) => {
+                    info!("机器人 {} 已连接", client.client_id());
+                    backoff = INITIAL_BACKOFF;
+                    self.serve(&client, incoming, &limiter, started_at, &mut seen).await;
+                    client.disconnect().await;
+                    warn!("与服务器断开连接");
+                }
+                Err(e) => warn!("连接失败: {:#}", e),
+            }
+            info!("{} 秒后重连", backoff.as_secs());
+            time::sleep(backoff).await;
+            backoff = (backoff * 2).min(MAX_BACKOFF);
+        }
+    }
+
+    async fn serve(
+        &self,
+        client: &Client,
+        mut incoming: Incoming,
+        limiter: &Arc<Limiter>,
+        started_at: DateTime<Utc>,
+        seen: &mut SeenMessages,
+    ) {
+        while let Some(message) = incoming.next().await {
+            if message.sender_id == client.client_id() || message.bot {
+                continue;
+            }
+            // 首次连接时回放的是启动前的房间历史，时间由服务器填写；私信不会回放
+            let replayed = matches!(message.message_type, MessageType::Text { .. }) && message.timestamp < started_at;
+            if replayed {
+                continue;
+            }
+            let (MessageType::Text { content } | MessageType::Direct { content, .. }) = &message.message_type else {
+                continue;
+            };
+            if !seen.insert(&message.id) {
+                continue;
+            }
+            for (trigger, handler) in &self.handlers {
+                let Some((args, captures)) = trigger.matches(content) else {
+                    continue;
+                };
+                let context = Context {
+                    client: client.clone(),
+                    message: message.clone(),
+                    args,
+                    captures,
+                    limiter: Arc::clone(limiter),
+                };
+                let span = info_span!("bot_handler", message_id = %message.id, sender = %message.sender_id);
+                let future = handler(context);
+                tokio::spawn(
+                    async move {
+                        if let Err(e) = future.await {
+                            warn!("处理消息失败: {:#}", e);
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+        }
+    }
+}
+
+fn boxed<F, Fut>(handler: F) -> Handler
+where
+    F: Fn(Context) -> Fut + Send + Sync + 'static,
+    Fut: Future<Output = Result<()>> + Send + 'static,
+{
+    Arc::new(move |context| Box::pin(handler(context)))
+}
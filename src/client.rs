@@ -1,156 +1,606 @@
-use crate::{crypto, message::*};
-use anyhow::{Context, Result};
+use crate::{
+    config::{ClientTransport, Config},
+    console, crypto,
+    message::*,
+    p2p::Direct,
+    tcp::{self, Frame},
+    telemetry,
+};
+use anyhow::{bail, Context, Result};
+use bytes::Bytes;
 use quinn::{Connection, Endpoint};
-use std::net::SocketAddr;
+use rustls::ClientConfig as RustlsClientConfig;
+use std::{
+    net::SocketAddr,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{self, Poll},
+    time::Duration,
+};
 use tokio::{
-    io::{AsyncBufReadExt, BufReader},
+    io::{AsyncWriteExt, ReadHalf, WriteHalf},
+    net::{TcpSocket, TcpStream},
     sync::mpsc,
+    task::JoinHandle,
 };
-use tracing::{info, warn};
+use tokio_rustls::{client::TlsStream, TlsConnector};
+use tokio_stream::{wrappers::UnboundedReceiverStream, Stream};
+use tracing::{info, info_span, instrument, warn, Instrument};
+
+pub use crate::crypto::SessionCache;
 
+/// 已连接服务器的客户端句柄，可以克隆后在多个任务中发送
+#[derive(Clone)]
 pub struct Client {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
     client_id: String,
-    endpoint: Endpoint,
-    connection: Option<Connection>,
+    bot: bool,
+    /// 当前所在房间，发送的消息归属于该房间
+    room: Mutex<String>,
+    transport: Transport,
+    /// 与其他客户端的直连，只在 QUIC 连接上可用
+    direct: Option<Arc<Direct>>,
+    /// 恢复了 TLS 会话，登录作为 0-RTT 早期数据送达
+    zero_rtt: bool,
+    tasks: Vec<JoinHandle<()>>,
 }
 
-impl Client {
-    pub fn new(client_id: String) -> Result<Self> {
-        let cert_path = std::path::Path::new("certs/server.crt");
-        if !cert_path.exists() {
-            return Err(anyhow::anyhow!(
-                "certs/server.crt not found."
-            ));
-        }
+/// QUIC 连接的统计，见 [`Client::stats`]
+#[derive(Debug, Clone, Copy)]
+pub struct NetStats {
+    /// 当前的往返时间估计
+    pub rtt: Duration,
+    /// 拥塞窗口（字节）
+    pub cwnd: u64,
+    pub congestion_events: u64,
+    pub sent_datagrams: u64,
+    pub sent_bytes: u64,
+    pub received_datagrams: u64,
+    pub received_bytes: u64,
+    pub lost_packets: u64,
+    pub lost_bytes: u64,
+    /// 路径 MTU 探测包，不计入丢包
+    pub mtu_probes: u64,
+    pub lost_mtu_probes: u64,
+    /// 探测到路径 MTU 变小的次数
+    pub black_holes: u64,
+}
+
+/// 握手完成、尚未拆分读写的连接。QUIC 连接可能已在 0-RTT 中完成登录
+enum Connected {
+    Quic { endpoint: Endpoint, connection: Connection, zero_rtt: bool },
+    Tcp(Box<TlsStream<TcpStream>>),
+}
 
-        println!("found cert");   
-        let rustls_config = crypto::create_client_config_with_cert(cert_path)?;
-        let client_config = crypto::create_quinn_client_config(rustls_config);
+type TcpWriter = Arc<tokio::sync::Mutex<WriteHalf<TlsStream<TcpStream>>>>;
 
-        // 如果你有多网卡或需要指定出口IP，可以将 "0.0.0.0" 替换为具体的本地IP。
-        let mut endpoint = Endpoint::client("0.0.0.0:0".parse()?)?;
-        endpoint.set_default_client_config(client_config);
+/// 与服务器的连接：QUIC，或 UDP 被阻断时的 TCP+TLS
+enum Transport {
+    Quic { endpoint: Endpoint, connection: Connection },
+    Tcp { writer: TcpWriter },
+}
 
-        Ok(Self {
-            client_id,
-            endpoint,
-            connection: None,
-        })
+/// 从服务器收到的消息流，输入提示等短暂信号通过 [`Incoming::next_signal`] 读取
+pub struct Incoming {
+    messages: UnboundedReceiverStream<Message>,
+    signals: mpsc::UnboundedReceiver<Signal>,
+}
+
+/// 从服务器收到的数据报信号，如输入提示
+pub struct Signals {
+    signals: mpsc::UnboundedReceiver<Signal>,
+}
+
+impl Incoming {
+    /// 下一个收到的数据报信号，连接关闭后返回 None
+    pub async fn next_signal(&mut self) -> Option<Signal> {
+        self.signals.recv().await
+    }
+
+    /// 拆分为消息流和信号，便于在 `select!` 中同时等待
+    pub fn split(self) -> (impl Stream<Item = Message> + Unpin, Signals) {
+        (self.messages, Signals { signals: self.signals })
+    }
+}
+
+impl Signals {
+    /// 下一个信号，连接关闭后返回 None
+    pub async fn next(&mut self) -> Option<Signal> {
+        self.signals.recv().await
+    }
+}
+
+impl Stream for Incoming {
+    type Item = Message;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut task::Context<'_>) -> Poll<Option<Message>> {
+        Pin::new(&mut self.messages).poll_next(cx)
+    }
+}
+
+impl Client {
+    /// 按 `config.client` 连接服务器并加入房间。返回发送用的句柄和接收消息的流。
+    /// 设置了 `client.fingerprint` 时按指纹固定证书，否则用 `tls.cert` 验证服务器。
+    /// 每次连接使用新的会话缓存，需要重连时恢复会话见 [`Client::connect_with_sessions`]
+    pub async fn connect(config: &Config) -> Result<(Self, Incoming)> {
+        Self::connect_with_sessions(config, &SessionCache::default()).await
     }
 
-    pub async fn connect(&mut self, server_addr: &str, port: u16) -> Result<()> {
-        let addr: SocketAddr = format!("{}:{}", server_addr, port).parse()
+    /// 与 [`Client::connect`] 相同，会话票据保存在 `sessions` 中。
+    /// 用同一个缓存重连时恢复 TLS 会话，QUIC 上登录随 0-RTT 数据发出
+    pub async fn connect_with_sessions(config: &Config, sessions: &SessionCache) -> Result<(Self, Incoming)> {
+        let settings = &config.client;
+        let mut rustls_config = if settings.fingerprint.is_empty() {
+            let cert_path = config.tls.cert.as_path();
+            if !cert_path.exists() {
+                return Err(anyhow::anyhow!(
+                    "{} not found.", cert_path.display()
+                ));
+            }
+            info!("使用证书 {}", cert_path.display());
+            crypto::create_client_config_with_cert(cert_path, sessions)?
+        } else {
+            info!("按指纹固定服务器证书 {}", settings.fingerprint);
+            crypto::create_client_config_with_fingerprint(&settings.fingerprint, sessions)?
+        };
+        rustls_config.enable_early_data = settings.zero_rtt;
+
+        if settings.target.is_empty() {
+            bail!("client.target is empty; discover a server first or set a target");
+        }
+        let addr: SocketAddr = format!("{}:{}", settings.target, settings.port).parse()
             .context("Invalid server address")?;
-        
+
         info!("connect to {}", addr);
-        println!("connecting to {}...", addr);
+        console::line(format_args!("connecting to {}...", addr));
 
-        let connection = self.endpoint
-            .connect(addr, "localhost")?
-            .await
-            .context("Failed to establish connection")?;
-        println!("connected");
-        
-        self.connection = Some(connection);
-        Ok(())
+        let connected = Self::establish(config, rustls_config, addr).await?;
+
+        let limit = config.transport.max_message_size;
+        let (message_tx, message_rx) = mpsc::unbounded_channel();
+        let (signal_tx, signal_rx) = mpsc::unbounded_channel();
+        let (transport, direct, zero_rtt, tasks) = match connected {
+            Connected::Quic { endpoint, connection, zero_rtt } => {
+                let span = info_span!(
+                    "connection",
+                    peer = %connection.remote_address(),
+                    conn_id = connection.stable_id(),
+                    client_id = %settings.id,
+                );
+                let direct = Direct::new(config, endpoint.clone(), connection.clone(), message_tx.clone())?;
+                let tasks = vec![
+                    tokio::spawn(
+                        Self::receive_messages(connection.clone(), message_tx, limit, Arc::clone(&direct))
+                            .instrument(span.clone()),
+                    ),
+                    tokio::spawn(Self::receive_signals(connection.clone(), signal_tx).instrument(span.clone())),
+                    tokio::spawn(Arc::clone(&direct).accept().instrument(span)),
+                ];
+                console::line(if zero_rtt { "connected (0-RTT)" } else { "connected" });
+                (Transport::Quic { endpoint, connection }, Some(direct), zero_rtt, tasks)
+            }
+            Connected::Tcp(stream) => {
+                let span = info_span!(
+                    "connection",
+                    peer = %addr,
+                    transport = "tcp",
+                    client_id = %settings.id,
+                );
+                let (reader, writer) = tokio::io::split(*stream);
+                let writer = Arc::new(tokio::sync::Mutex::new(writer));
+                let idle_timeout = Duration::from_secs(config.transport.idle_timeout_secs);
+                let keep_alive = Duration::from_secs(config.transport.keep_alive_secs);
+                let tasks = vec![
+                    tokio::spawn(
+                        Self::receive_frames(reader, message_tx, signal_tx, limit, idle_timeout)
+                            .instrument(span.clone()),
+                    ),
+                    tokio::spawn(Self::keep_alive(Arc::clone(&writer), keep_alive).instrument(span)),
+                ];
+                console::line("connected (TCP)");
+                (Transport::Tcp { writer }, None, false, tasks)
+            }
+        };
+
+        let client = Self {
+            inner: Arc::new(Inner {
+                client_id: settings.id.clone(),
+                bot: settings.bot,
+                room: Mutex::new(settings.room.trim_start_matches('#').to_string()),
+                transport,
+                direct,
+                zero_rtt,
+                tasks,
+            }),
+        };
+        // 登录：告知服务器本客户端ID，由服务器广播上线事件
+        if !zero_rtt {
+            client.join(&settings.room).await?;
+        }
+
+        let incoming = Incoming {
+            messages: UnboundedReceiverStream::new(message_rx),
+            signals: signal_rx,
+        };
+        Ok((client, incoming))
+    }
+
+    pub fn client_id(&self) -> &str {
+        &self.inner.client_id
+    }
+
+    /// 当前所在房间
+    pub fn room(&self) -> String {
+        self.inner.room.lock().unwrap().clone()
+    }
+
+    /// 本次连接是否恢复了 TLS 会话并在 0-RTT 中完成登录
+    pub fn zero_rtt(&self) -> bool {
+        self.inner.zero_rtt
+    }
+
+    /// 加入房间，服务器会回放该房间的历史消息
+    pub async fn join(&self, room: &str) -> Result<()> {
+        let join = Self::join_message(self.client_id(), self.inner.bot, room);
+        *self.inner.room.lock().unwrap() = join.room.clone();
+        self.send(join).await
     }
 
-    pub async fn disconnect(&mut self) -> Result<()> {
-        if let Some(connection) = &self.connection {
-            connection.close(0u32.into(), b"Goodbye");
-            self.connection = None;
-            println!("disconnected");
+    fn join_message(client_id: &str, bot: bool, room: &str) -> Message {
+        let mut join = Message::new_presence(client_id.to_string(), PresenceEvent::Join, None);
+        join.bot = bot;
+        join.room = room.trim_start_matches('#').to_string();
+        join
+    }
+
+    /// 发送消息到当前房间。与收件人有直连时，私信经直连发送
+    pub async fn send(&self, mut message: Message) -> Result<()> {
+        message.room = self.room();
+        if let Some(direct) = &self.inner.direct {
+            if direct.send(&message).await {
+                return Ok(());
+            }
         }
-        Ok(())
+        Self::send_message(&self.inner.transport, message).await
     }
 
-    pub async fn run_interactive(&mut self) -> Result<()> {
-
-        // 为信息队列准备
-        let (tx, mut rx) = mpsc::unbounded_channel::<Message>();
-        
-        let recv_connection = self.connection.as_ref().unwrap().clone();
-        let recv_task = tokio::spawn(async move {
-            loop{
-                match recv_connection.accept_uni().await{
-                    Ok(mut recvstream) => {
-                        match Self::receive_message(&mut recvstream).await{
-                            Ok(message) => {
-                                println!("{}", message.format_display());
-                            }
-                            Err(e) => {
-                                warn!("Failed to receive message: {}", e);
-                                break;
-                        }
+    /// 发送文本消息，返回消息以便之后编辑、删除或回复
+    pub async fn send_text(&self, content: impl Into<String>) -> Result<Message> {
+        let message = Message::new_text(self.client_id().to_string(), content.into());
+        self.send(message.clone()).await?;
+        Ok(message)
+    }
+
+    /// 发送私信，收件人可以在任何房间或联邦中的其他服务器上
+    pub async fn send_direct(&self, recipient: &str, content: impl Into<String>) -> Result<Message> {
+        let message = Message::new(self.client_id().to_string(), MessageType::Direct {
+            recipient: recipient.to_string(),
+            content: content.into(),
+        });
+        self.send(message.clone()).await?;
+        Ok(message)
+    }
+
+    /// 经服务器会合后与 `peer` 打洞直连，之后发给对方的私信不再经过服务器。
+    /// 失败时私信仍由服务器转发；只在 QUIC 连接上可用，对方也需要开启 `client.p2p`
+    pub async fn connect_direct(&self, peer: &str) -> Result<()> {
+        let direct = self.inner.direct.as_ref()
+            .context("Direct connections require a QUIC connection to the server")?;
+        direct.connect(peer).await
+    }
+
+    /// 当前直连的客户端ID
+    pub fn direct_peers(&self) -> Vec<String> {
+        self.inner.direct.as_ref().map(|direct| direct.peers()).unwrap_or_default()
+    }
+
+    /// 通过不可靠数据报发送短暂信号。TCP 连接上在后台写入，正在发送消息时可能丢弃
+    pub fn send_signal(&self, kind: SignalKind) -> Result<()> {
+        let data = Signal::new(self.client_id().to_string(), kind).to_bytes()?;
+        match &self.inner.transport {
+            Transport::Quic { connection, .. } => {
+                connection.send_datagram(Bytes::from(data))
+                    .context("Failed to send datagram")?;
+            }
+            Transport::Tcp { writer } => {
+                let writer = Arc::clone(writer);
+                tokio::spawn(async move {
+                    if let Ok(mut writer) = writer.try_lock() {
+                        let _ = tcp::write_frame(&mut *writer, &Frame::Signal(data)).await;
                     }
-                    }  
-                    Err(_) => {
+                });
+            }
+        }
+        Ok(())
+    }
+
+    /// 与服务器的 QUIC 连接的实时统计，TCP 连接上为 None
+    pub fn stats(&self) -> Option<NetStats> {
+        let Transport::Quic { connection, .. } = &self.inner.transport else {
+            return None;
+        };
+        let stats = connection.stats();
+        Some(NetStats {
+            rtt: stats.path.rtt,
+            cwnd: stats.path.cwnd,
+            congestion_events: stats.path.congestion_events,
+            sent_datagrams: stats.udp_tx.datagrams,
+            sent_bytes: stats.udp_tx.bytes,
+            received_datagrams: stats.udp_rx.datagrams,
+            received_bytes: stats.udp_rx.bytes,
+            lost_packets: stats.path.lost_packets,
+            lost_bytes: stats.path.lost_bytes,
+            mtu_probes: stats.path.sent_plpmtud_probes,
+            lost_mtu_probes: stats.path.lost_plpmtud_probes,
+            black_holes: stats.path.black_holes_detected,
+        })
+    }
+
+    /// 实际使用的传输方式，"quic" 或 "tcp"
+    pub fn transport(&self) -> &'static str {
+        match self.inner.transport {
+            Transport::Quic { .. } => "quic",
+            Transport::Tcp { .. } => "tcp",
+        }
+    }
+
+    /// 关闭连接并等待关闭帧发出，之后所有句柄都不可再用
+    pub async fn disconnect(&self) {
+        for task in &self.inner.tasks {
+            task.abort();
+        }
+        if let Some(direct) = &self.inner.direct {
+            direct.close();
+        }
+        match &self.inner.transport {
+            Transport::Quic { endpoint, connection } => {
+                connection.close(0u32.into(), b"Goodbye");
+                endpoint.wait_idle().await;
+            }
+            Transport::Tcp { writer } => {
+                let _ = writer.lock().await.shutdown().await;
+            }
+        }
+        console::line("disconnected");
+    }
+
+    /// 按 `client.transport` 建立连接。auto 模式下 QUIC 先行，`fallback_delay_ms` 内没有连上
+    /// 就同时尝试 TCP，先完成握手的一方胜出（类似 Happy Eyeballs）
+    async fn establish(config: &Config, rustls_config: RustlsClientConfig, addr: SocketAddr) -> Result<Connected> {
+        let quic = Self::connect_quic(config, rustls_config.clone(), addr);
+        let tcp = Self::connect_tcp(config, rustls_config, addr);
+        match config.client.transport {
+            ClientTransport::Quic => return quic.await,
+            ClientTransport::Tcp => return tcp.await,
+            ClientTransport::Auto => {}
+        }
+        tokio::pin!(quic, tcp);
+
+        let delay = Duration::from_millis(config.client.fallback_delay_ms);
+        let quic_result = tokio::select! {
+            result = &mut quic => Some(result),
+            _ = tokio::time::sleep(delay) => None,
+        };
+        match quic_result {
+            Some(Ok(connected)) => return Ok(connected),
+            Some(Err(e)) => {
+                warn!("QUIC 连接失败，改用 TCP: {:#}", e);
+                return tcp.await.map_err(|tcp_error| Self::both_failed(e, tcp_error));
+            }
+            None => info!("QUIC 在 {}ms 内未连上，同时尝试 TCP", delay.as_millis()),
+        }
+
+        tokio::select! {
+            result = &mut quic => match result {
+                Ok(connected) => Ok(connected),
+                Err(e) => tcp.await.map_err(|tcp_error| Self::both_failed(e, tcp_error)),
+            },
+            result = &mut tcp => match result {
+                Ok(connected) => Ok(connected),
+                Err(tcp_error) => quic.await.map_err(|e| Self::both_failed(e, tcp_error)),
+            },
+        }
+    }
+
+    fn both_failed(quic_error: anyhow::Error, tcp_error: anyhow::Error) -> anyhow::Error {
+        anyhow::anyhow!("Failed to establish connection: QUIC: {:#}; TCP: {:#}", quic_error, tcp_error)
+    }
+
+    async fn connect_quic(config: &Config, rustls_config: RustlsClientConfig, addr: SocketAddr) -> Result<Connected> {
+        let client_config = crypto::create_quinn_client_config(rustls_config, &config.transport)?;
+        // 多网卡或需要指定出口IP时，在配置中设置 client.bind
+        let mut endpoint = Endpoint::client(SocketAddr::new(config.client.bind, 0))?;
+        endpoint.set_default_client_config(client_config);
+
+        let connecting = endpoint.connect(addr, &config.tls.server_name)?;
+        let (connection, zero_rtt) = match connecting.into_0rtt() {
+            Ok((connection, accepted)) => {
+                let zero_rtt = Self::login_early(config, &connection, accepted).await?;
+                (connection, zero_rtt)
+            }
+            Err(connecting) => (connecting.await.context("Failed to establish connection")?, false),
+        };
+        info!("已通过 QUIC 连接 (0-RTT: {})", zero_rtt);
+        Ok(Connected::Quic { endpoint, connection, zero_rtt })
+    }
+
+    /// 有缓存的会话票据时，登录随握手一起作为 0-RTT 数据发出。0-RTT 数据可能被重放，
+    /// 所以只有幂等的登录提前发送，其他消息等握手完成后再发；服务器也只在握手完成后处理早期数据。
+    /// 返回服务器是否接受了 0-RTT；被拒绝时早期数据已丢弃，由调用方重新登录
+    async fn login_early(config: &Config, connection: &Connection, accepted: quinn::ZeroRttAccepted) -> Result<bool> {
+        let settings = &config.client;
+        let login = Self::send_on(connection, Self::join_message(&settings.id, settings.bot, &settings.room));
+        let (sent, accepted) = tokio::join!(login, accepted);
+        if let Some(reason) = connection.close_reason() {
+            return Err(reason).context("Failed to establish connection");
+        }
+        if !accepted {
+            info!("服务器拒绝了 0-RTT 数据");
+            return Ok(false);
+        }
+        sent?;
+        Ok(true)
+    }
+
+    async fn connect_tcp(config: &Config, mut rustls_config: RustlsClientConfig, addr: SocketAddr) -> Result<Connected> {
+        // TCP 上不发送早期数据，恢复会话也省不了往返。不用会话缓存，以免消耗或混入 QUIC 的 0-RTT 票据
+        rustls_config.resumption = rustls::client::Resumption::disabled();
+        let socket = if addr.is_ipv4() { TcpSocket::new_v4()? } else { TcpSocket::new_v6()? };
+        if !config.client.bind.is_unspecified() {
+            socket.bind(SocketAddr::new(config.client.bind, 0))?;
+        }
+        let stream = socket.connect(addr).await
+            .context("Failed to connect over TCP")?;
+        stream.set_nodelay(true)?;
+
+        let server_name = rustls::ServerName::try_from(config.tls.server_name.as_str())
+            .context("Invalid server name")?;
+        let stream = TlsConnector::from(Arc::new(rustls_config))
+            .connect(server_name, stream)
+            .await
+            .context("TLS handshake failed")?;
+        info!("已通过 TCP 连接");
+        Ok(Connected::Tcp(Box::new(stream)))
+    }
+
+    /// 服务器转来的直连地址交给 [`Direct`] 处理，其他消息交给接收流
+    async fn receive_messages(
+        connection: Connection,
+        tx: mpsc::UnboundedSender<Message>,
+        limit: usize,
+        direct: Arc<Direct>,
+    ) {
+        while let Ok(mut recvstream) = connection.accept_uni().await {
+            match Self::receive_message(&mut recvstream, limit).await {
+                Ok(Message { message_type: MessageType::PeerAddress { peer, addr, fingerprint }, .. }) => {
+                    tokio::spawn(Arc::clone(&direct).handle_address(peer, addr, fingerprint).in_current_span());
+                }
+                Ok(message) => {
+                    let span = info_span!("receive_message", message_id = %message.id, kind = message.kind());
+                    telemetry::set_parent(&span, &message);
+                    span.in_scope(|| info!("收到消息"));
+                    if tx.send(message).is_err() {
                         break;
                     }
+                }
+                // 单个流出错（超长、格式错误）只丢弃这一条消息
+                Err(e) => {
+                    warn!("Failed to receive message: {}", e);
+                    continue;
+                }
+            }
+        }
+    }
+
+    async fn receive_signals(connection: Connection, tx: mpsc::UnboundedSender<Signal>) {
+        while let Ok(datagram) = connection.read_datagram().await {
+            match Signal::from_bytes(&datagram) {
+                Ok(signal) => {
+                    if tx.send(signal).is_err() {
+                        break;
                     }
                 }
-            
-        });
-        
-        let send_connection = self.connection.as_ref().unwrap().clone();
-        let send_task = tokio::spawn(async move {
-            while let Some(message) = rx.recv().await {
-                if Self::send_message(&send_connection, message).await.is_err() {
+                Err(e) => warn!("Failed to parse datagram: {}", e),
+            }
+        }
+    }
+
+    /// TCP 连接上的帧：消息交给接收流，信号单独转发，Ping 只用于保活
+    async fn receive_frames(
+        mut reader: ReadHalf<TlsStream<TcpStream>>,
+        message_tx: mpsc::UnboundedSender<Message>,
+        signal_tx: mpsc::UnboundedSender<Signal>,
+        limit: usize,
+        idle_timeout: Duration,
+    ) {
+        loop {
+            let frame = match tokio::time::timeout(idle_timeout, tcp::read_frame(&mut reader, limit)).await {
+                Ok(Ok(Some(frame))) => frame,
+                Ok(Ok(None)) => break,
+                Ok(Err(e)) => {
+                    warn!("Failed to receive message: {}", e);
+                    break;
+                }
+                Err(_) => {
+                    warn!("TCP connection timed out");
+                    break;
+                }
+            };
+            match frame {
+                Frame::Message(data) => match Message::from_bytes(&data) {
+                    Ok(message) => {
+                        let span = info_span!("receive_message", message_id = %message.id, kind = message.kind());
+                        telemetry::set_parent(&span, &message);
+                        span.in_scope(|| info!("收到消息"));
+                        if message_tx.send(message).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => warn!("Failed to parse message: {}", e),
+                },
+                Frame::Signal(data) => match Signal::from_bytes(&data) {
+                    Ok(signal) => {
+                        let _ = signal_tx.send(signal);
+                    }
+                    Err(e) => warn!("Failed to parse signal: {}", e),
+                },
+                Frame::Ping => {}
+                Frame::Close(reason) => {
+                    info!("服务器关闭连接: {}", reason);
                     break;
                 }
             }
-        });
-        
-        // 用户输入处理
-        println!("输入消息并按回车发送，输入 '/quit' 退出");
-        println!("─────────────────────────────────────");
-        
-        let stdin = tokio::io::stdin();
-        let mut lines = BufReader::new(stdin).lines();
-        
-        while let Ok(Some(line)) = lines.next_line().await {
-            let input = line.trim();
-            
-            if input == "/quit" {
+        }
+    }
+
+    /// TCP 没有 QUIC 的保活机制，定期发送 Ping 让服务器知道连接仍然有效
+    async fn keep_alive(writer: TcpWriter, interval: Duration) {
+        let mut interval = tokio::time::interval(interval);
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            if tcp::write_frame(&mut *writer.lock().await, &Frame::Ping).await.is_err() {
                 break;
             }
-            
-            if input.is_empty() {
-                continue;
-            }
-            
-            let message = Message::new_text(self.client_id.clone(), input.to_string());
-            
-            if tx.send(message).is_err() {
-                break;
+        }
+    }
+
+    #[instrument(skip_all, fields(message_id = %message.id, kind = message.kind()))]
+    async fn send_message(transport: &Transport, mut message: Message) -> Result<()> {
+        telemetry::inject(&mut message);
+        match transport {
+            Transport::Quic { connection, .. } => Self::send_on(connection, message).await,
+            Transport::Tcp { writer } => {
+                let data = message.to_bytes()?;
+                tcp::write_frame(&mut *writer.lock().await, &Frame::Message(data)).await
+                    .context("Failed to send message")?;
+                Ok(())
             }
         }
-        
-        // 清理任务
-        recv_task.abort();
-        send_task.abort();
-        
-        Ok(())
     }
 
-    async fn send_message(connection: &Connection, message: Message) -> Result<()> {
+    /// 在 QUIC 连接上用一条单向流发送一条消息，服务器连接和直连共用
+    pub(crate) async fn send_on(connection: &Connection, message: Message) -> Result<()> {
         let mut send = connection.open_uni().await
             .context("Failed to open stream")?;
-        
+
         let data = message.to_bytes()?;
         send.write_all(&data).await
             .context("Failed to send message")?;
-        
+
         send.finish().await
             .context("Failed to finish stream")?;
-        
+
         Ok(())
     }
 
-    async fn receive_message(recv: &mut quinn::RecvStream) -> Result<Message> {
-        let data = recv.read_to_end(8192).await
+    pub(crate) async fn receive_message(recv: &mut quinn::RecvStream, limit: usize) -> Result<Message> {
+        let data = recv.read_to_end(limit).await
             .context("Failed to read message")?;
-        
+
         Message::from_bytes(&data)
     }
-}
\ No newline at end of file
+}
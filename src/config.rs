@@ -0,0 +1,641 @@
+use crate::message::is_valid_id;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+};
+
+/// 默认配置文件，存在时自动加载
+pub const DEFAULT_CONFIG_FILE: &str = "t3xt.toml";
+/// 环境变量前缀，例如 T3XT_SERVER_PORT 对应 [server] 下的 port
+const ENV_PREFIX: &str = "T3XT_";
+/// 选择配置文件中 `[profiles.<名称>]` 的环境变量，命令行的 --profile 优先
+pub const PROFILE_ENV: &str = "T3XT_PROFILE";
+
+/// 完整配置。按默认值、配置文件、配置方案、环境变量、命令行参数的顺序逐层覆盖
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub server: ServerSettings,
+    pub client: ClientSettings,
+    pub tls: TlsSettings,
+    pub transport: TransportSettings,
+    pub policy: PolicySettings,
+    pub metrics: MetricsSettings,
+    pub log: LogSettings,
+    pub telemetry: TelemetrySettings,
+    pub webhooks: WebhookSettings,
+    pub api: ApiSettings,
+    pub gateway: GatewaySettings,
+    pub webtransport: WebTransportSettings,
+    pub irc: IrcSettings,
+    pub discovery: DiscoverySettings,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerSettings {
+    /// 服务器ID，联邦中必须唯一
+    pub id: String,
+    /// 监听地址
+    pub bind: IpAddr,
+    pub port: u16,
+    /// 可以编辑或删除任何人消息的客户端ID
+    pub moderators: Vec<String>,
+    /// 主动连接的联邦对端服务器
+    pub peers: Vec<SocketAddr>,
+    /// 信任的对端服务器证书
+    pub peer_certs: Vec<PathBuf>,
+    /// 保存的历史消息条数
+    pub history_capacity: usize,
+    /// 客户端进入房间时回放的历史消息条数
+    pub history_replay: usize,
+    /// 在同一端口同时监听 TCP+TLS，供 UDP 被阻断的客户端使用
+    pub tcp: bool,
+    /// 为请求直连的客户端交换观测到的地址，之后的私信不再经过服务器
+    pub rendezvous: bool,
+}
+
+impl Default for ServerSettings {
+    fn default() -> Self {
+        Self {
+            id: "Server".to_string(),
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: 10005,
+            moderators: Vec::new(),
+            peers: Vec::new(),
+            peer_certs: Vec::new(),
+            history_capacity: 1000,
+            history_replay: 50,
+            tcp: true,
+            rendezvous: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClientSettings {
+    pub id: String,
+    /// 目标服务器地址，设为空时 `t3xt run` 在局域网中发现服务器
+    pub target: String,
+    pub port: u16,
+    /// 登录后加入的房间
+    pub room: String,
+    /// 本地绑定地址，多网卡时可指定出口IP
+    pub bind: IpAddr,
+    /// 登录时声明为机器人，在成员列表和消息中标记
+    pub bot: bool,
+    pub transport: ClientTransport,
+    /// auto 模式下 QUIC 先行的毫秒数，之后同时尝试 TCP
+    pub fallback_delay_ms: u64,
+    /// 服务器证书 DER 的 SHA-256（十六进制）。设置后按指纹固定证书，不再读取 tls.cert
+    pub fingerprint: String,
+    /// 允许与其他客户端直连（NAT 打洞），只在 QUIC 连接上可用
+    pub p2p: bool,
+    /// 等待会合和打洞的毫秒数，超时后私信继续经服务器转发
+    pub punch_timeout_ms: u64,
+    /// 重连时有缓存的会话票据，登录作为 0-RTT 早期数据随握手发出
+    pub zero_rtt: bool,
+}
+
+/// 客户端连接服务器的方式
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ClientTransport {
+    /// 先尝试 QUIC，短暂等待后同时尝试 TCP，先连上的胜出
+    Auto,
+    Quic,
+    Tcp,
+}
+
+impl Default for ClientSettings {
+    fn default() -> Self {
+        Self {
+            id: "Client".to_string(),
+            target: "127.0.0.1".to_string(),
+            port: 10005,
+            room: crate::message::DEFAULT_ROOM.to_string(),
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            bot: false,
+            transport: ClientTransport::Auto,
+            fallback_delay_ms: 300,
+            fingerprint: String::new(),
+            p2p: true,
+            punch_timeout_ms: 5000,
+            zero_rtt: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsSettings {
+    /// 服务器证书，不存在时自动生成自签名证书；客户端用它验证服务器
+    pub cert: PathBuf,
+    pub key: PathBuf,
+    /// 校验服务器证书时使用的名称
+    pub server_name: String,
+}
+
+impl Default for TlsSettings {
+    fn default() -> Self {
+        Self {
+            cert: PathBuf::from("certs/server.crt"),
+            key: PathBuf::from("certs/server.key"),
+            server_name: "localhost".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TransportSettings {
+    /// 超过该秒数没有收到任何数据就断开连接
+    pub idle_timeout_secs: u64,
+    /// 保活包的发送间隔（秒），必须小于空闲超时
+    pub keep_alive_secs: u64,
+    /// 单条消息的读取上限（字节）
+    pub max_message_size: usize,
+    /// 数据报收发缓冲区大小（字节）
+    pub datagram_buffer_size: usize,
+    /// 对方可同时打开的单向流数，每条消息占用一条
+    pub max_concurrent_streams: u32,
+    /// 单条流的接收窗口（字节）
+    pub stream_receive_window: u64,
+    /// 整个连接的接收窗口（字节）
+    pub receive_window: u64,
+    pub congestion_controller: CongestionController,
+    /// 测得 RTT 之前使用的估计值（毫秒）
+    pub initial_rtt_ms: u64,
+    /// 探测路径 MTU。关闭时只发送 1200 字节以内的包
+    pub mtu_discovery: bool,
+}
+
+/// QUIC 拥塞控制算法
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CongestionController {
+    NewReno,
+    Cubic,
+    /// 对丢包不敏感，适合有随机丢包的无线网络
+    Bbr,
+}
+
+impl Default for TransportSettings {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: 30,
+            keep_alive_secs: 5,
+            max_message_size: 8192,
+            datagram_buffer_size: 64 * 1024,
+            max_concurrent_streams: 100,
+            stream_receive_window: 1024 * 1024,
+            receive_window: 8 * 1024 * 1024,
+            congestion_controller: CongestionController::Cubic,
+            initial_rtt_ms: 333,
+            mtu_discovery: true,
+        }
+    }
+}
+
+/// 访问控制和限流，服务器运行中重新加载即时生效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PolicySettings {
+    /// 禁止登录的客户端ID
+    pub banned_ids: Vec<String>,
+    /// 禁止连接的IP地址
+    pub banned_ips: Vec<IpAddr>,
+    /// 每个客户端每秒可发送的消息数，0 表示不限制
+    pub rate_limit_per_sec: u32,
+    /// 限流时允许的突发消息数
+    pub rate_limit_burst: u32,
+}
+
+impl Default for PolicySettings {
+    fn default() -> Self {
+        Self {
+            banned_ids: Vec::new(),
+            banned_ips: Vec::new(),
+            rate_limit_per_sec: 0,
+            rate_limit_burst: 10,
+        }
+    }
+}
+
+/// Prometheus 指标的 HTTP 监听
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsSettings {
+    pub enabled: bool,
+    /// 在该地址提供 /metrics
+    pub listen: SocketAddr,
+}
+
+impl Default for MetricsSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9105),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+/// 日志输出。面向用户的内容始终打印到终端，不受这里影响
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogSettings {
+    /// 日志级别，支持 tracing 的过滤语法，如 "info,t3xt=debug"
+    pub level: String,
+    pub format: LogFormat,
+    /// 日志目录，按天滚动；为空时输出到标准错误
+    pub directory: PathBuf,
+    /// 保留的日志文件个数（天），0 表示不清理
+    pub retention_days: usize,
+}
+
+impl Default for LogSettings {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            format: LogFormat::Text,
+            directory: PathBuf::from("logs"),
+            retention_days: 7,
+        }
+    }
+}
+
+/// OpenTelemetry 链路追踪导出
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetrySettings {
+    pub enabled: bool,
+    /// OTLP/HTTP 的 span 接收地址
+    pub endpoint: String,
+    pub service_name: String,
+}
+
+impl Default for TelemetrySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            endpoint: "http://127.0.0.1:4318/v1/traces".to_string(),
+            service_name: "t3xt".to_string(),
+        }
+    }
+}
+
+/// 供脚本发消息和查询的 HTTP API，使用 Bearer 令牌认证
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiSettings {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+    /// 允许访问的令牌，重新加载即时生效
+    pub tokens: Vec<String>,
+    /// 请求未指定发送者时使用的机器人ID
+    pub sender_id: String,
+    /// 请求可以通过 `sender` 指定的其他机器人ID，不在列表中的请求被拒绝。
+    /// API 开启时客户端不能用 `sender_id` 和这些ID登录
+    pub senders: Vec<String>,
+}
+
+impl Default for ApiSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9106),
+            tokens: Vec::new(),
+            sender_id: "api".to_string(),
+            senders: Vec::new(),
+        }
+    }
+}
+
+/// 供浏览器连接的 WebSocket 网关，附带演示页面
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GatewaySettings {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+}
+
+impl Default for GatewaySettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 9107),
+        }
+    }
+}
+
+/// 供浏览器连接的 HTTP/3 WebTransport 监听。主端点的证书可以按哈希固定或由 CA 签发时使用同一张证书，
+/// 开发用的自签名证书不满足浏览器的要求，改用只在内存中定期更换的短期证书
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebTransportSettings {
+    pub enabled: bool,
+    /// UDP 监听地址，不能与 server.port 相同
+    pub listen: SocketAddr,
+}
+
+impl Default for WebTransportSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 4433),
+        }
+    }
+}
+
+/// 供 IRC 客户端连接的协议桥，频道对应 t3xt 房间
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IrcSettings {
+    pub enabled: bool,
+    pub listen: SocketAddr,
+}
+
+impl Default for IrcSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            listen: SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 6667),
+        }
+    }
+}
+
+/// 局域网服务发现：服务器通过 mDNS/DNS-SD 宣告 `_t3xt._udp`，客户端据此选择服务器
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DiscoverySettings {
+    /// 服务器在局域网中宣告自己的ID、端口和证书指纹，设为 false 关闭
+    pub announce: bool,
+    /// 客户端等待服务器响应的毫秒数
+    pub timeout_ms: u64,
+}
+
+impl Default for DiscoverySettings {
+    fn default() -> Self {
+        Self {
+            announce: true,
+            timeout_ms: 1500,
+        }
+    }
+}
+
+/// 服务器事件的出站 Webhook，重新加载即时生效
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookSettings {
+    pub targets: Vec<WebhookTarget>,
+    /// 每个事件最多尝试投递的次数
+    pub max_attempts: u32,
+    /// 第一次重试前等待的毫秒数，之后每次加倍
+    pub retry_backoff_ms: u64,
+    /// 单次请求的超时
+    pub timeout_secs: u64,
+    /// 多次重试仍失败的事件追加写入该文件，每行一个 JSON 对象
+    pub dead_letter: PathBuf,
+}
+
+impl Default for WebhookSettings {
+    fn default() -> Self {
+        Self {
+            targets: Vec::new(),
+            max_attempts: 5,
+            retry_backoff_ms: 500,
+            timeout_secs: 10,
+            dead_letter: PathBuf::from("logs/webhook-dead-letter.jsonl"),
+        }
+    }
+}
+
+/// 一个 Webhook 地址及其订阅的事件。房间为空时匹配所有房间；
+/// 提及和关键字都为空时推送房间内的所有消息，否则只推送命中其一的消息
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebhookTarget {
+    pub url: String,
+    /// HMAC-SHA256 签名密钥，为空时不签名
+    pub secret: String,
+    pub rooms: Vec<String>,
+    /// 提及这些客户端ID的消息
+    pub mentions: Vec<String>,
+    /// 包含这些关键字的消息，不区分大小写
+    pub keywords: Vec<String>,
+    /// 是否推送加入和离开事件
+    pub presence: bool,
+}
+
+impl Config {
+    /// 叠加默认值、配置文件和环境变量。`path` 为 None 时仅在默认文件存在时加载。
+    /// 命令行参数由调用方在之后覆盖。`T3XT_PROFILE` 指定的配置方案同样生效，见 [`Config::load_with_profile`]
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        Self::load_with_profile(path, None)
+    }
+
+    /// 与 [`Config::load`] 相同，另外把选中的 `[profiles.<名称>]` 覆盖在配置文件之上。
+    /// `profile` 为 None 时读取 `T3XT_PROFILE`，为空字符串时不使用配置方案
+    pub fn load_with_profile(path: Option<&Path>, profile: Option<&str>) -> Result<Self> {
+        let mut value = toml::Value::try_from(Config::default())?;
+        // 显式传入空名称表示不使用配置方案
+        let profile = profile
+            .map(str::to_string)
+            .or_else(|| std::env::var(PROFILE_ENV).ok())
+            .filter(|profile| !profile.is_empty());
+
+        let mut profiles = toml::Table::new();
+        if let Some(path) = Self::resolve_path(path) {
+            let path = path.as_path();
+            let text = fs::read_to_string(path)
+                .with_context(|| format!("Failed to read config file {}", path.display()))?;
+            let mut file: toml::Table = toml::from_str(&text)
+                .with_context(|| format!("Failed to parse config file {}", path.display()))?;
+            match file.remove("profiles") {
+                Some(toml::Value::Table(table)) => profiles = table,
+                Some(_) => bail!("profiles must be a table of [profiles.<name>] sections"),
+                None => {}
+            }
+            merge(&mut value, toml::Value::Table(file));
+        }
+
+        // 选中的配置方案覆盖在配置文件之上，写法与配置文件相同，例如 [profiles.lan.transport]
+        if let Some(name) = &profile {
+            match profiles.remove(name) {
+                Some(overlay @ toml::Value::Table(_)) => merge(&mut value, overlay),
+                Some(_) => bail!("profiles.{} must be a table", name),
+                None => bail!("Profile {:?} not found in the config file", name),
+            }
+        }
+
+        apply_env(&mut value, std::env::vars().filter(|(name, _)| name != PROFILE_ENV))?;
+
+        let config: Config = value.try_into().context("Invalid configuration")?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// 实际加载的配置文件：显式指定的路径，或存在时的默认文件
+    pub fn resolve_path(path: Option<&Path>) -> Option<PathBuf> {
+        match path {
+            Some(path) => Some(path.to_path_buf()),
+            None => Some(PathBuf::from(DEFAULT_CONFIG_FILE)).filter(|path| path.exists()),
+        }
+    }
+
+    /// 检查各项取值是否合理
+    pub fn validate(&self) -> Result<()> {
+        if !is_valid_id(&self.server.id) {
+            bail!("server.id must not be empty or contain spaces, control characters, '!', '@' or ':'");
+        }
+        if !is_valid_id(&self.client.id) {
+            bail!("client.id must not be empty or contain spaces, control characters, '!', '@' or ':'");
+        }
+        if self.transport.idle_timeout_secs == 0 {
+            bail!("transport.idle_timeout_secs must be greater than 0");
+        }
+        if self.transport.keep_alive_secs == 0 {
+            bail!("transport.keep_alive_secs must be greater than 0");
+        }
+        if self.transport.keep_alive_secs >= self.transport.idle_timeout_secs {
+            bail!("transport.keep_alive_secs must be less than transport.idle_timeout_secs");
+        }
+        if self.transport.max_message_size < 1024 {
+            bail!("transport.max_message_size must be at least 1024 bytes");
+        }
+        if self.transport.max_concurrent_streams == 0 {
+            bail!("transport.max_concurrent_streams must be greater than 0");
+        }
+        if self.transport.stream_receive_window == 0 || self.transport.stream_receive_window > self.transport.receive_window {
+            bail!("transport.stream_receive_window must be greater than 0 and not exceed transport.receive_window");
+        }
+        if self.transport.receive_window >= 1 << 62 {
+            bail!("transport.receive_window must be less than 2^62 bytes");
+        }
+        if self.transport.initial_rtt_ms == 0 {
+            bail!("transport.initial_rtt_ms must be greater than 0");
+        }
+        if self.server.history_capacity == 0 {
+            bail!("server.history_capacity must be greater than 0");
+        }
+        if self.server.history_replay > self.server.history_capacity {
+            bail!("server.history_replay must not exceed server.history_capacity");
+        }
+        if self.policy.rate_limit_per_sec > 0 && self.policy.rate_limit_burst == 0 {
+            bail!("policy.rate_limit_burst must be greater than 0 when rate limiting is enabled");
+        }
+        if let Err(e) = tracing_subscriber::EnvFilter::try_new(&self.log.level) {
+            bail!("log.level is invalid: {}", e);
+        }
+        if self.api.enabled && self.api.tokens.iter().all(|token| token.is_empty()) {
+            bail!("api.tokens must not be empty when the HTTP API is enabled");
+        }
+        if !is_valid_id(&self.api.sender_id) {
+            bail!("api.sender_id must not be empty or contain spaces, control characters, '!', '@' or ':'");
+        }
+        if !self.api.senders.iter().all(|id| is_valid_id(id)) {
+            bail!("api.senders must not contain empty IDs or IDs with spaces, control characters, '!', '@' or ':'");
+        }
+        let fingerprint = self.client.fingerprint.replace(':', "");
+        if !fingerprint.is_empty() && (fingerprint.len() != 64 || !fingerprint.chars().all(|c| c.is_ascii_hexdigit())) {
+            bail!("client.fingerprint must be a hex SHA-256 digest");
+        }
+        if self.webhooks.max_attempts == 0 {
+            bail!("webhooks.max_attempts must be greater than 0");
+        }
+        if self.client.punch_timeout_ms == 0 {
+            bail!("client.punch_timeout_ms must be greater than 0");
+        }
+        if self.discovery.timeout_ms == 0 {
+            bail!("discovery.timeout_ms must be greater than 0");
+        }
+        if self.webhooks.timeout_secs == 0 {
+            bail!("webhooks.timeout_secs must be greater than 0");
+        }
+        for target in &self.webhooks.targets {
+            if !(target.url.starts_with("http://") || target.url.starts_with("https://")) {
+                bail!("webhooks.targets: url must be an http(s) URL, got {:?}", target.url);
+            }
+        }
+        for path in &self.server.peer_certs {
+            if !path.exists() {
+                bail!("server.peer_certs: {} not found", path.display());
+            }
+        }
+        Ok(())
+    }
+
+    /// 以 TOML 格式输出生效的配置
+    pub fn to_toml(&self) -> Result<String> {
+        Ok(toml::to_string_pretty(self)?)
+    }
+}
+
+/// 把 overlay 中的键逐层合并到 base
+fn merge(base: &mut toml::Value, overlay: toml::Value) {
+    match (base, overlay) {
+        (toml::Value::Table(base), toml::Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// 应用 `T3XT_<节>_<键>` 形式的环境变量，按默认值的类型解析，列表用逗号分隔
+fn apply_env(value: &mut toml::Value, vars: impl Iterator<Item = (String, String)>) -> Result<()> {
+    let toml::Value::Table(sections) = value else {
+        return Ok(());
+    };
+    for (name, raw) in vars {
+        let Some(rest) = name.strip_prefix(ENV_PREFIX) else {
+            continue;
+        };
+        let rest = rest.to_lowercase();
+        let target = sections.iter_mut().find_map(|(section, table)| {
+            let key = rest.strip_prefix(section.as_str())?.strip_prefix('_')?;
+            table.as_table_mut()?.get_mut(key)
+        });
+        // 其他程序也可能使用同样的前缀，未知的变量只提示不报错。加载配置时日志还没有初始化，直接提示用户
+        let Some(target) = target else {
+            crate::console::error(format_args!("忽略未知的配置环境变量 {}", name));
+            continue;
+        };
+        *target = parse_env_value(target, &raw)
+            .with_context(|| format!("Invalid value for {}: {:?}", name, raw))?;
+    }
+    Ok(())
+}
+
+fn parse_env_value(current: &toml::Value, raw: &str) -> Result<toml::Value> {
+    Ok(match current {
+        toml::Value::Integer(_) => toml::Value::Integer(raw.trim().parse()?),
+        toml::Value::Boolean(_) => toml::Value::Boolean(raw.trim().parse()?),
+        toml::Value::Float(_) => toml::Value::Float(raw.trim().parse()?),
+        toml::Value::Array(_) => toml::Value::Array(
+            raw.split(',')
+                .map(str::trim)
+                .filter(|item| !item.is_empty())
+                .map(|item| toml::Value::String(item.to_string()))
+                .collect(),
+        ),
+        _ => toml::Value::String(raw.to_string()),
+    })
+}
@@ -0,0 +1,56 @@
+use crate::message::Message;
+use std::{
+    fmt::Display,
+    io::{self, Write},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+/// 作为库嵌入时默认不向终端输出，由命令行程序开启
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// 开启或关闭终端输出
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+fn enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// 打印一行面向用户的内容。运行日志交给 tracing，不经过这里
+pub fn line(text: impl Display) {
+    if !enabled() {
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    let _ = writeln!(stdout, "{}", text);
+}
+
+/// 按聊天格式显示一条消息，`local_id` 用于高亮提及自己的内容
+pub fn message(message: &Message, local_id: Option<&str>) {
+    line(message.format_display_for(local_id));
+}
+
+/// 状态提示，如链路变化、配置重新加载
+pub fn notice(text: impl Display) {
+    line(format_args!("* {}", text));
+}
+
+/// 面向用户的错误提示
+pub fn error(text: impl Display) {
+    if !enabled() {
+        return;
+    }
+    let mut stderr = io::stderr().lock();
+    let _ = writeln!(stderr, "{}", text);
+}
+
+/// 响铃提醒
+pub fn bell() {
+    if !enabled() {
+        return;
+    }
+    let mut stdout = io::stdout().lock();
+    let _ = write!(stdout, "\x07");
+    let _ = stdout.flush();
+}
@@ -1,48 +1,58 @@
 use anyhow::{Context, Result};
-use quinn::{ClientConfig, TransportConfig};
-use rustls::{Certificate, ClientConfig as RustlsClientConfig, PrivateKey, ServerConfig as RustlsServerConfig};
-use std::{fs, path::Path, sync::Arc, time::Duration};
+use quinn::{congestion, ClientConfig, MtuDiscoveryConfig, ServerConfig, TransportConfig, VarInt};
+use rustls::{
+    client::{ClientSessionMemoryCache, Resumption, ServerCertVerified, ServerCertVerifier},
+    server::{AllowAnyAnonymousOrAuthenticatedClient, ClientCertVerified, ClientCertVerifier, ServerSessionMemoryCache},
+    Certificate, CertificateError, ClientConfig as RustlsClientConfig, DistinguishedName, PrivateKey, RootCertStore, ServerConfig as RustlsServerConfig, ServerName,
+};
+use crate::{config::{CongestionController, TlsSettings, TransportSettings}, console};
+use std::{collections::HashMap, fs, path::Path, sync::{Arc, Mutex}, time::Duration};
 
+#[derive(Clone)]
 pub struct CertConfig {
     pub cert: Certificate,
     pub key: PrivateKey,
+    #[allow(dead_code)]
     pub cert_pem: String,
 }
 
 impl CertConfig {
-    pub fn generate_self_signed() -> Result<Self> {
+    pub fn generate_self_signed(tls: &TlsSettings) -> Result<Self> {
         use rcgen::{Certificate as RcgenCert, CertificateParams, DistinguishedName};
         
-        let mut params = CertificateParams::new(vec!["localhost".to_string()]);
+        let mut params = CertificateParams::new(vec![tls.server_name.clone()]);
         params.distinguished_name = DistinguishedName::new();
         params.distinguished_name.push(rcgen::DnType::CommonName, "T3XT Server");
         
         let cert = RcgenCert::from_params(params)
             .context("Failed to generate certificate")?;
         
-        let cert_der = cert.serialize_der()
-            .context("Failed to serialize certificate")?;
         let key_der = cert.serialize_private_key_der();
         
-        // 生成PEM格式
+        // 生成PEM格式。每次序列化都会重新签名，DER 从同一份 PEM 取出，保证与文件中的证书（及其指纹）一致
         let cert_pem = cert.serialize_pem()
             .context("Failed to serialize certificate to PEM")?;
+        let cert_der = first_cert(&cert_pem)?;
         
-        // 创建certs目录
-        fs::create_dir_all("certs").context("Failed to create certs directory")?;
+        // 创建证书目录
+        for path in [&tls.cert, &tls.key] {
+            if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+                fs::create_dir_all(dir).context("Failed to create certs directory")?;
+            }
+        }
         
         // 保存证书文件
-        fs::write("certs/server.crt", &cert_pem)
+        fs::write(&tls.cert, &cert_pem)
             .context("Failed to write certificate file")?;
         
         // 保存私钥文件
         let key_pem = cert.serialize_private_key_pem();
-        fs::write("certs/server.key", &key_pem)
+        fs::write(&tls.key, &key_pem)
             .context("Failed to write private key file")?;
         
-        println!("🔐 证书已保存到:");
-        println!("   📄 证书文件: certs/server.crt");
-        println!("   🔑 私钥文件: certs/server.key");
+        console::line("🔐 证书已保存到:");
+        console::line(format_args!("   📄 证书文件: {}", tls.cert.display()));
+        console::line(format_args!("   🔑 私钥文件: {}", tls.key.display()));
         
         Ok(Self {
             cert: Certificate(cert_der),
@@ -51,10 +61,47 @@ impl CertConfig {
         })
     }
     
-    pub fn load_from_files() -> Result<Self> {
-        let cert_pem = fs::read_to_string("certs/server.crt")
+    /// 只在内存中使用的临时自签名证书，如客户端直连时的身份，指纹在会合时交换
+    pub fn generate_ephemeral(server_name: &str) -> Result<Self> {
+        let cert = rcgen::generate_simple_self_signed(vec![server_name.to_string()])
+            .context("Failed to generate certificate")?;
+        let cert_pem = cert.serialize_pem()
+            .context("Failed to serialize certificate to PEM")?;
+
+        Ok(Self {
+            cert: Certificate(first_cert(&cert_pem)?),
+            key: PrivateKey(cert.serialize_private_key_der()),
+            cert_pem,
+        })
+    }
+    
+    /// 只在内存中使用的短期证书（ECDSA P-256），有效期从一小时前开始，
+    /// 供浏览器按 `serverCertificateHashes` 固定
+    pub fn generate_short_lived(server_name: &str, lifetime: Duration) -> Result<Self> {
+        use chrono::{Datelike, Timelike, Utc};
+
+        let now = Utc::now();
+        let midnight = rcgen::date_time_ymd(now.year(), now.month() as u8, now.day() as u8);
+        let mut params = rcgen::CertificateParams::new(vec![server_name.to_string()]);
+        params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+        params.not_before = midnight + Duration::from_secs(now.num_seconds_from_midnight().into()) - Duration::from_secs(3600);
+        params.not_after = params.not_before + lifetime;
+        let cert = rcgen::Certificate::from_params(params)
+            .context("Failed to generate certificate")?;
+        let cert_pem = cert.serialize_pem()
+            .context("Failed to serialize certificate to PEM")?;
+
+        Ok(Self {
+            cert: Certificate(first_cert(&cert_pem)?),
+            key: PrivateKey(cert.serialize_private_key_der()),
+            cert_pem,
+        })
+    }
+
+    pub fn load_from_files(tls: &TlsSettings) -> Result<Self> {
+        let cert_pem = fs::read_to_string(&tls.cert)
             .context("Failed to read certificate file")?;
-        let key_pem = fs::read_to_string("certs/server.key")
+        let key_pem = fs::read_to_string(&tls.key)
             .context("Failed to read private key file")?;
         
         // 解析证书
@@ -78,57 +125,278 @@ impl CertConfig {
         })
     }
     
-    pub fn get_or_create() -> Result<Self> {
-        if Path::new("certs/server.crt").exists() && Path::new("certs/server.key").exists() {
-            println!("📄 使用现有证书文件");
-            Self::load_from_files()
+    pub fn get_or_create(tls: &TlsSettings) -> Result<Self> {
+        if tls.cert.exists() && tls.key.exists() {
+            console::line("📄 使用现有证书文件");
+            Self::load_from_files(tls)
         } else {
-            println!("🔧 生成新的自签名证书");
-            Self::generate_self_signed()
+            console::line("🔧 生成新的自签名证书");
+            Self::generate_self_signed(tls)
         }
     }
 }
 
-pub fn create_server_config(cert_config: CertConfig) -> Result<RustlsServerConfig> {
-    let config = RustlsServerConfig::builder()
+fn first_cert(cert_pem: &str) -> Result<Vec<u8>> {
+    rustls_pemfile::certs(&mut cert_pem.as_bytes())
+        .context("Failed to parse certificate")?
+        .into_iter()
+        .next()
+        .context("No certificate found")
+}
+
+/// 服务器证书。普通客户端匿名连接；出示了受信任证书的连接视为联邦对端服务器。
+/// `sessions` 由服务器实例持有，重新加载证书时传入同一个缓存，客户端仍能恢复会话
+pub fn create_server_config(
+    cert_config: CertConfig,
+    federation_roots: RootCertStore,
+    sessions: Arc<ServerSessionMemoryCache>,
+) -> Result<RustlsServerConfig> {
+    let verifier = AllowAnyAnonymousOrAuthenticatedClient::new(federation_roots).boxed();
+    let mut config = RustlsServerConfig::builder()
         .with_safe_defaults()
-        .with_no_client_auth()
+        .with_client_cert_verifier(verifier)
         .with_single_cert(vec![cert_config.cert], cert_config.key)
         .context("Failed to create server config")?;
+    config.session_storage = sessions;
     
     Ok(config)
 }
 
-pub fn create_client_config_with_cert(cert_path: &Path) -> Result<RustlsClientConfig> {
+/// 服务器的会话缓存，会话票据只是缓存中的索引，服务器重启后客户端重新完整握手。
+/// 不用无状态票据：rustls 只对有状态的会话接受 0-RTT，带早期数据恢复时从缓存中取出会话，票据只能用一次
+pub fn server_sessions() -> Arc<ServerSessionMemoryCache> {
+    ServerSessionMemoryCache::new(4096)
+}
+
+/// 客户端的会话票据缓存，用同一个缓存重连时恢复会话并可发送 0-RTT 数据。克隆后共用缓存。
+/// 恢复会话时不再验证证书，所以按信任的证书指纹分开缓存，换了证书或指纹就重新完整握手。
+/// rustls 0.21 不提供客户端票据的序列化，缓存只在内存中，进程重启后第一次连接仍是完整握手
+#[derive(Clone, Default)]
+pub struct SessionCache {
+    sessions: Arc<Mutex<HashMap<String, Arc<ClientSessionMemoryCache>>>>,
+}
+
+impl SessionCache {
+    fn enable(&self, config: &mut RustlsClientConfig, trusted: String) {
+        let sessions = Arc::clone(
+            self.sessions.lock().unwrap()
+                .entry(trusted)
+                .or_insert_with(|| Arc::new(ClientSessionMemoryCache::new(32))),
+        );
+        config.resumption = Resumption::store(sessions);
+        config.enable_early_data = true;
+    }
+}
+
+/// 从 PEM 文件读取第一张证书
+pub fn load_cert(cert_path: &Path) -> Result<Certificate> {
     let cert_pem = fs::read_to_string(cert_path)
-        .context("Failed to read certificate file")?;
+        .with_context(|| format!("Failed to read certificate file {}", cert_path.display()))?;
     
     let cert_der = rustls_pemfile::certs(&mut cert_pem.as_bytes())
         .context("Failed to parse certificate")?
         .into_iter()
         .next()
         .context("No certificate found")?;
-    
-    let mut root_store = rustls::RootCertStore::empty();
-    root_store.add(&Certificate(cert_der))
+
+    Ok(Certificate(cert_der))
+}
+
+/// 联邦信任的证书：本服务器自己的证书加上配置的对端证书
+pub fn federation_root_store(cert_config: &CertConfig, peer_cert_paths: &[std::path::PathBuf]) -> Result<RootCertStore> {
+    let mut root_store = RootCertStore::empty();
+    root_store.add(&cert_config.cert)
+        .context("Failed to add own certificate to federation root store")?;
+    for path in peer_cert_paths {
+        root_store.add(&load_cert(path)?)
+            .context("Failed to add peer certificate to federation root store")?;
+    }
+    Ok(root_store)
+}
+
+/// 服务器作为客户端连接对端服务器时使用，出示自己的证书完成双向认证
+pub fn create_federation_client_config(cert_config: &CertConfig, roots: RootCertStore) -> Result<RustlsClientConfig> {
+    let config = RustlsClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_client_auth_cert(vec![cert_config.cert.clone()], cert_config.key.clone())
+        .context("Failed to create federation client config")?;
+
+    Ok(config)
+}
+
+pub fn create_client_config_with_cert(cert_path: &Path, sessions: &SessionCache) -> Result<RustlsClientConfig> {
+    let cert = load_cert(cert_path)?;
+    let mut root_store = RootCertStore::empty();
+    root_store.add(&cert)
         .context("Failed to add certificate to root store")?;
     
-    let config = RustlsClientConfig::builder()
+    let mut config = RustlsClientConfig::builder()
         .with_safe_defaults()
         .with_root_certificates(root_store)
         .with_no_client_auth();
+    sessions.enable(&mut config, certificate_hash(&cert));
     
     Ok(config)
 }
 
-pub fn create_quinn_client_config(rustls_config: RustlsClientConfig) -> ClientConfig {
+/// 按 SHA-256 指纹固定服务器证书，用于局域网中发现的服务器，不需要事先拿到证书文件
+pub fn create_client_config_with_fingerprint(fingerprint: &str, sessions: &SessionCache) -> Result<RustlsClientConfig> {
+    let fingerprint = normalize_fingerprint(fingerprint);
+    let verifier = PinnedCertificate {
+        fingerprint: fingerprint.clone(),
+    };
+    let mut config = RustlsClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    sessions.enable(&mut config, fingerprint);
+
+    Ok(config)
+}
+
+/// 指纹统一为小写十六进制，也接受 `ab:cd:...` 的写法
+pub fn normalize_fingerprint(fingerprint: &str) -> String {
+    fingerprint.replace(':', "").to_ascii_lowercase()
+}
+
+/// 只接受指纹匹配的证书。自签名证书没有可信的签发者，指纹就是身份
+struct PinnedCertificate {
+    fingerprint: String,
+}
+
+impl ServerCertVerifier for PinnedCertificate {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: std::time::SystemTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        if certificate_hash(end_entity) == self.fingerprint {
+            Ok(ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(CertificateError::ApplicationVerificationFailure))
+        }
+    }
+}
+
+/// 客户端直连中接受连接的一方。要求对方出示证书，握手后按会合时交换的指纹确认身份
+pub fn create_peer_server_config(identity: &CertConfig) -> Result<RustlsServerConfig> {
+    let config = RustlsServerConfig::builder()
+        .with_safe_defaults()
+        .with_client_cert_verifier(Arc::new(AnyClientCertificate))
+        .with_single_cert(vec![identity.cert.clone()], identity.key.clone())
+        .context("Failed to create peer server config")?;
+
+    Ok(config)
+}
+
+/// 客户端直连中发起连接的一方：按指纹固定对方证书，并出示自己的临时证书
+pub fn create_peer_client_config(identity: &CertConfig, fingerprint: &str) -> Result<RustlsClientConfig> {
+    let verifier = PinnedCertificate {
+        fingerprint: normalize_fingerprint(fingerprint),
+    };
+    let config = RustlsClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_client_auth_cert(vec![identity.cert.clone()], identity.key.clone())
+        .context("Failed to create peer client config")?;
+
+    Ok(config)
+}
+
+/// 接受任何客户端证书，握手仍会验证对方持有私钥
+struct AnyClientCertificate;
+
+impl ClientCertVerifier for AnyClientCertificate {
+    fn client_auth_root_subjects(&self) -> &[DistinguishedName] {
+        &[]
+    }
+
+    fn verify_client_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _now: std::time::SystemTime,
+    ) -> Result<ClientCertVerified, rustls::Error> {
+        Ok(ClientCertVerified::assertion())
+    }
+}
+
+/// 客户端与服务器共用的传输参数，数据报用于输入提示等短暂事件。
+/// 消息、联邦和直连都只用单向流，不接受对方打开双向流
+pub fn create_transport_config(settings: &TransportSettings) -> Result<TransportConfig> {
     let mut transport = TransportConfig::default();
-    transport.max_idle_timeout(Some(Duration::from_secs(30).try_into().unwrap()));
-    transport.keep_alive_interval(Some(Duration::from_secs(5)));
-    
+    let idle_timeout = Duration::from_secs(settings.idle_timeout_secs)
+        .try_into()
+        .context("Idle timeout out of range")?;
+    transport.max_idle_timeout(Some(idle_timeout));
+    transport.keep_alive_interval(Some(Duration::from_secs(settings.keep_alive_secs)));
+    transport.datagram_receive_buffer_size(Some(settings.datagram_buffer_size));
+    transport.datagram_send_buffer_size(settings.datagram_buffer_size);
+    transport.max_concurrent_uni_streams(settings.max_concurrent_streams.into());
+    transport.max_concurrent_bidi_streams(0u32.into());
+    transport.stream_receive_window(VarInt::from_u64(settings.stream_receive_window).context("Stream receive window out of range")?);
+    transport.receive_window(VarInt::from_u64(settings.receive_window).context("Receive window out of range")?);
+    transport.initial_rtt(Duration::from_millis(settings.initial_rtt_ms));
+    transport.mtu_discovery_config(settings.mtu_discovery.then(MtuDiscoveryConfig::default));
+    match settings.congestion_controller {
+        CongestionController::NewReno => transport.congestion_controller_factory(Arc::new(congestion::NewRenoConfig::default())),
+        CongestionController::Cubic => transport.congestion_controller_factory(Arc::new(congestion::CubicConfig::default())),
+        CongestionController::Bbr => transport.congestion_controller_factory(Arc::new(congestion::BbrConfig::default())),
+    };
+    Ok(transport)
+}
+
+pub fn create_quinn_client_config(rustls_config: RustlsClientConfig, settings: &TransportSettings) -> Result<ClientConfig> {
     let mut config = ClientConfig::new(Arc::new(rustls_config));
-    config.transport_config(Arc::new(transport));
-    config
+    config.transport_config(Arc::new(create_transport_config(settings)?));
+    Ok(config)
+}
+
+/// QUIC 上接受 0-RTT 早期数据。QUIC 的 TLS 只允许早期数据上限为 0 或 0xffffffff，
+/// 实际能发送多少由传输层限制：0-RTT 数据同样受 `transport.receive_window`、
+/// `stream_receive_window` 和 `max_concurrent_streams` 约束，并且只能在会话票据有效期内用一次。
+/// TCP 上的 TLS 不接受早期数据
+pub fn create_quinn_server_config(mut rustls_config: RustlsServerConfig, settings: &TransportSettings) -> Result<ServerConfig> {
+    rustls_config.max_early_data_size = u32::MAX;
+    let mut config = ServerConfig::with_crypto(Arc::new(rustls_config));
+    config.transport_config(Arc::new(create_transport_config(settings)?));
+    Ok(config)
+}
+
+/// 证书 DER 的 SHA-256，十六进制。浏览器用它固定自签名证书（`serverCertificateHashes`）
+pub fn certificate_hash(cert: &Certificate) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, &cert.0);
+    digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// 浏览器按 `serverCertificateHashes` 固定的证书的最长有效期
+const MAX_PINNED_LIFETIME: i64 = 14 * 24 * 3600;
+
+/// 浏览器能否按哈希固定这张证书：ECDSA P-256 公钥，有效期不超过 14 天
+pub fn is_hash_pinnable(cert: &Certificate) -> Result<bool> {
+    use x509_parser::{oid_registry::{OID_EC_P256, OID_KEY_TYPE_EC_PUBLIC_KEY}, prelude::*};
+
+    let (_, x509) = X509Certificate::from_der(&cert.0).context("Failed to parse certificate")?;
+    let validity = x509.validity();
+    let lifetime = validity.not_after.timestamp() - validity.not_before.timestamp();
+    let algorithm = &x509.public_key().algorithm;
+    let curve = algorithm.parameters.as_ref().and_then(|parameters| parameters.as_oid().ok());
+    Ok(lifetime <= MAX_PINNED_LIFETIME
+        && algorithm.algorithm == OID_KEY_TYPE_EC_PUBLIC_KEY
+        && curve == Some(OID_EC_P256))
+}
+
+/// 签发者与主体相同，即没有 CA 签发的自签名证书
+pub fn is_self_signed(cert: &Certificate) -> Result<bool> {
+    use x509_parser::prelude::*;
+
+    let (_, x509) = X509Certificate::from_der(&cert.0).context("Failed to parse certificate")?;
+    Ok(x509.issuer().as_raw() == x509.subject().as_raw())
 }
 
 /* 
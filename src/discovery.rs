@@ -0,0 +1,418 @@
+//! 局域网服务发现。服务器通过 mDNS/DNS-SD 宣告 `_t3xt._udp.local` 服务，
+//! TXT 记录中带有服务器ID和证书指纹；客户端用 [`discover`] 查询，连接时按指纹固定证书。
+
+use crate::{crypto, server::ServerState};
+use anyhow::{Context, Result};
+use hickory_proto::{
+    op::{Message as DnsMessage, MessageType, OpCode, Query},
+    rr::{
+        rdata::{A, PTR, SRV, TXT},
+        Name, RData, Record, RecordType,
+    },
+};
+use socket2::{Domain, Protocol, Socket, Type};
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket as StdUdpSocket},
+    sync::Arc,
+    time::Duration,
+};
+use tokio::{net::UdpSocket, time::Instant};
+use tracing::{debug, info, warn};
+
+/// DNS-SD 服务类型
+pub const SERVICE_TYPE: &str = "_t3xt._udp.local.";
+
+const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+const MDNS_PORT: u16 = 5353;
+/// 记录的缓存时间（秒）
+const TTL: u32 = 120;
+/// 回复非 5353 端口的单播查询时，TTL 不超过 10 秒（RFC 6762 第 6.7 节）
+const LEGACY_UNICAST_TTL: u32 = 10;
+
+/// 局域网中发现的服务器
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiscoveredServer {
+    pub id: String,
+    pub addr: SocketAddr,
+    /// 证书 DER 的 SHA-256，十六进制，用作 `client.fingerprint`
+    pub fingerprint: String,
+}
+
+/// 在局域网中查询 t3xt 服务器，等待 `wait` 后返回收到的全部响应，按ID排序
+pub async fn discover(wait: Duration) -> Result<Vec<DiscoveredServer>> {
+    let socket = UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).await
+        .context("Failed to bind discovery socket")?;
+    socket.set_multicast_ttl_v4(255)?;
+    let mut query = DnsMessage::new(0, MessageType::Query, OpCode::Query);
+    query.add_query(Query::query(service_name(), RecordType::PTR));
+    let query = query.to_vec()?;
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+
+    let deadline = Instant::now() + wait;
+    // 多播可能丢包，中途再查询一次
+    let mut resend = Some(Instant::now() + wait / 3);
+    socket.send_to(&query, group).await.context("Failed to send discovery query")?;
+
+    let mut found: Vec<DiscoveredServer> = Vec::new();
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let until = resend.map_or(deadline, |resend| resend.min(deadline));
+        let received = tokio::select! {
+            received = socket.recv_from(&mut buf) => received,
+            _ = tokio::time::sleep_until(until) => {
+                if resend.take().is_some() && Instant::now() < deadline {
+                    socket.send_to(&query, group).await.context("Failed to send discovery query")?;
+                    continue;
+                }
+                break;
+            }
+        };
+        let (len, source) = received.context("Failed to receive discovery response")?;
+        let response = match DnsMessage::from_vec(&buf[..len]) {
+            Ok(response) if response.metadata.message_type == MessageType::Response => response,
+            Ok(_) => continue,
+            Err(e) => {
+                debug!("忽略无法解析的 mDNS 包 from {}: {}", source, e);
+                continue;
+            }
+        };
+        for server in parse_response(&response, source.ip()) {
+            if !found.iter().any(|known| known.id == server.id && known.addr == server.addr) {
+                found.push(server);
+            }
+        }
+    }
+    found.sort_by(|a, b| a.id.cmp(&b.id).then(a.addr.cmp(&b.addr)));
+    Ok(found)
+}
+
+/// 从响应的 PTR、SRV、TXT 和 A 记录中取出服务器。没有 A 记录时用响应的来源地址
+fn parse_response(response: &DnsMessage, source: IpAddr) -> Vec<DiscoveredServer> {
+    let records: Vec<&Record> = response.answers.iter().chain(&response.additionals).collect();
+    let service = service_name();
+    let addresses: HashMap<&Name, Ipv4Addr> = records
+        .iter()
+        .filter_map(|record| match &record.data {
+            RData::A(A(ip)) => Some((&record.name, ip.to_owned())),
+            _ => None,
+        })
+        .collect();
+
+    records
+        .iter()
+        .filter_map(|record| match &record.data {
+            RData::PTR(PTR(instance)) if record.name == service => Some(instance),
+            _ => None,
+        })
+        .filter_map(|instance| {
+            let srv = records.iter().find_map(|record| match &record.data {
+                RData::SRV(srv) if record.name == *instance => Some(srv),
+                _ => None,
+            })?;
+            let txt = records.iter().find_map(|record| match &record.data {
+                RData::TXT(txt) if record.name == *instance => Some(txt),
+                _ => None,
+            })?;
+            let entries = txt_entries(txt);
+            let ip = addresses.get(&srv.target).map_or(source, |ip| IpAddr::V4(*ip));
+            Some(DiscoveredServer {
+                id: entries.get("id")?.clone(),
+                addr: SocketAddr::new(ip, srv.port),
+                fingerprint: entries.get("fp")?.clone(),
+            })
+        })
+        .collect()
+}
+
+fn txt_entries(txt: &TXT) -> HashMap<String, String> {
+    txt.txt_data
+        .iter()
+        .filter_map(|entry| {
+            let entry = String::from_utf8_lossy(entry);
+            let (key, value) = entry.split_once('=')?;
+            Some((key.to_ascii_lowercase(), value.to_string()))
+        })
+        .collect()
+}
+
+fn service_name() -> Name {
+    Name::from_ascii(SERVICE_TYPE).expect("valid service name")
+}
+
+/// 服务器在局域网中宣告的内容
+pub(crate) struct Announcement {
+    pub(crate) server_id: String,
+    /// 监听地址，未指定具体IP时按查询方选择出口地址
+    pub(crate) bind: IpAddr,
+    pub(crate) port: u16,
+}
+
+/// 加入 mDNS 多播组，启动时宣告两次，之后回答对 `_t3xt._udp` 的查询。
+/// 只有绑定套接字失败时返回错误，单次宣告或回答失败记录日志后继续
+pub(crate) async fn announce(announcement: Announcement, state: Arc<ServerState>) -> Result<()> {
+    let socket = bind_mdns().context("Failed to bind mDNS socket")?;
+    let instance = announcement.instance_name()?;
+    let host = announcement.host_name()?;
+    let group = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+    info!("在局域网中宣告 {}", instance);
+
+    // RFC 6762 第 8.3 节：启动时至少宣告两次，间隔一秒
+    for _ in 0..2 {
+        let result = async {
+            let fingerprint = fingerprint(&state).await?;
+            let message = announcement.response(0, &instance, &host, group, &fingerprint, TTL)?;
+            socket.send_to(&message.to_vec()?, group).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("mDNS 宣告失败: {:#}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+
+    let mut buf = vec![0u8; 9000];
+    loop {
+        let (len, source) = match socket.recv_from(&mut buf).await {
+            Ok(received) => received,
+            Err(e) => {
+                // 例如 ICMP 不可达，稍后继续接收
+                warn!("接收 mDNS 包失败: {}", e);
+                tokio::time::sleep(Duration::from_secs(1)).await;
+                continue;
+            }
+        };
+        let Ok(query) = DnsMessage::from_vec(&buf[..len]) else {
+            continue;
+        };
+        if query.metadata.message_type != MessageType::Query {
+            continue;
+        }
+        let service = service_name();
+        let asked = query.queries.iter().any(|question| {
+            let name = question.name();
+            let query_type = question.query_type();
+            (*name == service && matches!(query_type, RecordType::PTR | RecordType::ANY))
+                || (*name == instance && matches!(query_type, RecordType::SRV | RecordType::TXT | RecordType::ANY))
+        });
+        if !asked {
+            continue;
+        }
+        debug!("回答 mDNS 查询 from {}", source);
+        // 源端口不是 5353 的是简单的单播查询方，直接回复对方（RFC 6762 第 6.7 节）
+        let (id, target, ttl) = if source.port() == MDNS_PORT {
+            (0, group, TTL)
+        } else {
+            (query.metadata.id, source, LEGACY_UNICAST_TTL)
+        };
+        let result = async {
+            // 证书可能已重新加载，每次回答时重新计算指纹
+            let fingerprint = fingerprint(&state).await?;
+            let mut response = announcement.response(id, &instance, &host, source, &fingerprint, ttl)?;
+            if target == source {
+                response.add_queries(query.queries.iter().cloned());
+            }
+            socket.send_to(&response.to_vec()?, target).await?;
+            anyhow::Ok(())
+        }
+        .await;
+        if let Err(e) = result {
+            warn!("mDNS 回复失败 to {}: {:#}", target, e);
+        }
+    }
+}
+
+async fn fingerprint(state: &ServerState) -> Result<String> {
+    let cert_path = state.config.read().await.tls.cert.clone();
+    Ok(crypto::certificate_hash(&crypto::load_cert(&cert_path)?))
+}
+
+/// 5353 端口可能已被系统的 mDNS 服务占用，需要地址和端口复用
+fn bind_mdns() -> Result<UdpSocket> {
+    let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+    socket.set_reuse_address(true)?;
+    socket.set_reuse_port(true)?;
+    socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())?;
+    socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+    socket.set_multicast_loop_v4(true)?;
+    socket.set_multicast_ttl_v4(255)?;
+    socket.set_nonblocking(true)?;
+    Ok(UdpSocket::from_std(socket.into())?)
+}
+
+impl Announcement {
+    /// 服务实例名 `<服务器ID>._t3xt._udp.local.`
+    fn instance_name(&self) -> Result<Name> {
+        Name::from_ascii(SERVICE_TYPE)?
+            .prepend_label(self.server_id.as_bytes())
+            .context("Invalid server ID for mDNS")
+    }
+
+    /// 主机名 `t3xt-<服务器ID>.local.`，只保留字母、数字和连字符
+    fn host_name(&self) -> Result<Name> {
+        let label: String = self
+            .server_id
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+            .collect();
+        Name::from_ascii(format!("t3xt-{}.local.", label)).context("Invalid server ID for mDNS")
+    }
+
+    /// PTR、SRV、TXT 和 A 记录，A 记录是通往 `peer` 的本机地址
+    fn response(
+        &self,
+        id: u16,
+        instance: &Name,
+        host: &Name,
+        peer: SocketAddr,
+        fingerprint: &str,
+        ttl: u32,
+    ) -> Result<DnsMessage> {
+        let mut message = DnsMessage::new(id, MessageType::Response, OpCode::Query);
+        message.metadata.authoritative = true;
+        message.add_answer(Record::from_rdata(service_name(), ttl, RData::PTR(PTR(instance.clone()))));
+        message.add_additional(Record::from_rdata(
+            instance.clone(),
+            ttl,
+            RData::SRV(SRV::new(0, 0, self.port, host.clone())),
+        ));
+        message.add_additional(Record::from_rdata(
+            instance.clone(),
+            ttl,
+            RData::TXT(TXT::new(vec![
+                format!("id={}", self.server_id),
+                format!("fp={}", fingerprint),
+            ])),
+        ));
+        if let Some(ip) = self.address_toward(peer) {
+            message.add_additional(Record::from_rdata(host.clone(), ttl, RData::A(A(ip))));
+        }
+        Ok(message)
+    }
+
+    fn address_toward(&self, peer: SocketAddr) -> Option<Ipv4Addr> {
+        match self.bind {
+            IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+            _ => {
+                // 连接 UDP 套接字不发送数据，只让系统选出口地址
+                let probe = StdUdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+                probe.connect(peer).ok()?;
+                match probe.local_addr().ok()?.ip() {
+                    IpAddr::V4(ip) if !ip.is_unspecified() => Some(ip),
+                    _ => None,
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{client::Client, config::Config, server::Server};
+
+    const FINGERPRINT: &str = "ab12";
+    const SOURCE: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 20));
+
+    fn announcement(bind: IpAddr) -> Announcement {
+        Announcement {
+            server_id: "hub".to_string(),
+            bind,
+            port: 10005,
+        }
+    }
+
+    /// 经过编码和解码，与从网络上收到的响应相同
+    fn round_trip(message: &DnsMessage) -> DnsMessage {
+        DnsMessage::from_vec(&message.to_vec().unwrap()).unwrap()
+    }
+
+    fn response(announcement: &Announcement, fingerprint: &str) -> DnsMessage {
+        let instance = announcement.instance_name().unwrap();
+        let host = announcement.host_name().unwrap();
+        let peer = SocketAddr::from((MDNS_GROUP, MDNS_PORT));
+        round_trip(&announcement.response(0, &instance, &host, peer, fingerprint, TTL).unwrap())
+    }
+
+    #[test]
+    fn parses_announced_records() {
+        let response = response(&announcement(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))), FINGERPRINT);
+        assert_eq!(
+            parse_response(&response, SOURCE),
+            vec![DiscoveredServer {
+                id: "hub".to_string(),
+                addr: "10.0.0.5:10005".parse().unwrap(),
+                fingerprint: FINGERPRINT.to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn falls_back_to_the_source_address() {
+        let mut message = response(&announcement(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5))), FINGERPRINT);
+        message.additionals.retain(|record| record.record_type() != RecordType::A);
+        let servers = parse_response(&round_trip(&message), SOURCE);
+        assert_eq!(servers[0].addr, SocketAddr::new(SOURCE, 10005));
+    }
+
+    #[test]
+    fn skips_incomplete_or_foreign_records() {
+        let announcement = announcement(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 5)));
+        let instance = announcement.instance_name().unwrap();
+
+        // 没有指纹的服务器不能固定证书，忽略
+        let mut message = response(&announcement, FINGERPRINT);
+        for record in &mut message.additionals {
+            if let RData::TXT(txt) = &mut record.data {
+                *txt = TXT::new(vec!["id=hub".to_string()]);
+            }
+        }
+        assert!(parse_response(&round_trip(&message), SOURCE).is_empty());
+
+        // 没有 SRV 记录时不知道端口
+        let mut message = response(&announcement, FINGERPRINT);
+        message.additionals.retain(|record| record.record_type() != RecordType::SRV);
+        assert!(parse_response(&round_trip(&message), SOURCE).is_empty());
+
+        // 其他服务类型的 PTR 不是 t3xt 服务器
+        let mut message = DnsMessage::new(0, MessageType::Response, OpCode::Query);
+        let other = Name::from_ascii("_http._tcp.local.").unwrap();
+        message.add_answer(Record::from_rdata(other, TTL, RData::PTR(PTR(instance))));
+        assert!(parse_response(&round_trip(&message), SOURCE).is_empty());
+    }
+
+    #[tokio::test]
+    async fn discovered_fingerprint_pins_the_certificate() {
+        let mut config = Config::default();
+        config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        config.server.port = 0;
+        config.discovery.announce = false;
+        let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+        let port = server.local_addr().unwrap().port();
+        tokio::spawn({
+            let server = Arc::clone(&server);
+            async move { server.run().await }
+        });
+        let actual = crypto::certificate_hash(&crypto::load_cert(&config.tls.cert).unwrap());
+
+        for (fingerprint, accepted) in [(actual, true), ("00".repeat(32), false)] {
+            let mut announcement = announcement(IpAddr::V4(Ipv4Addr::LOCALHOST));
+            announcement.port = port;
+            let discovered = parse_response(&response(&announcement, &fingerprint), SOURCE).remove(0);
+            let mut client_config = config.clone();
+            client_config.client.target = discovered.addr.ip().to_string();
+            client_config.client.port = discovered.addr.port();
+            client_config.client.fingerprint = discovered.fingerprint;
+            // 指纹固定时不读取证书文件
+            client_config.tls.cert = "missing.crt".into();
+            match Client::connect(&client_config).await {
+                Ok((client, _incoming)) => {
+                    assert!(accepted, "mismatched fingerprint was accepted");
+                    client.disconnect().await;
+                }
+                Err(e) => assert!(!accepted, "matching fingerprint was rejected: {e:#}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,126 @@
+use crate::{
+    console,
+    message::*,
+    metrics::METRICS,
+    telemetry,
+    server::{Link, Server, ServerState},
+};
+use anyhow::{bail, Context, Result};
+use quinn::{ClientConfig, Connection, Endpoint};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tracing::{info, info_span, instrument, warn, Instrument};
+
+/// 重连等待时间的上限
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+/// 联邦消息的读取上限，路由通告可能远大于普通消息
+const MAX_LINK_MESSAGE_SIZE: usize = 1024 * 1024;
+
+/// 主动连接对端服务器，链路断开后按指数退避重连
+pub async fn dial(endpoint: Endpoint, state: Arc<ServerState>, addr: SocketAddr) {
+    let mut backoff = Duration::from_secs(1);
+    loop {
+        info!("连接联邦对端 {}", addr);
+        let server_name = state.config.read().await.tls.server_name.clone();
+        let client_config = state.federation_client.read().await.clone();
+        match connect(&endpoint, client_config, addr, &server_name).await {
+            Ok(connection) => {
+                backoff = Duration::from_secs(1);
+                if let Err(e) = run_link(connection, Arc::clone(&state)).await {
+                    warn!("联邦链路 {} 断开: {}", addr, e);
+                }
+            }
+            Err(e) => warn!("连接联邦对端 {} 失败: {}", addr, e),
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn connect(endpoint: &Endpoint, client_config: ClientConfig, addr: SocketAddr, server_name: &str) -> Result<Connection> {
+    let connection = endpoint
+        .connect_with(client_config, addr, server_name)?
+        .await
+        .context("Failed to establish federation connection")?;
+    Ok(connection)
+}
+
+/// 在已认证的连接上交换服务器身份，然后持续接收对端转发的房间消息
+#[instrument(name = "link", skip_all, fields(
+    peer = %connection.remote_address(),
+    conn_id = connection.stable_id(),
+    server_id = tracing::field::Empty,
+))]
+pub async fn run_link(connection: Connection, state: Arc<ServerState>) -> Result<()> {
+    let hello = Message::new(state.server_id.clone(), MessageType::ServerHello {
+        server_id: state.server_id.clone(),
+    });
+    Server::send_message(&connection, hello).await?;
+
+    let mut recv = connection.accept_uni().await
+        .context("Failed to accept federation hello")?;
+    let limit = state.config.read().await.transport.max_message_size;
+    let hello = Server::receive_message(&mut recv, limit).await?;
+    let MessageType::ServerHello { server_id } = hello.message_type else {
+        bail!("expected ServerHello from {}", connection.remote_address());
+    };
+    if server_id == state.server_id {
+        connection.close(0u32.into(), b"same server id");
+        bail!("peer {} uses our own server id", connection.remote_address());
+    }
+
+    {
+        let mut links = state.links.write().await;
+        links.push(Link {
+            connection: connection.clone(),
+            server_id: server_id.clone(),
+        });
+    }
+    METRICS.federation_links.inc();
+    tracing::Span::current().record("server_id", server_id.as_str());
+    info!("联邦链路已建立");
+    console::notice(format_args!("联邦链路已建立: {} ({})", server_id, connection.remote_address()));
+    state.advertise_routes().await;
+
+    let result = receive_loop(&connection, &state, &server_id).await;
+
+    {
+        let mut links = state.links.write().await;
+        links.retain(|link| link.connection.stable_id() != connection.stable_id());
+    }
+    METRICS.federation_links.dec();
+    // 丢弃经由该链路的路由，并把变化通告给其余对端
+    state.routing.lock().await.remove_link(&server_id);
+    state.advertise_routes().await;
+    info!("联邦链路断开");
+    console::notice(format_args!("联邦链路断开: {}", server_id));
+    result
+}
+
+async fn receive_loop(connection: &Connection, state: &ServerState, server_id: &str) -> Result<()> {
+    loop {
+        let mut recv = connection.accept_uni().await
+            .context("Federation link closed")?;
+        let message = match recv.read_to_end(MAX_LINK_MESSAGE_SIZE).await {
+            Ok(data) => {
+                METRICS.bytes_received.inc_by(data.len() as u64);
+                Message::from_bytes(&data)
+            }
+            Err(e) => Err(e.into()),
+        };
+        if let Ok(message) = &message {
+            METRICS.messages_received.with_label_values(&[message.kind()]).inc();
+        }
+        match message {
+            Ok(Message { message_type: MessageType::RouteUpdate { routes }, .. }) => {
+                state.routing.lock().await.update(server_id, routes, &state.server_id);
+                state.advertise_routes().await;
+            }
+            Ok(message) => {
+                let span = info_span!("handle_remote", message_id = %message.id, kind = message.kind());
+                telemetry::set_parent(&span, &message);
+                state.handle_remote(message).instrument(span).await;
+            }
+            Err(e) => warn!("解析联邦消息失败 from {}: {}", server_id, e),
+        }
+    }
+}
@@ -0,0 +1,151 @@
+use crate::{
+    message::*,
+    metrics::METRICS,
+    server::{Outgoing, Peer, PeerConnection, Server, ServerState},
+    telemetry,
+};
+use anyhow::{Context, Result};
+use axum::{
+    extract::{
+        ws::{CloseFrame, Message as Frame, WebSocket, WebSocketUpgrade},
+        ConnectInfo, State,
+    },
+    http::StatusCode,
+    response::{Html, IntoResponse, Response},
+    routing::get,
+    Json, Router,
+};
+use serde_json::json;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::mpsc;
+use tracing::{info, info_span, warn, Instrument};
+
+/// 网关自带的演示页面
+const DEMO_PAGE: &str = include_str!("../web/index.html");
+
+/// 在 `listen` 上提供 WebSocket 网关：`/ws` 收发与 QUIC 相同的消息 JSON，`/` 为演示页面，
+/// `/webtransport` 告诉演示页面如何连接 WebTransport
+pub async fn serve(listen: SocketAddr, state: Arc<ServerState>) -> Result<()> {
+    let app = Router::new()
+        .route("/", get(|| async { Html(DEMO_PAGE) }))
+        .route("/ws", get(upgrade))
+        .route("/webtransport", get(webtransport))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(listen).await
+        .with_context(|| format!("Failed to bind gateway listener {}", listen))?;
+    info!("WebSocket 网关监听地址: http://{}", listen);
+    axum::serve(listener, app.into_make_service_with_connect_info::<SocketAddr>())
+        .await
+        .context("Gateway listener failed")?;
+    Ok(())
+}
+
+/// 演示页面连接 WebTransport 用的端口和证书哈希，未开启时返回 404
+async fn webtransport(State(state): State<Arc<ServerState>>) -> Response {
+    match state.webtransport.read().await.as_ref() {
+        Some(listener) => Json(json!({
+            "port": listener.port,
+            "certificate_hash": listener.certificate_hash,
+        }))
+        .into_response(),
+        None => StatusCode::NOT_FOUND.into_response(),
+    }
+}
+
+async fn upgrade(
+    State(state): State<Arc<ServerState>>,
+    ConnectInfo(remote): ConnectInfo<SocketAddr>,
+    ws: WebSocketUpgrade,
+) -> Response {
+    let config = state.config.read().await;
+    if config.policy.banned_ips.contains(&remote.ip()) {
+        METRICS.connections_rejected.with_label_values(&["banned"]).inc();
+        warn!("拒绝被禁止的地址: {}", remote);
+        return StatusCode::FORBIDDEN.into_response();
+    }
+    let limit = config.transport.max_message_size;
+    drop(config);
+    METRICS.connections_accepted.inc();
+    ws.max_message_size(limit)
+        .on_upgrade(move |socket| handle_socket(socket, state, remote))
+}
+
+/// 与 QUIC 客户端一样登记到客户端列表，发送 Join 后才会广播上线事件
+async fn handle_socket(socket: WebSocket, state: Arc<ServerState>, remote: SocketAddr) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connection = PeerConnection::Gateway {
+        transport: "ws",
+        remote,
+        outgoing: tx,
+    };
+    let peer_addr = connection.key();
+    state.add_peer(Peer::new(connection.clone())).await;
+    let span = info_span!(
+        "connection",
+        peer = %peer_addr,
+        transport = "websocket",
+        client_id = tracing::field::Empty,
+    );
+    span.in_scope(|| info!("新客户端连接"));
+
+    async {
+        if let Err(e) = run(socket, rx, &connection, &state, &peer_addr).await {
+            warn!("WebSocket 连接错误: {:#}", e);
+        }
+        state.remove_peer(&peer_addr).await;
+    }
+    .instrument(span)
+    .await;
+}
+
+async fn run(
+    mut socket: WebSocket,
+    mut outgoing: mpsc::UnboundedReceiver<Outgoing>,
+    connection: &PeerConnection,
+    state: &ServerState,
+    peer_addr: &str,
+) -> Result<()> {
+    loop {
+        tokio::select! {
+            frame = socket.recv() => {
+                let data = match frame {
+                    Some(Ok(Frame::Text(text))) => text.as_str().as_bytes().to_vec(),
+                    Some(Ok(Frame::Binary(data))) => data.to_vec(),
+                    Some(Ok(Frame::Close(_))) | None => return Ok(()),
+                    Some(Ok(_)) => continue,
+                    Some(Err(e)) => return Err(e.into()),
+                };
+                METRICS.bytes_received.inc_by(data.len() as u64);
+                // 消息和信号共用一个通道，按字段区分
+                if let Ok(message) = Message::from_bytes(&data) {
+                    let span = info_span!("handle_message", message_id = %message.id, kind = message.kind());
+                    telemetry::set_parent(&span, &message);
+                    Server::handle_message(connection, state, peer_addr, message)
+                        .instrument(span)
+                        .await;
+                } else if let Ok(signal) = Signal::from_bytes(&data) {
+                    state.forward_signal(peer_addr, signal).await;
+                } else {
+                    warn!("解析消息失败 from {}", peer_addr);
+                }
+            }
+            Some(outgoing) = outgoing.recv() => {
+                let data = match outgoing {
+                    Outgoing::Message(mut message) => {
+                        telemetry::inject(&mut message);
+                        message.to_bytes()?
+                    }
+                    Outgoing::Signal(signal) => signal.to_bytes()?,
+                    Outgoing::Close(reason) => {
+                        let frame = CloseFrame { code: axum::extract::ws::close_code::POLICY, reason: reason.into() };
+                        let _ = socket.send(Frame::Close(Some(frame))).await;
+                        return Ok(());
+                    }
+                };
+                METRICS.bytes_sent.inc_by(data.len() as u64);
+                let text = String::from_utf8(data).context("Message is not valid UTF-8")?;
+                socket.send(Frame::Text(text.into())).await.context("Failed to send message")?;
+            }
+        }
+    }
+}
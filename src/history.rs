@@ -0,0 +1,164 @@
+use crate::message::*;
+use chrono::Utc;
+use std::collections::{HashSet, VecDeque};
+
+/// 服务器保存的最近消息，编辑和删除会就地更新
+pub struct History {
+    messages: VecDeque<Message>,
+    capacity: usize,
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            messages: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, message: Message) {
+        if self.messages.len() == self.capacity {
+            self.messages.pop_front();
+        }
+        self.messages.push_back(message);
+    }
+
+    /// 将消息应用到历史：正文追加，编辑、删除和回应更新目标消息。
+    /// 返回受影响的历史消息，未产生变化时返回 None
+    pub fn apply(&mut self, message: Message) -> Option<&Message> {
+        let target_id = match &message.message_type {
+            MessageType::Text { .. } | MessageType::Tombstone => {
+                // 重复收到（如重连后的历史回放）不再追加
+                if self.get(&message.id).is_some() {
+                    return None;
+                }
+                let id = message.id.clone();
+                self.push(message);
+                id
+            }
+            MessageType::Edit { target_id, content } => {
+                if !self.edit(target_id, content) {
+                    return None;
+                }
+                target_id.clone()
+            }
+            MessageType::Delete { target_id } => {
+                if !self.delete(target_id) {
+                    return None;
+                }
+                target_id.clone()
+            }
+            MessageType::Reaction { target_id, emoji } => {
+                if !self.react(target_id, emoji, &message.sender_id) {
+                    return None;
+                }
+                target_id.clone()
+            }
+            _ => return None,
+        };
+        self.get(&target_id)
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Message> {
+        self.messages.iter().find(|message| message.id == id)
+    }
+
+    /// 修改文本消息内容，目标不存在或已删除时返回 false
+    pub fn edit(&mut self, id: &str, content: &str) -> bool {
+        let Some(message) = self.messages.iter_mut().find(|message| message.id == id) else {
+            return false;
+        };
+        let MessageType::Text { content: old } = &mut message.message_type else {
+            return false;
+        };
+        *old = content.to_string();
+        message.edited_at = Some(Utc::now());
+        true
+    }
+
+    /// 将消息替换为墓碑，保留ID、发送者和时间
+    pub fn delete(&mut self, id: &str) -> bool {
+        let Some(message) = self.messages.iter_mut().find(|message| message.id == id) else {
+            return false;
+        };
+        if matches!(message.message_type, MessageType::Tombstone) {
+            return false;
+        }
+        message.message_type = MessageType::Tombstone;
+        message.reactions.clear();
+        true
+    }
+
+    /// 记录表情回应，同一客户端对同一表情只计一次
+    pub fn react(&mut self, id: &str, emoji: &str, client_id: &str) -> bool {
+        let Some(message) = self.messages.iter_mut().find(|message| message.id == id) else {
+            return false;
+        };
+        if !matches!(message.message_type, MessageType::Text { .. }) {
+            return false;
+        }
+        message.reactions
+            .entry(emoji.to_string())
+            .or_default()
+            .insert(client_id.to_string())
+    }
+
+    /// 按ID前缀查找消息，前缀有歧义时返回 None
+    pub fn find_by_prefix(&self, prefix: &str) -> Option<&Message> {
+        let prefix = prefix.trim_start_matches('#');
+        let mut matches = self.messages.iter().filter(|message| message.id.starts_with(prefix));
+        let found = matches.next()?;
+        matches.next().is_none().then_some(found)
+    }
+
+    /// 以某条消息为根的讨论串，按深度优先顺序返回（深度, 消息）
+    pub fn thread(&self, root_id: &str) -> Vec<(usize, &Message)> {
+        let mut thread = Vec::new();
+        if let Some(root) = self.get(root_id) {
+            self.collect_replies(root, 0, &mut HashSet::new(), &mut thread);
+        }
+        thread
+    }
+
+    /// `visited` 记录已加入的消息，异常数据中的回复环只展开一次
+    fn collect_replies<'a>(
+        &'a self,
+        message: &'a Message,
+        depth: usize,
+        visited: &mut HashSet<&'a str>,
+        thread: &mut Vec<(usize, &'a Message)>,
+    ) {
+        if !visited.insert(&message.id) {
+            return;
+        }
+        thread.push((depth, message));
+        for reply in self.messages.iter().filter(|reply| reply.reply_to.as_deref() == Some(message.id.as_str())) {
+            self.collect_replies(reply, depth + 1, visited, thread);
+        }
+    }
+
+    /// 沿回复链向上找到讨论串的根消息
+    pub fn thread_root<'a>(&'a self, message: &'a Message) -> &'a Message {
+        let mut current = message;
+        // 回复链长度不会超过历史条数，防止异常数据形成环
+        for _ in 0..self.messages.len() {
+            match current.reply_to.as_deref().and_then(|id| self.get(id)) {
+                Some(parent) => current = parent,
+                None => break,
+            }
+        }
+        current
+    }
+
+    /// 某房间最近的 n 条消息，按时间先后排列
+    pub fn recent(&self, room: &str, n: usize) -> Vec<&Message> {
+        let mut recent: Vec<&Message> = self.messages
+            .iter()
+            .rev()
+            .filter(|message| message.room == room)
+            .take(n)
+            .collect();
+        recent.reverse();
+        recent
+    }
+}
@@ -0,0 +1,141 @@
+use crate::{message::Message, metrics::METRICS};
+use std::panic::{self, AssertUnwindSafe};
+use tracing::error;
+
+/// 触发钩子的本地客户端
+#[derive(Debug, Clone)]
+pub struct PeerInfo {
+    /// 对端地址
+    pub addr: String,
+    pub client_id: String,
+    /// 当前所在房间
+    pub room: String,
+    /// 登录时声明为机器人
+    pub bot: bool,
+}
+
+/// 钩子对消息的处理结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Action {
+    /// 交给下一个钩子，全部通过后按正常流程处理
+    Continue,
+    /// 丢弃消息，后面的钩子不再执行
+    Drop,
+}
+
+/// 传给 [`MessageHook::on_message`] 的上下文，可以回复发送者
+pub struct Context<'a> {
+    server_id: &'a str,
+    peer: &'a PeerInfo,
+    replies: Vec<Message>,
+}
+
+impl Context<'_> {
+    /// 发送消息的客户端
+    pub fn peer(&self) -> &PeerInfo {
+        self.peer
+    }
+
+    /// 以服务器身份回复发送者，只有发送者能看到
+    pub fn reply(&mut self, content: impl Into<String>) {
+        let message = Message::new_text(self.server_id.to_string(), content.into());
+        self.reply_message(message);
+    }
+
+    /// 把任意消息回复给发送者，房间设为发送者所在房间
+    pub fn reply_message(&mut self, mut message: Message) {
+        message.room = self.peer.room.clone();
+        self.replies.push(message);
+    }
+}
+
+/// 服务器端的消息处理插件，如过滤、补充内容和审计。
+///
+/// 钩子按注册顺序组成流水线，在服务器线程中同步调用，不应阻塞。
+/// 某个回调 panic 时只跳过该钩子本次的处理，服务器继续运行
+pub trait MessageHook: Send + Sync {
+    /// 出现在日志和指标中的名称
+    fn name(&self) -> &str {
+        "hook"
+    }
+
+    /// 客户端登录后调用，切换房间不会再次调用
+    fn on_connect(&self, _peer: &PeerInfo) {}
+
+    /// 收到已登录客户端的消息后、处理和转发之前调用，可以修改消息。
+    /// 发送者和房间已按服务器记录填好；登录消息和来自联邦链路的消息不经过这里
+    fn on_message(&self, _ctx: &mut Context<'_>, _message: &mut Message) -> Action {
+        Action::Continue
+    }
+
+    /// 已登录的客户端断开后调用
+    fn on_disconnect(&self, _peer: &PeerInfo) {}
+}
+
+/// 按注册顺序执行的钩子
+#[derive(Default)]
+pub(crate) struct Pipeline {
+    hooks: Vec<Box<dyn MessageHook>>,
+}
+
+impl Pipeline {
+    pub(crate) fn push(&mut self, hook: Box<dyn MessageHook>) {
+        self.hooks.push(hook);
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub(crate) fn connect(&self, peer: &PeerInfo) {
+        for hook in &self.hooks {
+            guard(hook.as_ref(), "on_connect", || hook.on_connect(peer));
+        }
+    }
+
+    /// 依次执行 on_message，返回最终结果和需要回复给发送者的消息
+    pub(crate) fn message(&self, server_id: &str, peer: &PeerInfo, message: &mut Message) -> (Action, Vec<Message>) {
+        let mut ctx = Context {
+            server_id,
+            peer,
+            replies: Vec::new(),
+        };
+        for hook in &self.hooks {
+            // panic 时丢弃该钩子改了一半的消息
+            let mut candidate = message.clone();
+            match guard(hook.as_ref(), "on_message", || hook.on_message(&mut ctx, &mut candidate)) {
+                Some(action) => {
+                    *message = candidate;
+                    if action == Action::Drop {
+                        return (Action::Drop, ctx.replies);
+                    }
+                }
+                None => continue,
+            }
+        }
+        (Action::Continue, ctx.replies)
+    }
+
+    pub(crate) fn disconnect(&self, peer: &PeerInfo) {
+        for hook in &self.hooks {
+            guard(hook.as_ref(), "on_disconnect", || hook.on_disconnect(peer));
+        }
+    }
+}
+
+/// 执行一个回调，panic 时记录日志和指标并返回 None
+fn guard<T>(hook: &dyn MessageHook, callback: &str, f: impl FnOnce() -> T) -> Option<T> {
+    match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(value) => Some(value),
+        Err(payload) => {
+            let reason = payload
+                .downcast_ref::<&str>()
+                .copied()
+                .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                .unwrap_or("unknown");
+            error!(hook = hook.name(), "钩子 {} panic: {}", callback, reason);
+            METRICS.hook_panics.with_label_values(&[hook.name()]).inc();
+            None
+        }
+    }
+}
@@ -0,0 +1,359 @@
+//! WebTransport 所需的最小 HTTP/3：控制流和 SETTINGS、扩展 CONNECT 请求、
+//! WebTransport 单向流和数据报的头部。
+//!
+//! 只支持一个会话，不支持 HTTP 请求本身。QPACK 不使用动态表（SETTINGS 中容量为 0），
+//! 请求头只解析判断 WebTransport 请求所需的字段
+
+use anyhow::{bail, Context, Result};
+use quinn::{RecvStream, SendStream};
+use std::fmt;
+
+/// 单向流类型
+pub(crate) const STREAM_CONTROL: u64 = 0x00;
+pub(crate) const STREAM_QPACK_ENCODER: u64 = 0x02;
+pub(crate) const STREAM_QPACK_DECODER: u64 = 0x03;
+pub(crate) const STREAM_WEBTRANSPORT_UNI: u64 = 0x54;
+
+/// 帧类型
+const FRAME_HEADERS: u64 = 0x01;
+const FRAME_SETTINGS: u64 = 0x04;
+
+/// SETTINGS：扩展 CONNECT、HTTP 数据报和 WebTransport（draft-02，与浏览器一致）
+const SETTINGS: [(u64, u64); 4] = [
+    (0x08, 1),       // ENABLE_CONNECT_PROTOCOL
+    (0x33, 1),       // H3_DATAGRAM
+    (0x2b60_3742, 1), // ENABLE_WEBTRANSPORT
+    (0x2b60_3743, 1), // WEBTRANSPORT_MAX_SESSIONS
+];
+
+/// 错误码
+pub(crate) const H3_NO_ERROR: u32 = 0x100;
+pub(crate) const H3_STREAM_CREATION_ERROR: u32 = 0x103;
+const H3_CLOSED_CRITICAL_STREAM: u32 = 0x104;
+const H3_FRAME_ERROR: u32 = 0x106;
+const H3_SETTINGS_ERROR: u32 = 0x109;
+const H3_MISSING_SETTINGS: u32 = 0x10a;
+pub(crate) const H3_REQUEST_REJECTED: u32 = 0x10b;
+
+/// 请求头帧的大小上限
+const MAX_HEADERS_SIZE: u64 = 16 * 1024;
+
+/// SETTINGS 帧的大小上限
+const MAX_SETTINGS_SIZE: u64 = 4 * 1024;
+
+/// 对端违反 HTTP/3 时关闭整个连接使用的错误码和原因
+#[derive(Debug)]
+pub(crate) struct ConnectionError {
+    pub(crate) code: u32,
+    pub(crate) reason: &'static str,
+}
+
+impl ConnectionError {
+    fn new(code: u32, reason: &'static str) -> Self {
+        Self { code, reason }
+    }
+}
+
+impl fmt::Display for ConnectionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (0x{:x})", self.reason, self.code)
+    }
+}
+
+pub(crate) fn encode_varint(value: u64, out: &mut Vec<u8>) {
+    match value {
+        0..=0x3f => out.push(value as u8),
+        0x40..=0x3fff => out.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes()),
+        0x4000..=0x3fff_ffff => out.extend_from_slice(&(value as u32 | 0x8000_0000).to_be_bytes()),
+        _ => out.extend_from_slice(&(value | 0xc000_0000_0000_0000).to_be_bytes()),
+    }
+}
+
+/// 从缓冲区开头解码一个 varint 并前移
+pub(crate) fn decode_varint(buf: &mut &[u8]) -> Option<u64> {
+    let first = *buf.first()?;
+    let len = 1 << (first >> 6);
+    let bytes = buf.get(..len)?;
+    let mut value = u64::from(first & 0x3f);
+    for byte in &bytes[1..] {
+        value = (value << 8) | u64::from(*byte);
+    }
+    *buf = &buf[len..];
+    Some(value)
+}
+
+pub(crate) async fn read_varint(recv: &mut RecvStream) -> Result<u64> {
+    let mut buf = [0u8; 8];
+    recv.read_exact(&mut buf[..1]).await?;
+    let len = 1 << (buf[0] >> 6);
+    recv.read_exact(&mut buf[1..len]).await?;
+    decode_varint(&mut &buf[..len]).context("Invalid varint")
+}
+
+/// 打开服务器的控制流并发送 SETTINGS。控制流在连接期间不能关闭，返回的流需要一直持有
+pub(crate) async fn open_control_stream(connection: &quinn::Connection) -> Result<SendStream> {
+    let mut payload = Vec::new();
+    for (id, value) in SETTINGS {
+        encode_varint(id, &mut payload);
+        encode_varint(value, &mut payload);
+    }
+    let mut data = Vec::new();
+    encode_varint(STREAM_CONTROL, &mut data);
+    encode_frame(FRAME_SETTINGS, &payload, &mut data);
+    let mut send = connection.open_uni().await.context("Failed to open control stream")?;
+    send.write_all(&data).await.context("Failed to send SETTINGS")?;
+    Ok(send)
+}
+
+/// 读取对端控制流（流类型之后）的第一个帧，它必须是 SETTINGS。返回其中的设置
+pub(crate) async fn read_settings(recv: &mut RecvStream) -> Result<Vec<(u64, u64)>, ConnectionError> {
+    let closed = |_| ConnectionError::new(H3_CLOSED_CRITICAL_STREAM, "control stream closed");
+    if read_varint(recv).await.map_err(closed)? != FRAME_SETTINGS {
+        return Err(ConnectionError::new(H3_MISSING_SETTINGS, "first control frame is not SETTINGS"));
+    }
+    let len = read_varint(recv).await.map_err(closed)?;
+    if len > MAX_SETTINGS_SIZE {
+        return Err(ConnectionError::new(H3_FRAME_ERROR, "SETTINGS frame too large"));
+    }
+    let mut payload = vec![0u8; len as usize];
+    recv.read_exact(&mut payload).await
+        .map_err(|_| ConnectionError::new(H3_CLOSED_CRITICAL_STREAM, "control stream closed"))?;
+    decode_settings(&payload)
+}
+
+/// SETTINGS 帧内容是成对的 varint。HTTP/2 才有的设置（0x02 到 0x05）和重复的设置都是错误
+fn decode_settings(mut payload: &[u8]) -> Result<Vec<(u64, u64)>, ConnectionError> {
+    let mut settings: Vec<(u64, u64)> = Vec::new();
+    while !payload.is_empty() {
+        let (Some(id), Some(value)) = (decode_varint(&mut payload), decode_varint(&mut payload)) else {
+            return Err(ConnectionError::new(H3_FRAME_ERROR, "truncated SETTINGS frame"));
+        };
+        if (0x02..=0x05).contains(&id) || settings.iter().any(|(existing, _)| *existing == id) {
+            return Err(ConnectionError::new(H3_SETTINGS_ERROR, "reserved or duplicate setting"));
+        }
+        settings.push((id, value));
+    }
+    Ok(settings)
+}
+
+fn encode_frame(frame_type: u64, payload: &[u8], out: &mut Vec<u8>) {
+    encode_varint(frame_type, out);
+    encode_varint(payload.len() as u64, out);
+    out.extend_from_slice(payload);
+}
+
+/// 读取请求流上的 HEADERS 帧，跳过之前的未知帧，返回解码后的字段
+pub(crate) async fn read_request(recv: &mut RecvStream) -> Result<Vec<(String, String)>> {
+    loop {
+        let frame_type = read_varint(recv).await?;
+        let len = read_varint(recv).await?;
+        if len > MAX_HEADERS_SIZE {
+            bail!("Request header frame too large: {} bytes", len);
+        }
+        let mut payload = vec![0u8; len as usize];
+        recv.read_exact(&mut payload).await?;
+        if frame_type == FRAME_HEADERS {
+            return decode_field_section(&payload);
+        }
+    }
+}
+
+/// 是否为 WebTransport 的扩展 CONNECT 请求。扩展 CONNECT 还必须带有 https 的
+/// :scheme 和非空的 :authority、:path（RFC 9220）
+pub(crate) fn is_webtransport_request(fields: &[(String, String)]) -> bool {
+    let field = |name: &str| fields.iter().find(|(n, _)| n == name).map(|(_, value)| value.as_str());
+    field(":method") == Some("CONNECT")
+        && field(":protocol") == Some("webtransport")
+        && field(":scheme") == Some("https")
+        && field(":authority").is_some_and(|authority| !authority.is_empty())
+        && field(":path").is_some_and(|path| !path.is_empty())
+}
+
+/// 响应的 HEADERS 帧：200 带上 WebTransport 草案版本，其他状态只有状态码
+pub(crate) fn response(status: u16) -> Vec<u8> {
+    // 必需插入数和基址都为 0，之后是静态表中的 :status
+    let mut fields = vec![0x00, 0x00];
+    match status {
+        200 => {
+            fields.push(0xc0 | 25);
+            encode_literal("sec-webtransport-http3-draft", "draft02", &mut fields);
+        }
+        _ => fields.push(0xc0 | 27), // 404
+    }
+    let mut data = Vec::new();
+    encode_frame(FRAME_HEADERS, &fields, &mut data);
+    data
+}
+
+/// 带字面名称的字段行，不使用 Huffman 编码
+fn encode_literal(name: &str, value: &str, out: &mut Vec<u8>) {
+    encode_prefix_int(name.len() as u64, 3, 0x20, out);
+    out.extend_from_slice(name.as_bytes());
+    encode_prefix_int(value.len() as u64, 7, 0x00, out);
+    out.extend_from_slice(value.as_bytes());
+}
+
+fn encode_prefix_int(value: u64, bits: u8, flags: u8, out: &mut Vec<u8>) {
+    let max = (1u64 << bits) - 1;
+    if value < max {
+        out.push(flags | value as u8);
+        return;
+    }
+    out.push(flags | max as u8);
+    let mut rest = value - max;
+    while rest >= 0x80 {
+        out.push(0x80 | (rest & 0x7f) as u8);
+        rest >>= 7;
+    }
+    out.push(rest as u8);
+}
+
+fn decode_prefix_int(buf: &mut &[u8], bits: u8) -> Result<u64> {
+    let (&first, rest) = buf.split_first().context("Truncated field section")?;
+    *buf = rest;
+    let max = (1u64 << bits) - 1;
+    let mut value = u64::from(first) & max;
+    if value < max {
+        return Ok(value);
+    }
+    let mut shift = 0;
+    loop {
+        let (&byte, rest) = buf.split_first().context("Truncated field section")?;
+        *buf = rest;
+        if shift > 56 {
+            bail!("Integer overflow in field section");
+        }
+        value += u64::from(byte & 0x7f) << shift;
+        shift += 7;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+}
+
+/// 长度前缀的字符串，`bits` 为长度前缀的位数，其上一位是 Huffman 标志
+fn decode_string(buf: &mut &[u8], bits: u8) -> Result<String> {
+    let huffman = buf.first().is_some_and(|first| first & (1 << bits) != 0);
+    let len = decode_prefix_int(buf, bits)? as usize;
+    if buf.len() < len {
+        bail!("Truncated field section");
+    }
+    let (raw, rest) = buf.split_at(len);
+    *buf = rest;
+    let bytes = if huffman { huffman_decode(raw)? } else { raw.to_vec() };
+    String::from_utf8(bytes).context("Field is not UTF-8")
+}
+
+/// 解码 QPACK 字段块。不接受动态表引用，不认识的静态表项以空名称返回
+fn decode_field_section(mut buf: &[u8]) -> Result<Vec<(String, String)>> {
+    let buf = &mut buf;
+    let required_insert_count = decode_prefix_int(buf, 8)?;
+    decode_prefix_int(buf, 7)?;
+    if required_insert_count != 0 {
+        bail!("QPACK dynamic table is not supported");
+    }
+    let mut fields = Vec::new();
+    while let Some(&first) = buf.first() {
+        let field = if first & 0x80 != 0 {
+            // 索引字段行
+            if first & 0x40 == 0 {
+                bail!("QPACK dynamic table is not supported");
+            }
+            let (name, value) = static_entry(decode_prefix_int(buf, 6)?);
+            (name.to_string(), value.to_string())
+        } else if first & 0x40 != 0 {
+            // 引用名称的字面字段行
+            if first & 0x10 == 0 {
+                bail!("QPACK dynamic table is not supported");
+            }
+            let (name, _) = static_entry(decode_prefix_int(buf, 4)?);
+            (name.to_string(), decode_string(buf, 7)?)
+        } else if first & 0x20 != 0 {
+            // 带字面名称的字段行
+            let name = decode_string(buf, 3)?;
+            (name, decode_string(buf, 7)?)
+        } else {
+            bail!("QPACK dynamic table is not supported");
+        };
+        fields.push(field);
+    }
+    Ok(fields)
+}
+
+/// QPACK 静态表中与判断请求有关的项（RFC 9204 附录 A）
+fn static_entry(index: u64) -> (&'static str, &'static str) {
+    match index {
+        0 => (":authority", ""),
+        1 => (":path", "/"),
+        15 => (":method", "CONNECT"),
+        16 => (":method", "DELETE"),
+        17 => (":method", "GET"),
+        18 => (":method", "HEAD"),
+        19 => (":method", "OPTIONS"),
+        20 => (":method", "POST"),
+        21 => (":method", "PUT"),
+        22 => (":scheme", "http"),
+        23 => (":scheme", "https"),
+        _ => ("", ""),
+    }
+}
+
+/// HPACK 的 Huffman 编码是规范编码（RFC 7541 附录 B），由每个符号的码长即可解码
+const HUFFMAN_LENGTHS: [u8; 256] = [
+    13, 23, 28, 28, 28, 28, 28, 28, 28, 24, 30, 28, 28, 30, 28, 28,
+    28, 28, 28, 28, 28, 28, 30, 28, 28, 28, 28, 28, 28, 28, 28, 28,
+    6, 10, 10, 12, 13, 6, 8, 11, 10, 10, 8, 11, 8, 6, 6, 6,
+    5, 5, 5, 6, 6, 6, 6, 6, 6, 6, 7, 8, 15, 6, 12, 10,
+    13, 6, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7, 7,
+    7, 7, 7, 7, 7, 7, 7, 7, 8, 7, 8, 13, 19, 13, 14, 6,
+    15, 5, 6, 5, 6, 5, 6, 6, 6, 5, 7, 7, 6, 6, 6, 5,
+    6, 7, 6, 5, 5, 6, 7, 7, 7, 7, 7, 15, 11, 14, 13, 28,
+    20, 22, 20, 20, 22, 22, 22, 23, 22, 23, 23, 23, 23, 23, 24, 23,
+    24, 24, 22, 23, 24, 23, 23, 23, 23, 21, 22, 23, 22, 23, 23, 24,
+    22, 21, 20, 22, 22, 23, 23, 21, 23, 22, 22, 24, 21, 22, 23, 23,
+    21, 21, 22, 21, 23, 22, 23, 23, 20, 22, 22, 22, 23, 22, 22, 23,
+    26, 26, 20, 19, 22, 23, 22, 25, 26, 26, 26, 27, 27, 26, 24, 25,
+    19, 21, 26, 27, 27, 26, 27, 24, 21, 21, 26, 26, 28, 27, 27, 27,
+    20, 24, 20, 21, 22, 21, 21, 23, 22, 22, 25, 25, 24, 24, 26, 23,
+    26, 27, 26, 26, 27, 27, 27, 27, 27, 28, 27, 27, 27, 27, 27, 26,
+];
+
+/// 最长码长（EOS 为 30 位）
+const HUFFMAN_MAX_BITS: usize = 30;
+
+fn huffman_decode(input: &[u8]) -> Result<Vec<u8>> {
+    // 按码长、符号排序即为规范编码的分配顺序
+    let mut count = [0u32; HUFFMAN_MAX_BITS + 1];
+    for &len in &HUFFMAN_LENGTHS {
+        count[len as usize] += 1;
+    }
+    let mut symbols: Vec<u8> = (0..=255).collect();
+    symbols.sort_by_key(|&symbol| HUFFMAN_LENGTHS[symbol as usize]);
+
+    let mut out = Vec::with_capacity(input.len() * 8 / 5);
+    let bits = input.iter().flat_map(|byte| (0..8).rev().map(move |shift| (byte >> shift) & 1));
+    let (mut code, mut first, mut index, mut len) = (0u32, 0u32, 0u32, 0usize);
+    // 当前符号已读的位是否全为 1，末尾不足一个符号的填充必须是 EOS 的前缀
+    let mut all_ones = true;
+    for bit in bits {
+        code |= u32::from(bit);
+        all_ones &= bit == 1;
+        len += 1;
+        if len > HUFFMAN_MAX_BITS {
+            bail!("Invalid Huffman code");
+        }
+        let n = count[len];
+        if code - first < n {
+            out.push(symbols[(index + code - first) as usize]);
+            (code, first, index, len, all_ones) = (0, 0, 0, 0, true);
+            continue;
+        }
+        index += n;
+        first = (first + n) << 1;
+        code <<= 1;
+    }
+    if len > 7 || !all_ones {
+        bail!("Invalid Huffman padding");
+    }
+    Ok(out)
+}
@@ -0,0 +1,299 @@
+use crate::{
+    client::{Client, Incoming},
+    console,
+    history::History,
+    message::*,
+};
+use anyhow::Result;
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+use tokio_stream::{Stream, StreamExt};
+use tracing::warn;
+
+/// 输入提示的有效期，超时后自动失效
+const TYPING_TTL: Duration = Duration::from_secs(4);
+
+/// 在线心跳的最短间隔
+const PING_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 客户端缓存的消息条数，用于编辑和删除后重新显示
+const DISPLAY_CACHE: usize = 500;
+
+/// 终端聊天界面：从 `input` 逐行读取命令和消息，把收到的消息打印到终端。
+/// 输入结束、输入 /quit 或连接关闭时返回
+pub async fn run(client: &Client, incoming: Incoming, mut input: impl Stream<Item = String> + Unpin) -> Result<()> {
+    let mut chat = Chat {
+        client_id: client.client_id().to_string(),
+        displayed: History::new(DISPLAY_CACHE),
+        typing: HashMap::new(),
+        sent: Vec::new(),
+        typing_sent: None,
+        ping_sent: None,
+    };
+
+    let (mut messages, mut signals) = incoming.split();
+    console::line("输入消息并按回车发送，输入 '/quit' 退出");
+    console::line("命令: /who  /away [状态]  /back  /typing  /edit <内容>  /delete  /netstat");
+    console::line("      /reply <#id> <内容>  /react <#id> <表情>  /thread <#id>  /mentions  /join <房间>");
+    console::line("      /msg <用户> <内容>  /p2p <用户>  其他 /命令 作为文本发送，供机器人处理");
+    console::line("─────────────────────────────────────");
+
+    loop {
+        tokio::select! {
+            line = input.next() => {
+                let Some(line) = line else { break };
+                if !chat.handle_input(client, line.trim()).await {
+                    break;
+                }
+            }
+            message = messages.next() => {
+                let Some(message) = message else { break };
+                // 收到正文后该用户的输入提示随之结束
+                chat.typing.remove(&message.sender_id);
+                chat.display_message(message);
+            }
+            Some(signal) = signals.next() => chat.handle_signal(signal),
+        }
+    }
+    Ok(())
+}
+
+struct Chat {
+    client_id: String,
+    /// 已显示的消息，用于重新显示、回复和讨论串
+    displayed: History,
+    /// 正在输入的用户及其提示的过期时间
+    typing: HashMap<String, Instant>,
+    /// 本客户端发送的文本消息ID，最近的在末尾
+    sent: Vec<String>,
+    /// 最近一次发出输入提示和在线心跳的时间
+    typing_sent: Option<Instant>,
+    ping_sent: Option<Instant>,
+}
+
+impl Chat {
+    /// 处理一行输入，返回 false 表示退出
+    async fn handle_input(&mut self, client: &Client, input: &str) -> bool {
+        if input == "/quit" {
+            return false;
+        }
+
+        if input.is_empty() {
+            return true;
+        }
+
+        // 本地命令不经过服务器，用心跳刷新服务器记录的空闲时间
+        if self.ping_sent.is_none_or(|sent| sent.elapsed() >= PING_INTERVAL) {
+            self.ping_sent = Some(Instant::now());
+            if let Err(e) = client.send_signal(SignalKind::Ping) {
+                warn!("Failed to send ping signal: {}", e);
+            }
+        }
+
+        // 行模式下无法感知按键：每行正文发出前自动发出输入提示（至多每半个有效期一次），
+        // 也可以用 /typing 主动发出
+        if input == "/typing" || (!input.starts_with('/') && self.typing_due()) {
+            self.typing_sent = Some(Instant::now());
+            if let Err(e) = client.send_signal(SignalKind::Typing) {
+                warn!("Failed to send typing signal: {}", e);
+            }
+            if input == "/typing" {
+                return true;
+            }
+        }
+
+        if let Some(room) = input.strip_prefix("/join") {
+            let room = room.trim();
+            if room.is_empty() {
+                console::line(format_args!("用法: /join <房间>（当前房间: {}）", client.room()));
+                return true;
+            }
+            return client.join(room).await.is_ok();
+        }
+
+        // 打洞可能需要几秒，在后台进行，结果由直连模块打印
+        if let Some(peer) = input.strip_prefix("/p2p") {
+            let peer = peer.trim().trim_start_matches('@').to_string();
+            if peer.is_empty() {
+                let peers = client.direct_peers();
+                let peers = if peers.is_empty() { "无".to_string() } else { peers.join(", ") };
+                console::line(format_args!("用法: /p2p <用户>（当前直连: {}）", peers));
+                return true;
+            }
+            console::line(format_args!("正在与 {} 建立直连...", peer));
+            let client = client.clone();
+            tokio::spawn(async move {
+                if let Err(e) = client.connect_direct(&peer).await {
+                    console::line(format_args!("无法与 {} 直连，私信继续经服务器转发: {:#}", peer, e));
+                }
+            });
+            return true;
+        }
+
+        if input == "/netstat" {
+            print_netstat(client);
+            return true;
+        }
+
+        if let Some(id) = input.strip_prefix("/thread") {
+            self.print_thread(id.trim());
+            return true;
+        }
+
+        let message = match self.parse_command(input) {
+            Some(Ok(message)) => message,
+            Some(Err(usage)) => {
+                console::line(usage);
+                return true;
+            }
+            None => Message::new_text(self.client_id.clone(), input.to_string()),
+        };
+
+        match &message.message_type {
+            MessageType::Text { .. } => self.sent.push(message.id.clone()),
+            MessageType::Delete { target_id } => self.sent.retain(|id| id != target_id),
+            // 私信不会回传给发送者，在本地显示
+            MessageType::Direct { .. } => console::message(&message, Some(&self.client_id)),
+            _ => {}
+        }
+        // 自己的操作不会被服务器回传，直接更新本地缓存
+        self.displayed.apply(message.clone());
+
+        client.send(message).await.is_ok()
+    }
+
+    fn typing_due(&self) -> bool {
+        self.typing_sent.is_none_or(|sent| sent.elapsed() >= TYPING_TTL / 2)
+    }
+
+    /// 显示收到的消息；编辑和删除会重新显示被修改的那一行
+    fn display_message(&mut self, message: Message) {
+        let local_id = Some(self.client_id.as_str());
+        // 被提及或收到私信时响铃
+        let direct = matches!(message.message_type, MessageType::Direct { .. });
+        if message.sender_id != self.client_id && (direct || message.mentions_user(&self.client_id)) {
+            console::bell();
+        }
+        let line = message.format_display_for(local_id);
+        let is_reaction = matches!(message.message_type, MessageType::Reaction { .. });
+        match self.displayed.apply(message) {
+            Some(updated) if !is_reaction => console::message(updated, local_id),
+            _ => console::line(line),
+        }
+    }
+
+    /// 显示某条消息所在的整个讨论串
+    fn print_thread(&self, id: &str) {
+        let Some(message) = self.displayed.find_by_prefix(id).filter(|_| !id.is_empty()) else {
+            console::line(format_args!("找不到消息 {}", id));
+            return;
+        };
+        let root = self.displayed.thread_root(message);
+        console::line(format_args!("── 讨论串 #{} ──", short_id(&root.id)));
+        for (depth, message) in self.displayed.thread(&root.id) {
+            console::line(format_args!("{}{}", "  ".repeat(depth), message.format_display_for(Some(&self.client_id))));
+        }
+    }
+
+    fn handle_signal(&mut self, signal: Signal) {
+        match signal.kind {
+            SignalKind::Typing => {
+                let now = Instant::now();
+                let active = self.typing
+                    .get(&signal.sender_id)
+                    .is_some_and(|expires| *expires > now);
+                if !active {
+                    console::line(format_args!("{} is typing…", signal.sender_id));
+                }
+                self.typing.insert(signal.sender_id, now + TYPING_TTL);
+            }
+            // 服务器不转发心跳
+            SignalKind::Ping => {}
+        }
+    }
+
+    /// 解析斜杠命令；非命令输入返回 None，用法错误返回 Some(Err)
+    fn parse_command(&self, input: &str) -> Option<Result<Message, &'static str>> {
+        let client_id = self.client_id.as_str();
+        let (command, arg) = match input.split_once(' ') {
+            Some((command, arg)) => (command, Some(arg.trim()).filter(|arg| !arg.is_empty())),
+            None => (input, None),
+        };
+        let message = match command {
+            "/who" => Message::new(client_id.to_string(), MessageType::WhoRequest),
+            "/mentions" => Message::new(client_id.to_string(), MessageType::MentionsRequest),
+            "/away" => Message::new_presence(
+                client_id.to_string(),
+                PresenceEvent::Away,
+                arg.map(str::to_string),
+            ),
+            "/back" => Message::new_presence(client_id.to_string(), PresenceEvent::Back, None),
+            "/edit" => {
+                let (Some(target_id), Some(content)) = (self.sent.last(), arg) else {
+                    return Some(Err("用法: /edit <新内容>（修改最近发送的消息）"));
+                };
+                Message::new(client_id.to_string(), MessageType::Edit {
+                    target_id: target_id.clone(),
+                    content: content.to_string(),
+                })
+            }
+            "/delete" => {
+                let Some(target_id) = self.sent.last() else {
+                    return Some(Err("没有可删除的消息"));
+                };
+                Message::new(client_id.to_string(), MessageType::Delete { target_id: target_id.clone() })
+            }
+            "/msg" => {
+                let Some((recipient, content)) = arg.and_then(|arg| arg.split_once(' ')) else {
+                    return Some(Err("用法: /msg <用户> <内容>"));
+                };
+                Message::new(client_id.to_string(), MessageType::Direct {
+                    recipient: recipient.trim_start_matches('@').to_string(),
+                    content: content.trim().to_string(),
+                })
+            }
+            "/reply" | "/react" => {
+                let Some((id, rest)) = arg.and_then(|arg| arg.split_once(' ')) else {
+                    return Some(Err("用法: /reply <#id> <内容> 或 /react <#id> <表情>"));
+                };
+                let Some(target) = self.displayed.find_by_prefix(id) else {
+                    return Some(Err("找不到该消息，或ID前缀不唯一"));
+                };
+                let rest = rest.trim().to_string();
+                if command == "/reply" {
+                    Message::new_reply(client_id.to_string(), rest, target.id.clone())
+                } else {
+                    Message::new(client_id.to_string(), MessageType::Reaction {
+                        target_id: target.id.clone(),
+                        emoji: rest,
+                    })
+                }
+            }
+            // 未知的斜杠命令原样发到房间，由机器人处理
+            _ => return None,
+        };
+        Some(Ok(message))
+    }
+}
+
+/// 与服务器连接的往返时间、拥塞窗口、收发和丢包统计
+fn print_netstat(client: &Client) {
+    let Some(stats) = client.stats() else {
+        console::line("TCP 连接没有传输统计");
+        return;
+    };
+    console::line(format_args!(
+        "RTT {:.1}ms  拥塞窗口 {} 字节  拥塞事件 {}",
+        stats.rtt.as_secs_f64() * 1000.0, stats.cwnd, stats.congestion_events,
+    ));
+    console::line(format_args!(
+        "发送 {} 个 UDP 包 {} 字节  接收 {} 个 {} 字节",
+        stats.sent_datagrams, stats.sent_bytes, stats.received_datagrams, stats.received_bytes,
+    ));
+    console::line(format_args!(
+        "丢包 {} 个 {} 字节  MTU 探测 {} 个（丢失 {}）  黑洞 {} 次",
+        stats.lost_packets, stats.lost_bytes, stats.mtu_probes, stats.lost_mtu_probes, stats.black_holes,
+    ));
+}
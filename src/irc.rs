@@ -0,0 +1,494 @@
+use crate::{
+    message::*,
+    metrics::METRICS,
+    server::{Outgoing, Peer, PeerConnection, Server, ServerState},
+};
+use anyhow::{Context, Result};
+use std::{net::SocketAddr, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{tcp::OwnedReadHalf, TcpListener, TcpStream},
+    sync::mpsc,
+};
+use tracing::{info, info_span, warn, Instrument};
+
+/// IRC 客户端看到的用户名和主机名
+const HOST: &str = "t3xt";
+
+/// 在 `listen` 上提供 IRC 协议桥，支持 RFC 1459/2812 的一个子集：
+/// NICK、USER、JOIN、PART、PRIVMSG、NOTICE、NAMES、WHO、QUIT 和 PING
+pub async fn serve(listen: SocketAddr, state: Arc<ServerState>) -> Result<()> {
+    let listener = TcpListener::bind(listen).await
+        .with_context(|| format!("Failed to bind IRC listener {}", listen))?;
+    info!("IRC 协议桥监听地址: {}", listen);
+    loop {
+        let (stream, remote) = listener.accept().await.context("Failed to accept IRC connection")?;
+        if state.config.read().await.policy.banned_ips.contains(&remote.ip()) {
+            METRICS.connections_rejected.with_label_values(&["banned"]).inc();
+            warn!("拒绝被禁止的地址: {}", remote);
+            continue;
+        }
+        METRICS.connections_accepted.inc();
+        tokio::spawn(handle_client(stream, Arc::clone(&state), remote));
+    }
+}
+
+/// 与 QUIC 客户端一样登记到客户端列表，JOIN 频道时才登录
+async fn handle_client(stream: TcpStream, state: Arc<ServerState>, remote: SocketAddr) {
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connection = PeerConnection::Gateway {
+        transport: "irc",
+        remote,
+        outgoing: tx,
+    };
+    let peer_addr = connection.key();
+    state.add_peer(Peer::new(connection.clone())).await;
+    let span = info_span!(
+        "connection",
+        peer = %peer_addr,
+        transport = "irc",
+        client_id = tracing::field::Empty,
+    );
+    span.in_scope(|| info!("新客户端连接"));
+
+    let session = Session {
+        state: &state,
+        connection,
+        peer_addr: peer_addr.clone(),
+        nick: None,
+        user: None,
+        registered: false,
+        channel: None,
+    };
+    async {
+        if let Err(e) = run(stream, rx, session).await {
+            warn!("IRC 连接错误: {:#}", e);
+        }
+        state.remove_peer(&peer_addr).await;
+    }
+    .instrument(span)
+    .await;
+}
+
+async fn run(stream: TcpStream, mut outgoing: mpsc::UnboundedReceiver<Outgoing>, mut session: Session<'_>) -> Result<()> {
+    let (read, mut write) = stream.into_split();
+    let limit = session.state.config.read().await.transport.max_message_size;
+    let (lines_tx, mut lines) = mpsc::channel(32);
+    let reader = tokio::spawn(read_lines(read, limit, lines_tx));
+
+    let result = async {
+        loop {
+            let replies = tokio::select! {
+                line = lines.recv() => match line {
+                    Some(line) => match session.handle(&line?).await {
+                        Flow::Continue(replies) => replies,
+                        Flow::Quit(replies) => {
+                            send_lines(&mut write, &replies).await?;
+                            return Ok(());
+                        }
+                    },
+                    None => return Ok(()),
+                },
+                Some(outgoing) = outgoing.recv() => match outgoing {
+                    Outgoing::Message(message) => session.translate(&message),
+                    // IRC 没有输入提示
+                    Outgoing::Signal(_) => continue,
+                    Outgoing::Close(reason) => {
+                        send_lines(&mut write, &[format!("ERROR :Closing link: {}", reason)]).await?;
+                        return Ok(());
+                    }
+                },
+            };
+            send_lines(&mut write, &replies).await?;
+        }
+    }
+    .await;
+    reader.abort();
+    result
+}
+
+/// 逐行读取，\r\n、\n 和单独的 \r 都视为行尾，空行跳过；非 UTF-8 字节按替换字符处理
+async fn read_lines(read: OwnedReadHalf, limit: usize, lines: mpsc::Sender<Result<String>>) {
+    let mut reader = BufReader::new(read);
+    let mut buf = Vec::new();
+    loop {
+        let available = match reader.fill_buf().await {
+            Ok([]) => return,
+            Ok(available) => available,
+            Err(e) => {
+                let _ = lines.send(Err(e.into())).await;
+                return;
+            }
+        };
+        let (line, consumed) = match available.iter().position(|byte| matches!(byte, b'\r' | b'\n')) {
+            Some(end) => {
+                buf.extend_from_slice(&available[..end]);
+                (true, end + 1)
+            }
+            None => {
+                buf.extend_from_slice(available);
+                (false, available.len())
+            }
+        };
+        reader.consume(consumed);
+        METRICS.bytes_received.inc_by(consumed as u64);
+        if buf.len() >= limit {
+            let _ = lines.send(Err(anyhow::anyhow!("Line exceeds {} bytes", limit))).await;
+            return;
+        }
+        if line && !buf.is_empty() {
+            let line = String::from_utf8_lossy(&buf).to_string();
+            buf.clear();
+            if lines.send(Ok(line)).await.is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// 房间名、表情等其他字段中的 \r、\n 和 NUL 在这里统一去掉，一行只能是一条命令
+async fn send_lines(write: &mut (impl AsyncWriteExt + Unpin), lines: &[String]) -> Result<()> {
+    for line in lines {
+        let mut data = line.replace(['\r', '\n', '\0'], "");
+        data.push_str("\r\n");
+        write.write_all(data.as_bytes()).await.context("Failed to write to IRC client")?;
+        METRICS.bytes_sent.inc_by(data.len() as u64);
+    }
+    Ok(())
+}
+
+enum Flow {
+    Continue(Vec<String>),
+    Quit(Vec<String>),
+}
+
+/// 一个 IRC 连接的状态。t3xt 客户端同一时间只在一个房间，因此最多加入一个频道
+struct Session<'a> {
+    state: &'a ServerState,
+    connection: PeerConnection,
+    peer_addr: String,
+    nick: Option<String>,
+    user: Option<String>,
+    registered: bool,
+    /// 当前频道对应的房间名，不含 #
+    channel: Option<String>,
+}
+
+impl Session<'_> {
+    async fn handle(&mut self, line: &str) -> Flow {
+        let Some((command, params)) = parse(line) else {
+            return Flow::Continue(Vec::new());
+        };
+        let replies = match command.as_str() {
+            // 不支持能力协商，客户端会直接继续注册
+            "CAP" | "PASS" | "PONG" => Vec::new(),
+            "PING" => vec![format!(":{0} PONG {0} :{1}", self.server(), params.first().copied().unwrap_or_default())],
+            "QUIT" => return Flow::Quit(vec![format!("ERROR :Closing link: {} (Quit)", self.nick_or_star())]),
+            "NICK" => return self.nick(&params).await,
+            "USER" => self.user(&params),
+            _ if !self.registered => vec![self.numeric("451", ":You have not registered")],
+            "JOIN" => return self.join(&params).await,
+            "PART" => self.part(&params).await,
+            "PRIVMSG" | "NOTICE" => self.privmsg(&command, &params).await,
+            "NAMES" => self.names(params.first().copied()).await,
+            "WHO" => self.who(params.first().copied()).await,
+            _ => vec![self.numeric("421", &format!("{} :Unknown command", command))],
+        };
+        Flow::Continue(replies)
+    }
+
+    async fn nick(&mut self, params: &[&str]) -> Flow {
+        let Some(nick) = params.first().copied() else {
+            return Flow::Continue(vec![self.numeric("431", ":No nickname given")]);
+        };
+        if self.nick.as_deref() == Some(nick) {
+            return Flow::Continue(Vec::new());
+        }
+        if !is_valid_id(nick) || nick.starts_with(['#', '&']) || nick.contains([',', '*', '?']) {
+            return Flow::Continue(vec![self.numeric("432", &format!("{} :Erroneous nickname", nick))]);
+        }
+        if self.registered {
+            return Flow::Continue(vec![self.numeric("400", "NICK :Changing nickname is not supported")]);
+        }
+        // 与服务器核心登录时的检查相同，被拒绝的ID不发送欢迎信息
+        if self.state.config.read().await.policy.banned_ids.iter().any(|id| id == nick) {
+            return Flow::Quit(vec![
+                self.numeric("465", ":You are banned from this server"),
+                format!("ERROR :Closing link: {} (Banned)", nick),
+            ]);
+        }
+        if self.state.is_reserved(nick).await {
+            return Flow::Continue(vec![self.numeric("432", &format!("{} :Nickname is reserved", nick))]);
+        }
+        if self.state.is_online(nick).await {
+            return Flow::Continue(vec![self.numeric("433", &format!("{} :Nickname is already in use", nick))]);
+        }
+        self.nick = Some(nick.to_string());
+        Flow::Continue(self.register())
+    }
+
+    fn user(&mut self, params: &[&str]) -> Vec<String> {
+        if self.registered {
+            return vec![self.numeric("462", ":You may not reregister")];
+        }
+        let Some(user) = params.first() else {
+            return vec![self.numeric("461", "USER :Not enough parameters")];
+        };
+        self.user = Some(user.to_string());
+        self.register()
+    }
+
+    /// NICK 和 USER 都收到后发送欢迎信息
+    fn register(&mut self) -> Vec<String> {
+        if self.registered || self.nick.is_none() || self.user.is_none() {
+            return Vec::new();
+        }
+        self.registered = true;
+        let nick = self.nick_or_star().to_string();
+        vec![
+            self.numeric("001", &format!(":Welcome to t3xt, {}", nick)),
+            self.numeric("002", &format!(":Your host is {}, running t3xt {}", self.server(), env!("CARGO_PKG_VERSION"))),
+            self.numeric("004", &format!("{} {} o o", self.server(), env!("CARGO_PKG_VERSION"))),
+            self.numeric("005", "CHANTYPES=# NETWORK=t3xt :are supported by this server"),
+            self.numeric("422", ":MOTD File is missing"),
+        ]
+    }
+
+    async fn join(&mut self, params: &[&str]) -> Flow {
+        let Some(channels) = params.first() else {
+            return Flow::Continue(vec![self.numeric("461", "JOIN :Not enough parameters")]);
+        };
+        if *channels == "0" {
+            return Flow::Continue(self.leave(None).await);
+        }
+        // 同时只能在一个频道，列表中以最后一个为准
+        let channel = channels.rsplit(',').next().unwrap_or_default();
+        let Some(room) = channel.strip_prefix('#').filter(|room| !room.is_empty()) else {
+            return Flow::Continue(vec![self.numeric("403", &format!("{} :No such channel", channel))]);
+        };
+        if self.channel.as_deref() == Some(room) {
+            return Flow::Continue(Vec::new());
+        }
+
+        let mut replies = Vec::new();
+        if let Some(previous) = self.channel.take() {
+            replies.push(format!(":{} PART #{}", prefix(self.nick_or_star()), previous));
+        }
+        let mut join = Message::new_presence(self.nick_or_star().to_string(), PresenceEvent::Join, None);
+        join.room = room.to_string();
+        self.submit(join).await;
+        // 注册之后ID可能已被其他连接占用，服务器拒绝登录时会关闭连接，不能再回复加入成功
+        let logged_in = self.state.identity(&self.peer_addr).await.is_some_and(|peer| Some(peer.client_id) == self.nick);
+        if !logged_in {
+            let nick = self.nick_or_star().to_string();
+            replies.push(match self.state.is_online(&nick).await {
+                true => self.numeric("433", &format!("{} :Nickname is already in use", nick)),
+                false => self.numeric("432", &format!("{} :Erroneous nickname", nick)),
+            });
+            replies.push(format!("ERROR :Closing link: {} (Login rejected)", nick));
+            return Flow::Quit(replies);
+        }
+        self.channel = Some(room.to_string());
+        replies.push(format!(":{} JOIN #{}", prefix(self.nick_or_star()), room));
+        replies.extend(self.names(Some(channel)).await);
+        Flow::Continue(replies)
+    }
+
+    async fn part(&mut self, params: &[&str]) -> Vec<String> {
+        let Some(channels) = params.first() else {
+            return vec![self.numeric("461", "PART :Not enough parameters")];
+        };
+        let current = self.channel.as_deref().map(|room| format!("#{}", room));
+        match channels.split(',').find(|channel| Some(*channel) == current.as_deref()) {
+            Some(_) => self.leave(params.get(1).copied()).await,
+            None => vec![self.numeric("442", &format!("{} :You're not on that channel", channels))],
+        }
+    }
+
+    /// 离开当前频道，t3xt 中相当于退出登录但保持连接
+    async fn leave(&mut self, reason: Option<&str>) -> Vec<String> {
+        let Some(room) = self.channel.take() else {
+            return Vec::new();
+        };
+        self.state.log_out(&self.peer_addr).await;
+        let mut line = format!(":{} PART #{}", prefix(self.nick_or_star()), room);
+        if let Some(reason) = reason {
+            line.push_str(&format!(" :{}", reason));
+        }
+        vec![line]
+    }
+
+    async fn privmsg(&mut self, command: &str, params: &[&str]) -> Vec<String> {
+        let (Some(target), Some(text)) = (params.first().copied(), params.get(1).copied()) else {
+            return vec![self.numeric("412", &format!("{} :No text to send", command))];
+        };
+        let content = match text.strip_prefix('\x01') {
+            // CTCP 只保留 ACTION（/me），其他如 VERSION 忽略
+            Some(ctcp) => match ctcp.trim_end_matches('\x01').strip_prefix("ACTION ") {
+                Some(action) => format!("* {}", action),
+                None => return Vec::new(),
+            },
+            None => text.to_string(),
+        };
+        let Some(room) = self.channel.clone() else {
+            return vec![self.numeric("404", &format!("{} :Join a channel first", target))];
+        };
+
+        let message = match target.strip_prefix('#') {
+            Some(target_room) if target_room == room => Message::new_text(self.nick_or_star().to_string(), content),
+            Some(_) => return vec![self.numeric("404", &format!("{} :Cannot send to channel", target))],
+            None => Message::new(self.nick_or_star().to_string(), MessageType::Direct {
+                recipient: target.to_string(),
+                content,
+            }),
+        };
+        self.submit(message).await;
+        Vec::new()
+    }
+
+    async fn names(&self, channel: Option<&str>) -> Vec<String> {
+        let Some(room) = self.target_room(channel) else {
+            return vec![self.numeric("366", "* :End of /NAMES list")];
+        };
+        let names: Vec<String> = self.state.members(Some(&room)).await
+            .into_iter()
+            .map(|member| member.client_id)
+            .collect();
+        let mut replies = Vec::new();
+        if !names.is_empty() {
+            replies.push(self.numeric("353", &format!("= #{} :{}", room, names.join(" "))));
+        }
+        replies.push(self.numeric("366", &format!("#{} :End of /NAMES list", room)));
+        replies
+    }
+
+    async fn who(&self, mask: Option<&str>) -> Vec<String> {
+        let (members, channel) = match mask.filter(|mask| !mask.starts_with('#')) {
+            // 按昵称查询
+            Some(nick) => {
+                let members = self.state.members(None).await;
+                (members.into_iter().filter(|member| member.client_id == nick).collect(), "*".to_string())
+            }
+            None => match self.target_room(mask) {
+                Some(room) => (self.state.members(Some(&room)).await, format!("#{}", room)),
+                None => (Vec::new(), "*".to_string()),
+            },
+        };
+        let mut replies: Vec<String> = members
+            .iter()
+            .map(|member| {
+                let server = member.server.as_deref().unwrap_or(self.server());
+                let here = if member.away { "G" } else { "H" };
+                self.numeric("352", &format!(
+                    "{} {} {} {} {} {} :0 {}",
+                    channel, member.client_id, HOST, server, member.client_id, here, member.client_id
+                ))
+            })
+            .collect();
+        replies.push(self.numeric("315", &format!("{} :End of /WHO list", mask.unwrap_or("*"))));
+        replies
+    }
+
+    /// 把服务器发来的消息转换成 IRC 命令
+    fn translate(&self, message: &Message) -> Vec<String> {
+        let nick = self.nick_or_star();
+        let from = prefix(&message.sender_id);
+        let room = &message.room;
+        match &message.message_type {
+            // 服务器自己发出的提示，如限流
+            MessageType::Text { content } if message.sender_id.is_empty() || message.sender_id == self.server() => {
+                lines(content).map(|line| format!(":{} NOTICE {} :{}", self.server(), nick, line)).collect()
+            }
+            MessageType::Text { content } => {
+                lines(content).map(|line| format!(":{} PRIVMSG #{} :{}", from, room, line)).collect()
+            }
+            MessageType::Direct { content, .. } if message.sender_id != nick => {
+                lines(content).map(|line| format!(":{} PRIVMSG {} :{}", from, nick, line)).collect()
+            }
+            MessageType::Presence { event: PresenceEvent::Join, .. } => vec![format!(":{} JOIN #{}", from, room)],
+            MessageType::Presence { event: PresenceEvent::Leave, .. } => vec![format!(":{} PART #{}", from, room)],
+            MessageType::Edit { content, .. } => {
+                lines(content).map(|line| format!(":{} NOTICE #{} :(edited) {}", from, room, line)).collect()
+            }
+            MessageType::Delete { .. } => vec![format!(":{} NOTICE #{} :(deleted a message)", from, room)],
+            MessageType::Reaction { emoji, .. } => vec![format!(":{} NOTICE #{} :(reacted {})", from, room, emoji)],
+            _ => Vec::new(),
+        }
+    }
+
+    /// 交给服务器核心处理，与 QUIC 客户端发来的消息相同
+    async fn submit(&self, message: Message) {
+        let span = info_span!("handle_message", message_id = %message.id, kind = message.kind());
+        Server::handle_message(&self.connection, self.state, &self.peer_addr, message)
+            .instrument(span)
+            .await;
+    }
+
+    /// 参数中的频道，省略时为当前频道
+    fn target_room(&self, channel: Option<&str>) -> Option<String> {
+        match channel {
+            Some(channel) => channel.strip_prefix('#').filter(|room| !room.is_empty()).map(str::to_string),
+            None => self.channel.clone(),
+        }
+    }
+
+    fn server(&self) -> &str {
+        &self.state.server_id
+    }
+
+    fn nick_or_star(&self) -> &str {
+        self.nick.as_deref().unwrap_or("*")
+    }
+
+    fn numeric(&self, code: &str, params: &str) -> String {
+        format!(":{} {} {} {}", self.server(), code, self.nick_or_star(), params)
+    }
+}
+
+/// 本地登录的ID已经校验过，其他服务器转发来的ID中不能出现在前缀里的字符替换为 `_`
+fn prefix(nick: &str) -> String {
+    let nick: String = nick
+        .chars()
+        .map(|c| if c.is_whitespace() || c.is_control() || matches!(c, '!' | '@' | ':') { '_' } else { c })
+        .collect();
+    format!("{}!{}@{}", nick, nick, HOST)
+}
+
+/// 多行消息拆成多条 IRC 消息，\r、\n 都作为换行，NUL 去掉，避免注入额外的 IRC 命令
+fn lines(content: &str) -> impl Iterator<Item = String> + '_ {
+    content
+        .split(['\r', '\n'])
+        .map(|line| line.replace('\0', ""))
+        .filter(|line| !line.is_empty())
+}
+
+/// 解析一行 IRC 消息，返回大写的命令和参数；忽略标签和来源前缀
+fn parse(line: &str) -> Option<(String, Vec<&str>)> {
+    let mut rest = line.trim_start();
+    if rest.starts_with('@') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    if rest.starts_with(':') {
+        rest = rest.split_once(' ')?.1.trim_start();
+    }
+    let (command, mut rest) = rest.split_once(' ').unwrap_or((rest, ""));
+    if command.is_empty() {
+        return None;
+    }
+    let mut params = Vec::new();
+    loop {
+        rest = rest.trim_start_matches(' ');
+        if rest.is_empty() {
+            break;
+        }
+        if let Some(trailing) = rest.strip_prefix(':') {
+            params.push(trailing);
+            break;
+        }
+        let (param, remaining) = rest.split_once(' ').unwrap_or((rest, ""));
+        params.push(param);
+        rest = remaining;
+    }
+    Some((command.to_ascii_uppercase(), params))
+}
+
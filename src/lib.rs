@@ -0,0 +1,51 @@
+//! t3xt：基于 QUIC 的文本聊天，支持房间、联邦和消息编辑。
+//!
+//! 客户端连接后得到发送用的 [`client::Client`] 句柄和接收消息的流：
+//!
+//! ```no_run
+//! use t3xt::{client::Client, config::Config};
+//! use tokio_stream::StreamExt;
+//!
+//! # async fn demo() -> anyhow::Result<()> {
+//! let config = Config::load(None)?;
+//! let (client, mut incoming) = Client::connect(&config).await?;
+//! client.send_text("hello").await?;
+//! while let Some(message) = incoming.next().await {
+//!     println!("{}", message.format_display_for(Some(client.client_id())));
+//! }
+//! client.disconnect().await;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! 服务器通过 [`server::Server::builder`] 创建，可以注册消息和上下线回调，
+//! 以及在处理消息前过滤或修改消息的 [`hooks::MessageHook`]。
+//! 机器人可以用 [`bot::Bot`] 注册斜杠命令和正则处理函数。
+//! 终端输出默认关闭，见 [`console::set_enabled`]。
+
+pub mod bot;
+pub mod client;
+pub mod config;
+pub mod console;
+pub mod discovery;
+pub mod hooks;
+pub mod interactive;
+pub mod logging;
+pub mod message;
+pub mod server;
+pub mod webhooks;
+
+mod api;
+mod crypto;
+mod federation;
+mod gateway;
+mod history;
+mod http3;
+mod irc;
+mod metrics;
+mod p2p;
+mod reload;
+mod routing;
+mod tcp;
+mod telemetry;
+mod webtransport;
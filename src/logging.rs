@@ -0,0 +1,81 @@
+use crate::{
+    config::{LogFormat, LogSettings, TelemetrySettings},
+    telemetry,
+};
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider;
+use opentelemetry_sdk::trace::SdkTracerProvider;
+use tracing_appender::{
+    non_blocking::WorkerGuard,
+    rolling::{RollingFileAppender, Rotation},
+};
+use tracing_subscriber::{
+    fmt::writer::BoxMakeWriter, layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer,
+};
+
+/// 日志文件名前缀，滚动后为 t3xt.log.YYYY-MM-DD
+const LOG_FILE_PREFIX: &str = "t3xt.log";
+
+/// 需要保持到进程退出：释放时写完缓冲的日志并导出剩余的 span
+pub struct LogGuard {
+    _file: Option<WorkerGuard>,
+    tracer_provider: Option<SdkTracerProvider>,
+}
+
+impl Drop for LogGuard {
+    fn drop(&mut self) {
+        if let Some(provider) = self.tracer_provider.take() {
+            if let Err(e) = provider.shutdown() {
+                crate::console::error(format_args!("导出 trace 失败: {}", e));
+            }
+        }
+    }
+}
+
+/// 初始化全局日志，启用 telemetry 时同时把 span 导出到 OTLP collector
+pub fn init(settings: &LogSettings, telemetry_settings: &TelemetrySettings, instance_id: &str) -> Result<LogGuard> {
+    let filter = EnvFilter::try_new(&settings.level).context("Invalid log level")?;
+
+    let (writer, file_guard, ansi) = if settings.directory.as_os_str().is_empty() {
+        (BoxMakeWriter::new(std::io::stderr), None, true)
+    } else {
+        let mut builder = RollingFileAppender::builder()
+            .rotation(Rotation::DAILY)
+            .filename_prefix(LOG_FILE_PREFIX);
+        if settings.retention_days > 0 {
+            builder = builder.max_log_files(settings.retention_days);
+        }
+        let appender = builder
+            .build(&settings.directory)
+            .with_context(|| format!("Failed to open log directory {}", settings.directory.display()))?;
+        let (non_blocking, guard) = tracing_appender::non_blocking(appender);
+        (BoxMakeWriter::new(non_blocking), Some(guard), false)
+    };
+
+    let fmt_layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(ansi);
+    let fmt_layer = match settings.format {
+        LogFormat::Text => fmt_layer.boxed(),
+        // 每行一个 JSON 对象，带上当前所在的连接 span
+        LogFormat::Json => fmt_layer.json().with_current_span(true).with_span_list(true).boxed(),
+    };
+
+    let tracer_provider = if telemetry_settings.enabled {
+        Some(telemetry::tracer_provider(telemetry_settings, instance_id)?)
+    } else {
+        None
+    };
+    let otel_layer = tracer_provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("t3xt")));
+
+    tracing_subscriber::registry()
+        .with(fmt_layer)
+        .with(otel_layer)
+        .with(filter)
+        .init();
+
+    Ok(LogGuard {
+        _file: file_guard,
+        tracer_provider,
+    })
+}
@@ -1,93 +1,390 @@
 use anyhow::Result;
-use clap::{Parser, Subcommand};
-use tracing_subscriber::{FmtSubscriber};
-
-mod client;
-mod crypto;
-mod message;
-mod server;
+use clap::{Args, Parser, Subcommand};
+use std::{
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
+    sync::Arc,
+};
+use t3xt::{client, config, console, discovery, interactive, logging, server};
+use tokio::io::{AsyncBufReadExt, BufReader};
+use tokio_stream::{wrappers::LinesStream, Stream, StreamExt};
+use tracing::error;
 
 #[derive(Parser)]
 #[command(author, version, about)]
 struct Cli {
+    /// 配置文件路径，默认加载当前目录下的 t3xt.toml（如果存在）
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+
+    /// 使用配置文件中的 [profiles.<名称>]，覆盖在其他设置之上；也可用 T3XT_PROFILE 指定
+    #[arg(long, global = true)]
+    profile: Option<String>,
+
+    #[command(flatten)]
+    log: LogArgs,
+
     #[command(subcommand)]
     command: Commands,
 }
 
+#[derive(Args, Clone)]
+struct LogArgs {
+    /// 日志级别，如 info、debug 或 "info,t3xt=debug"
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// 日志格式
+    #[arg(long, global = true, value_enum)]
+    log_format: Option<config::LogFormat>,
+
+    /// 日志目录，按天滚动；传入 - 则输出到标准错误
+    #[arg(long, global = true)]
+    log_dir: Option<PathBuf>,
+}
+
+impl LogArgs {
+    fn apply(&self, config: &mut config::Config) {
+        if let Some(level) = &self.log_level {
+            config.log.level = level.clone();
+        }
+        if let Some(format) = self.log_format {
+            config.log.format = format;
+        }
+        if let Some(directory) = &self.log_dir {
+            config.log.directory = match directory.to_str() {
+                Some("-") => PathBuf::new(),
+                _ => directory.clone(),
+            };
+        }
+    }
+}
+
+/// 命令行参数只在显式指定时覆盖配置文件和环境变量
 #[derive(Subcommand)]
 enum Commands {
     /// 启动服务器模式
-    Serve {
-        /// 服务器ID
-        #[arg(short, long, default_value = "Server")]
-        id: String,
-        
-        /// 监听端口
-        #[arg(short, long, default_value = "10005")]
-        port: u16,
-    },
+    Serve(Box<ServeArgs>),
     /// 启动客户端模式（连接到服务器）
     Run {
-        /// 目标服务器地址
-        #[arg(short, long, default_value = "127.0.0.1")]
-        target: String,
+        /// 目标服务器地址。配置中也为空时在局域网中发现服务器
+        #[arg(short, long)]
+        target: Option<String>,
+
+        /// 在局域网中发现服务器并选择要连接的一个
+        #[arg(short, long, conflicts_with = "target")]
+        discover: bool,
+
+        /// 服务器证书的 SHA-256 指纹，设置后按指纹固定证书
+        #[arg(long)]
+        fingerprint: Option<String>,
         
         /// 目标服务器端口
-        #[arg(short, long, default_value = "10005")]
-        port: u16,
+        #[arg(short, long)]
+        port: Option<u16>,
         
         /// 客户端ID
-        #[arg(short, long, default_value = "Client")]
-        id: String,
+        #[arg(short, long)]
+        id: Option<String>,
+
+        /// 加入的房间
+        #[arg(short, long)]
+        room: Option<String>,
+
+        /// 传输方式，auto 先尝试 QUIC，UDP 不通时改用 TCP
+        #[arg(long, value_enum)]
+        transport: Option<config::ClientTransport>,
+    },
+    /// 列出局域网中宣告自己的服务器
+    Discover,
+    /// 配置文件相关命令
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
     },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+#[derive(Args, Clone)]
+struct ServeArgs {
+    /// 服务器ID
+    #[arg(short, long)]
+    id: Option<String>,
 
-    /*
-    let file_appender = tracing_appender::rolling::daily("./logs", "t3xt.log");
-    let (non_blocking, _guard) = tracing_appender::non_blocking(file_appender);
+    /// 监听地址
+    #[arg(short, long)]
+    bind: Option<IpAddr>,
 
-    let subscriber = FmtSubscriber::builder()
-        .with_writer(non_blocking) // 日志写到文件
-        .with_max_level(tracing::Level::INFO)
-        .finish();
+    /// 监听端口
+    #[arg(short, long)]
+    port: Option<u16>,
 
-    tracing::subscriber::set_global_default(subscriber)
-        .expect("setting default subscriber failed");
-    */
-    
+    /// 管理员客户端ID，可以编辑或删除任何人的消息（可重复）
+    #[arg(short, long = "moderator")]
+    moderators: Vec<String>,
+
+    /// 要连接的联邦对端服务器地址，如 10.0.0.2:10005（可重复）
+    #[arg(long = "peer")]
+    peers: Vec<SocketAddr>,
+
+    /// 信任的对端服务器证书，默认只信任本服务器证书（可重复）
+    #[arg(long = "peer-cert")]
+    peer_certs: Vec<PathBuf>,
+
+    /// 在该地址提供 Prometheus 指标，如 127.0.0.1:9105
+    #[arg(long)]
+    metrics: Option<SocketAddr>,
+
+    /// 在该地址提供 HTTP API，如 127.0.0.1:9106，令牌在配置文件的 [api] 中设置
+    #[arg(long)]
+    api: Option<SocketAddr>,
+
+    /// 在该地址提供 WebSocket 网关和演示页面，如 127.0.0.1:9107
+    #[arg(long)]
+    gateway: Option<SocketAddr>,
+
+    /// 在该 UDP 地址提供 HTTP/3 WebTransport，如 127.0.0.1:4433
+    #[arg(long)]
+    webtransport: Option<SocketAddr>,
+
+    /// 在该地址提供 IRC 协议桥，如 127.0.0.1:6667
+    #[arg(long)]
+    irc: Option<SocketAddr>,
+
+    /// 不在同一端口监听 TCP 备用传输
+    #[arg(long)]
+    no_tcp: bool,
+}
+
+impl ServeArgs {
+    /// 用命令行参数覆盖配置，重新加载配置时也会再次应用
+    fn apply(&self, config: &mut config::Config) {
+        let settings = &mut config.server;
+        if let Some(id) = &self.id {
+            settings.id = id.clone();
+        }
+        if let Some(bind) = self.bind {
+            settings.bind = bind;
+        }
+        if let Some(port) = self.port {
+            settings.port = port;
+        }
+        override_list(&mut settings.moderators, self.moderators.clone());
+        override_list(&mut settings.peers, self.peers.clone());
+        override_list(&mut settings.peer_certs, self.peer_certs.clone());
+        if self.no_tcp {
+            settings.tcp = false;
+        }
+        if let Some(listen) = self.metrics {
+            config.metrics.enabled = true;
+            config.metrics.listen = listen;
+        }
+        if let Some(listen) = self.api {
+            config.api.enabled = true;
+            config.api.listen = listen;
+        }
+        if let Some(listen) = self.gateway {
+            config.gateway.enabled = true;
+            config.gateway.listen = listen;
+        }
+        if let Some(listen) = self.webtransport {
+            config.webtransport.enabled = true;
+            config.webtransport.listen = listen;
+        }
+        if let Some(listen) = self.irc {
+            config.irc.enabled = true;
+            config.irc.listen = listen;
+        }
+    }
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// 校验配置文件并打印生效的配置（已叠加环境变量）
+    Check {
+        /// 要检查的配置文件，默认使用 --config 或 t3xt.toml
+        file: Option<PathBuf>,
+    },
+}
+
+/// 标准输入的行，读取出错视为输入结束
+fn stdin_lines() -> impl Stream<Item = String> + Unpin {
+    LinesStream::new(BufReader::new(tokio::io::stdin()).lines()).map_while(Result::ok)
+}
+
+async fn discover(config: &config::Config) -> Result<Vec<discovery::DiscoveredServer>> {
+    console::line("正在局域网中查找服务器...");
+    discovery::discover(std::time::Duration::from_millis(config.discovery.timeout_ms)).await
+}
+
+fn print_servers(servers: &[discovery::DiscoveredServer]) {
+    for (index, server) in servers.iter().enumerate() {
+        console::line(format_args!(
+            "  {}. {:<16} {:<22} sha256:{}",
+            index + 1, server.id, server.addr, server.fingerprint
+        ));
+    }
+}
+
+/// 列出发现的服务器并读取用户的选择，只有一个时直接使用
+async fn choose_server(
+    config: &config::Config,
+    input: &mut (impl Stream<Item = String> + Unpin),
+) -> Result<Option<discovery::DiscoveredServer>> {
+    let mut servers = discover(config).await?;
+    print_servers(&servers);
+    if servers.len() <= 1 {
+        return Ok(servers.pop());
+    }
+    loop {
+        console::line(format_args!("选择要连接的服务器 [1-{}]:", servers.len()));
+        let Some(line) = input.next().await else {
+            return Ok(None);
+        };
+        match line.trim().parse::<usize>() {
+            Ok(choice) if (1..=servers.len()).contains(&choice) => return Ok(Some(servers.swap_remove(choice - 1))),
+            _ => console::error(format_args!("请输入 1 到 {} 之间的数字", servers.len())),
+        }
+    }
+}
+
+/// 用非空的命令行列表参数覆盖配置
+fn override_list<T>(target: &mut Vec<T>, values: Vec<T>) {
+    if !values.is_empty() {
+        *target = values;
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
     let cli = Cli::parse();
+    console::set_enabled(true);
+
+    if let Commands::Config { command: ConfigCommand::Check { file } } = &cli.command {
+        let path = file.as_deref().or(cli.config.as_deref());
+        match config::Config::load_with_profile(path, cli.profile.as_deref()) {
+            Ok(config) => {
+                console::line("# 配置有效，生效的配置如下");
+                console::line(config.to_toml()?.trim_end());
+            }
+            Err(e) => {
+                console::error(format_args!("配置无效: {:#}", e));
+                std::process::exit(1);
+            }
+        }
+        return Ok(());
+    }
+
+    let mut config = config::Config::load_with_profile(cli.config.as_deref(), cli.profile.as_deref())?;
+    cli.log.apply(&mut config);
+    config.validate()?;
+    let instance_id = match &cli.command {
+        Commands::Serve(args) => args.id.as_ref().unwrap_or(&config.server.id),
+        Commands::Run { id, .. } => id.as_ref().unwrap_or(&config.client.id),
+        Commands::Discover => &config.client.id,
+        Commands::Config { .. } => unreachable!("handled above"),
+    };
+    let _log_guard = logging::init(&config.log, &config.telemetry, instance_id)?;
     
     match cli.command {
-        Commands::Serve { id, port } => {
-            println!("server started [{}] 监听端口: {}", id, port);
-            
-            let server = server::Server::new(id, port)?;
-            
-            if let Err(e) = server.run().await {
-                eprintln!("服务器错误: {}", e);
+        Commands::Serve(args) => {
+            args.apply(&mut config);
+            config.validate()?;
+
+            console::line(format_args!("server started [{}] 监听端口: {}", config.server.id, config.server.port));
+
+            // 未指定配置文件时监视默认文件，之后创建也会被加载
+            let config_path = config::Config::resolve_path(cli.config.as_deref())
+                .unwrap_or_else(|| PathBuf::from(config::DEFAULT_CONFIG_FILE));
+            let config_file = cli.config;
+            let profile = cli.profile;
+            let log_args = cli.log;
+            let loader: server::ConfigLoader = Arc::new(move || {
+                let mut config = config::Config::load_with_profile(config_file.as_deref(), profile.as_deref())?;
+                log_args.apply(&mut config);
+                args.apply(&mut config);
+                config.validate()?;
+                Ok(config)
+            });
+
+            let server = server::Server::builder(config)
+                .with_reload(loader, config_path)
+                .build()?;
+
+            // /quit 时正常返回，让日志缓冲在退出前写完
+            let result = tokio::select! {
+                result = server.run() => result,
+                _ = server.console(stdin_lines()) => Ok(()),
+            };
+            if let Err(e) = result {
+                error!("服务器错误: {:#}", e);
+                console::error(format_args!("服务器错误: {}", e));
                 std::process::exit(1);
             }
         }
-        Commands::Run { target, port, id } => {
-            println!("启动T3XT客户端 [{}] 连接到: {}:{}", id, target, port);
-            
-            let mut client = client::Client::new(id)?;
-            
-            if let Err(e) = client.connect(&target, port).await {
-                eprintln!("连接失败: {}", e);
-                std::process::exit(1);
+        Commands::Discover => {
+            let servers = discover(&config).await?;
+            if servers.is_empty() {
+                console::line("局域网中没有发现服务器");
             }
-            
-            if let Err(e) = client.run_interactive().await {
-                eprintln!("客户端错误: {}", e);
+            print_servers(&servers);
+        }
+        Commands::Run { target, discover: discover_flag, fingerprint, port, id, room, transport } => {
+            let mut input = stdin_lines();
+            let settings = &mut config.client;
+            if let Some(target) = target {
+                settings.target = target;
+            }
+            if let Some(fingerprint) = fingerprint {
+                settings.fingerprint = fingerprint;
+            }
+            if let Some(port) = port {
+                settings.port = port;
+            }
+            if let Some(id) = id {
+                settings.id = id;
+            }
+            if let Some(room) = room {
+                settings.room = room;
+            }
+            if let Some(transport) = transport {
+                settings.transport = transport;
             }
+            if discover_flag || config.client.target.is_empty() {
+                let Some(server) = choose_server(&config, &mut input).await? else {
+                    console::error("局域网中没有发现服务器，请用 --target 指定");
+                    std::process::exit(1);
+                };
+                let settings = &mut config.client;
+                settings.target = server.addr.ip().to_string();
+                settings.port = server.addr.port();
+                // 显式指定的指纹优先，不一致时握手失败
+                if settings.fingerprint.is_empty() {
+                    settings.fingerprint = server.fingerprint;
+                }
+            }
+            config.validate()?;
+
+            let settings = &config.client;
+            console::line(format_args!("启动T3XT客户端 [{}] 连接到: {}:{}", settings.id, settings.target, settings.port));
             
-            let _ = client.disconnect().await;
+            let (client, incoming) = match client::Client::connect(&config).await {
+                Ok(connected) => connected,
+                Err(e) => {
+                    error!("连接失败: {:#}", e);
+                    console::error(format_args!("连接失败: {}", e));
+                    std::process::exit(1);
+                }
+            };
+
+            if let Err(e) = interactive::run(&client, incoming, input).await {
+                error!("客户端错误: {:#}", e);
+                console::error(format_args!("客户端错误: {}", e));
+            }
+
+            client.disconnect().await;
         }
+        Commands::Config { .. } => unreachable!("handled above"),
     }
     
     Ok(())
-}
\ No newline at end of file
+}
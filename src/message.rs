@@ -1,27 +1,178 @@
 use serde::{Deserialize, Serialize};
 use chrono::{DateTime, Utc};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    net::SocketAddr,
+};
+use uuid::Uuid;
+
+/// 在线状态事件
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PresenceEvent {
+    Join,
+    Leave,
+    Away,
+    Back,
+}
+
+/// `/who` 返回的成员信息
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemberInfo {
+    pub client_id: String,
+    pub away: bool,
+    pub status: Option<String>,
+    pub idle_secs: u64,
+    /// 是否为机器人
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bot: bool,
+    /// 通过联邦连接在其他服务器上的成员所在服务器
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server: Option<String>,
+}
+
+/// 联邦路由通告中的一条路由
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Route {
+    pub client_id: String,
+    pub room: String,
+    /// 从通告方到客户端所在服务器的路径，第一项是通告方，最后一项是客户端所在服务器
+    pub path: Vec<String>,
+}
 
 /// 极简消息类型 - 只保留核心功能
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum MessageType {
     Text { content: String },
+    /// 在线状态变化，由服务器生成并广播
+    Presence { event: PresenceEvent, status: Option<String> },
+    /// 请求当前在线成员列表
+    WhoRequest,
+    WhoResponse { members: Vec<MemberInfo> },
+    /// 修改之前发送的文本消息
+    Edit { target_id: String, content: String },
+    /// 删除之前发送的文本消息
+    Delete { target_id: String },
+    /// 已删除消息在历史记录中的墓碑
+    Tombstone,
+    /// 对某条消息的表情回应，由服务器按消息汇总
+    Reaction { target_id: String, emoji: String },
+    /// 请求离线期间错过的 @提及
+    MentionsRequest,
+    MentionsResponse { messages: Vec<Message> },
+    /// 联邦链路建立后双方首先交换的服务器身份
+    ServerHello { server_id: String },
+    /// 联邦对端可达的客户端完整列表
+    RouteUpdate { routes: Vec<Route> },
+    /// 发给单个客户端的私信，不进入房间历史
+    Direct { recipient: String, content: String },
+    /// 直连会合：客户端发给服务器时 `addr` 为空，服务器填上发送者的观测地址后转给 `peer`。
+    /// 服务器回复的 `addr` 为空表示对方无法直连；`fingerprint` 为对方临时证书的指纹
+    PeerAddress { peer: String, addr: Option<SocketAddr>, fingerprint: String },
+}
+
+/// 未指定房间时使用的默认房间
+pub const DEFAULT_ROOM: &str = "lobby";
+
+fn default_room() -> String {
+    DEFAULT_ROOM.to_string()
+}
+
+/// 短暂信号类型，丢失无妨
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignalKind {
+    /// 正在输入
+    Typing,
+    /// 在线心跳：客户端有操作时发出，服务器据此刷新空闲时间，不转发
+    Ping,
+}
+
+/// 通过 QUIC 不可靠数据报传输的短暂事件，不经过可靠流
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Signal {
+    pub sender_id: String,
+    pub kind: SignalKind,
+}
+
+impl Signal {
+    pub fn new(sender_id: String, kind: SignalKind) -> Self {
+        Self { sender_id, kind }
+    }
+
+    pub fn to_bytes(&self) -> anyhow::Result<Vec<u8>> {
+        Ok(serde_json::to_vec(self)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> anyhow::Result<Self> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
 }
 
 /// 服务器间消息结构
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
+    pub id: String,
     pub timestamp: DateTime<Utc>,
     pub sender_id: String,
+    /// 消息所属房间
+    #[serde(default = "default_room")]
+    pub room: String,
     pub message_type: MessageType,
+    /// 最近一次编辑的时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// 所回复消息的ID，用于组织讨论串
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<String>,
+    /// 表情回应汇总：表情 -> 回应者ID
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub reactions: BTreeMap<String, BTreeSet<String>>,
+    /// 最先接收该消息的服务器
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin: Option<String>,
+    /// 已经转发过该消息的服务器，用于防止联邦环路
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub via: Vec<String>,
+    /// 发送者是否为机器人，由服务器按登录时的声明填写
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub bot: bool,
+    /// W3C trace context（traceparent 等），每一跳发送前写入当前 span
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub trace_context: HashMap<String, String>,
 }
 
 impl Message {
     /// 创建新文本消息
     pub fn new_text(sender_id: String, content: String) -> Self {
+        Self::new(sender_id, MessageType::Text { content })
+    }
+
+    /// 创建回复某条消息的文本消息
+    pub fn new_reply(sender_id: String, content: String, reply_to: String) -> Self {
         Self {
+            reply_to: Some(reply_to),
+            ..Self::new_text(sender_id, content)
+        }
+    }
+
+    /// 创建在线状态消息，sender_id 为状态变化的客户端
+    pub fn new_presence(sender_id: String, event: PresenceEvent, status: Option<String>) -> Self {
+        Self::new(sender_id, MessageType::Presence { event, status })
+    }
+
+    pub fn new(sender_id: String, message_type: MessageType) -> Self {
+        Self {
+            id: Uuid::new_v4().to_string(),
             timestamp: Utc::now(),
             sender_id,
-            message_type: MessageType::Text { content },
+            room: default_room(),
+            message_type,
+            edited_at: None,
+            reply_to: None,
+            reactions: BTreeMap::new(),
+            origin: None,
+            via: Vec::new(),
+            bot: false,
+            trace_context: HashMap::new(),
         }
     }
 
@@ -38,16 +189,231 @@ impl Message {
         Ok(message)
     }
 
-    /// 格式化显示消息
-    pub fn format_display(&self) -> String {
+    /// 消息类型的名称，用作指标标签
+    pub fn kind(&self) -> &'static str {
+        match self.message_type {
+            MessageType::Text { .. } => "text",
+            MessageType::Presence { .. } => "presence",
+            MessageType::WhoRequest => "who_request",
+            MessageType::WhoResponse { .. } => "who_response",
+            MessageType::Edit { .. } => "edit",
+            MessageType::Delete { .. } => "delete",
+            MessageType::Tombstone => "tombstone",
+            MessageType::Reaction { .. } => "reaction",
+            MessageType::MentionsRequest => "mentions_request",
+            MessageType::MentionsResponse { .. } => "mentions_response",
+            MessageType::ServerHello { .. } => "server_hello",
+            MessageType::RouteUpdate { .. } => "route_update",
+            MessageType::Direct { .. } => "direct",
+            MessageType::PeerAddress { .. } => "peer_address",
+        }
+    }
+
+    /// 是否为需要在联邦服务器之间转发的房间消息或私信
+    pub fn is_room_traffic(&self) -> bool {
+        matches!(
+            self.message_type,
+            MessageType::Text { .. }
+                | MessageType::Presence { .. }
+                | MessageType::Edit { .. }
+                | MessageType::Delete { .. }
+                | MessageType::Reaction { .. }
+                | MessageType::Direct { .. }
+        )
+    }
+
+    /// 是否为只能由服务器发出的消息，客户端发来时直接丢弃
+    pub fn is_server_only(&self) -> bool {
+        matches!(
+            self.message_type,
+            MessageType::WhoResponse { .. }
+                | MessageType::MentionsResponse { .. }
+                | MessageType::ServerHello { .. }
+                | MessageType::RouteUpdate { .. }
+                | MessageType::Tombstone
+        )
+    }
+
+    /// 文本内容中 @提及 的客户端ID，去重并保持出现顺序
+    pub fn mentions(&self) -> Vec<String> {
+        match &self.message_type {
+            MessageType::Text { content } => parse_mentions(content),
+            _ => Vec::new(),
+        }
+    }
+
+    /// 是否提及了某个客户端
+    pub fn mentions_user(&self, client_id: &str) -> bool {
+        self.mentions().iter().any(|id| id == client_id)
+    }
+
+    /// 格式化显示消息，并高亮对本地用户的 @提及
+    pub fn format_display_for(&self, local_id: Option<&str>) -> String {
+        let time = self.timestamp.format("%H:%M:%S");
         match &self.message_type {
             MessageType::Text { content } => {
-                format!("[{}] {}: {}", 
-                    self.timestamp.format("%H:%M:%S"),
-                    self.sender_id,
-                    content
+                let edited = if self.edited_at.is_some() { " (edited)" } else { "" };
+                format!("[{}] #{} {}{}: {}{}{}", 
+                    time,
+                    short_id(&self.id),
+                    self.format_sender(),
+                    self.format_reply_to(),
+                    highlight_mentions(content, local_id),
+                    edited,
+                    self.format_reactions()
                 )
             }
+            MessageType::Edit { target_id, content } => {
+                format!("[{}] {} edited #{}: {}", time, self.sender_id, short_id(target_id), highlight_mentions(content, local_id))
+            }
+            MessageType::Delete { target_id } => {
+                format!("[{}] * {} deleted #{}", time, self.sender_id, short_id(target_id))
+            }
+            MessageType::Tombstone => format!("[{}] #{} {}{}: (message deleted)",
+                time,
+                short_id(&self.id),
+                self.sender_id,
+                self.format_reply_to()
+            ),
+            MessageType::Reaction { target_id, emoji } => {
+                format!("[{}] * {} reacted {} to #{}", time, self.sender_id, emoji, short_id(target_id))
+            }
+            MessageType::Presence { event, status } => {
+                let action = match event {
+                    PresenceEvent::Join => format!("joined #{}", self.room),
+                    PresenceEvent::Leave => format!("left #{}", self.room),
+                    PresenceEvent::Away => "is away".to_string(),
+                    PresenceEvent::Back => "is back".to_string(),
+                };
+                match status {
+                    Some(status) => format!("[{}] * {} {} ({})", time, self.format_sender(), action, status),
+                    None => format!("[{}] * {} {}", time, self.format_sender(), action),
+                }
+            }
+            MessageType::WhoRequest => format!("[{}] {} requested /who", time, self.sender_id),
+            MessageType::WhoResponse { members } => {
+                let mut out = format!("[{}] 在线成员 ({}):", time, members.len());
+                for member in members {
+                    match &member.server {
+                        Some(server) => out.push_str(&format!("\n  {:<16} @{}", member.client_id, server)),
+                        None => out.push_str(&format!("\n  {:<16} idle {:>5}s", member.client_id, member.idle_secs)),
+                    }
+                    if member.bot {
+                        out.push_str("  [bot]");
+                    }
+                    if member.away {
+                        out.push_str("  [away]");
+                    }
+                    if let Some(status) = &member.status {
+                        out.push_str(&format!("  {}", status));
+                    }
+                }
+                out
+            }
+            MessageType::ServerHello { server_id } => format!("[{}] 联邦服务器 {}", time, server_id),
+            MessageType::RouteUpdate { routes } => format!("[{}] {} 通告 {} 条路由", time, self.sender_id, routes.len()),
+            MessageType::Direct { recipient, content } => format!("[{}] #{} {} → {}: {}",
+                time,
+                short_id(&self.id),
+                self.format_sender(),
+                recipient,
+                highlight_mentions(content, local_id)
+            ),
+            MessageType::PeerAddress { peer, addr, .. } => match addr {
+                Some(addr) => format!("[{}] {} 的直连地址 {}", time, peer, addr),
+                None => format!("[{}] {} 无法直连", time, peer),
+            },
+            MessageType::MentionsRequest => format!("[{}] {} requested /mentions", time, self.sender_id),
+            MessageType::MentionsResponse { messages } => {
+                if messages.is_empty() {
+                    return format!("[{}] 没有错过的提及", time);
+                }
+                let mut out = format!("[{}] 离线期间的提及 ({}):", time, messages.len());
+                for message in messages {
+                    out.push_str("\n  ");
+                    out.push_str(&message.format_display_for(local_id));
+                }
+                out
+            }
+        }
+    }
+}
+
+impl Message {
+    fn format_sender(&self) -> String {
+        if self.bot {
+            format!("{} [bot]", self.sender_id)
+        } else {
+            self.sender_id.clone()
         }
     }
-}
\ No newline at end of file
+
+    fn format_reply_to(&self) -> String {
+        match &self.reply_to {
+            Some(parent) => format!(" ↳#{}", short_id(parent)),
+            None => String::new(),
+        }
+    }
+
+    fn format_reactions(&self) -> String {
+        if self.reactions.is_empty() {
+            return String::new();
+        }
+        let counts: Vec<String> = self.reactions
+            .iter()
+            .map(|(emoji, senders)| format!("{} {}", emoji, senders.len()))
+            .collect();
+        format!("  [{}]", counts.join(", "))
+    }
+}
+
+/// 提及中允许出现在客户端ID里的字符
+fn is_mention_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '_' | '-' | '.')
+}
+
+/// 从文本中解析 `@client_id` 形式的提及
+pub fn parse_mentions(content: &str) -> Vec<String> {
+    let mut mentions: Vec<String> = Vec::new();
+    for token in content.split_whitespace() {
+        let Some(rest) = token.strip_prefix('@') else {
+            continue;
+        };
+        // 去掉句末的标点，例如 "@alice," 或 "@bob."
+        let id: String = rest.chars().take_while(|c| is_mention_char(*c)).collect();
+        let id = id.trim_end_matches('.');
+        if !id.is_empty() && !mentions.iter().any(|m| m == id) {
+            mentions.push(id.to_string());
+        }
+    }
+    mentions
+}
+
+/// 用反色高亮对本地用户的提及
+fn highlight_mentions(content: &str, local_id: Option<&str>) -> String {
+    let Some(local_id) = local_id else {
+        return content.to_string();
+    };
+    let mut out = String::with_capacity(content.len());
+    for token in content.split_inclusive(char::is_whitespace) {
+        let word = token.trim_end();
+        // 与 parse_mentions 规则一致，"@alice" 不会匹配 "@alice2"
+        if word.starts_with('@') && parse_mentions(word).first().map(String::as_str) == Some(local_id) {
+            let len = 1 + local_id.len();
+            out.push_str(&format!("\x1b[1;7m{}\x1b[0m{}", &token[..len], &token[len..]));
+        } else {
+            out.push_str(token);
+        }
+    }
+    out
+}
+
+/// 客户端ID不能为空，不能含空白、控制字符或 IRC 前缀中的分隔符 `!`、`@`、`:`
+pub fn is_valid_id(id: &str) -> bool {
+    !id.is_empty() && !id.contains(|c: char| c.is_whitespace() || c.is_control() || matches!(c, '!' | '@' | ':'))
+}
+
+/// 取消息ID的前8位
+pub fn short_id(id: &str) -> &str {
+    id.get(..8).unwrap_or(id)
+}
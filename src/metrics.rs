@@ -0,0 +1,175 @@
+use crate::server::ServerState;
+use anyhow::{Context, Result};
+use axum::{extract::State, http::header, response::IntoResponse, routing::get, Router};
+use prometheus::{
+    Encoder, GaugeVec, Histogram, HistogramOpts, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, Opts,
+    Registry, TextEncoder,
+};
+use quinn::Connection;
+use std::{net::SocketAddr, sync::{Arc, LazyLock}};
+use tracing::{info, warn};
+
+/// 进程内唯一的指标集合，发送和接收路径上直接记录
+pub static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics::new().expect("invalid metric definition"));
+
+pub struct Metrics {
+    registry: Registry,
+    pub connected_peers: IntGauge,
+    pub federation_links: IntGauge,
+    pub connections_accepted: IntCounter,
+    /// 按原因：handshake、banned
+    pub connections_rejected: IntCounterVec,
+    /// 按消息类型
+    pub messages_received: IntCounterVec,
+    pub messages_relayed: IntCounterVec,
+    pub bytes_received: IntCounter,
+    pub bytes_sent: IntCounter,
+    /// 按客户端ID或对端服务器ID
+    pub send_failures: IntCounterVec,
+    /// 一次广播发给房间内所有客户端的耗时
+    pub broadcast_seconds: Histogram,
+    /// 按钩子名称
+    pub hook_panics: IntCounterVec,
+    /// 按结果：delivered、retried、dead_letter
+    pub webhook_deliveries: IntCounterVec,
+    /// 以下按连接统计，每次抓取时根据 `Connection::stats()` 重新填充
+    quic_rtt_seconds: GaugeVec,
+    quic_cwnd_bytes: IntGaugeVec,
+    quic_congestion_events: IntGaugeVec,
+    quic_lost_packets: IntGaugeVec,
+    quic_sent_packets: IntGaugeVec,
+}
+
+impl Metrics {
+    fn new() -> Result<Self> {
+        let registry = Registry::new_custom(Some("t3xt".to_string()), None)?;
+        let connection_labels = &["peer", "kind"];
+        let metrics = Self {
+            connected_peers: IntGauge::new("connected_peers", "Clients currently connected")?,
+            federation_links: IntGauge::new("federation_links", "Federation links currently established")?,
+            connections_accepted: IntCounter::new("connections_accepted_total", "Connections accepted")?,
+            connections_rejected: IntCounterVec::new(
+                Opts::new("connections_rejected_total", "Connections rejected"),
+                &["reason"],
+            )?,
+            messages_received: IntCounterVec::new(
+                Opts::new("messages_received_total", "Messages received from clients and links"),
+                &["type"],
+            )?,
+            messages_relayed: IntCounterVec::new(
+                Opts::new("messages_relayed_total", "Messages relayed to federation links"),
+                &["type"],
+            )?,
+            bytes_received: IntCounter::new("bytes_received_total", "Message payload bytes received")?,
+            bytes_sent: IntCounter::new("bytes_sent_total", "Message payload bytes sent")?,
+            send_failures: IntCounterVec::new(
+                Opts::new("send_failures_total", "Failed message sends per peer"),
+                &["peer"],
+            )?,
+            broadcast_seconds: Histogram::with_opts(
+                HistogramOpts::new("broadcast_duration_seconds", "Room broadcast fan-out latency")
+                    .buckets(prometheus::exponential_buckets(0.0001, 4.0, 10)?),
+            )?,
+            hook_panics: IntCounterVec::new(
+                Opts::new("hook_panics_total", "Message hook callbacks that panicked"),
+                &["hook"],
+            )?,
+            webhook_deliveries: IntCounterVec::new(
+                Opts::new("webhook_deliveries_total", "Outgoing webhook delivery attempts by result"),
+                &["result"],
+            )?,
+            quic_rtt_seconds: GaugeVec::new(
+                Opts::new("quic_rtt_seconds", "Smoothed round-trip time"),
+                connection_labels,
+            )?,
+            quic_cwnd_bytes: IntGaugeVec::new(
+                Opts::new("quic_cwnd_bytes", "Congestion window"),
+                connection_labels,
+            )?,
+            quic_congestion_events: IntGaugeVec::new(
+                Opts::new("quic_congestion_events", "Congestion events on the connection"),
+                connection_labels,
+            )?,
+            quic_lost_packets: IntGaugeVec::new(
+                Opts::new("quic_lost_packets", "Packets lost on the connection"),
+                connection_labels,
+            )?,
+            quic_sent_packets: IntGaugeVec::new(
+                Opts::new("quic_sent_packets", "Packets sent on the connection"),
+                connection_labels,
+            )?,
+            registry,
+        };
+
+        let registry = &metrics.registry;
+        registry.register(Box::new(metrics.connected_peers.clone()))?;
+        registry.register(Box::new(metrics.federation_links.clone()))?;
+        registry.register(Box::new(metrics.connections_accepted.clone()))?;
+        registry.register(Box::new(metrics.connections_rejected.clone()))?;
+        registry.register(Box::new(metrics.messages_received.clone()))?;
+        registry.register(Box::new(metrics.messages_relayed.clone()))?;
+        registry.register(Box::new(metrics.bytes_received.clone()))?;
+        registry.register(Box::new(metrics.bytes_sent.clone()))?;
+        registry.register(Box::new(metrics.send_failures.clone()))?;
+        registry.register(Box::new(metrics.broadcast_seconds.clone()))?;
+        registry.register(Box::new(metrics.hook_panics.clone()))?;
+        registry.register(Box::new(metrics.webhook_deliveries.clone()))?;
+        registry.register(Box::new(metrics.quic_rtt_seconds.clone()))?;
+        registry.register(Box::new(metrics.quic_cwnd_bytes.clone()))?;
+        registry.register(Box::new(metrics.quic_congestion_events.clone()))?;
+        registry.register(Box::new(metrics.quic_lost_packets.clone()))?;
+        registry.register(Box::new(metrics.quic_sent_packets.clone()))?;
+        Ok(metrics)
+    }
+
+    /// 用当前连接的统计替换上一次抓取的值，已断开的连接随之消失
+    fn record_connections(&self, connections: Vec<(String, &'static str, Connection)>) {
+        self.quic_rtt_seconds.reset();
+        self.quic_cwnd_bytes.reset();
+        self.quic_congestion_events.reset();
+        self.quic_lost_packets.reset();
+        self.quic_sent_packets.reset();
+        for (peer, kind, connection) in connections {
+            let labels = [peer.as_str(), kind];
+            let path = connection.stats().path;
+            self.quic_rtt_seconds.with_label_values(&labels).set(path.rtt.as_secs_f64());
+            self.quic_cwnd_bytes.with_label_values(&labels).set(saturate(path.cwnd));
+            self.quic_congestion_events.with_label_values(&labels).set(saturate(path.congestion_events));
+            self.quic_lost_packets.with_label_values(&labels).set(saturate(path.lost_packets));
+            self.quic_sent_packets.with_label_values(&labels).set(saturate(path.sent_packets));
+        }
+    }
+
+    fn encode(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+fn saturate(value: u64) -> i64 {
+    i64::try_from(value).unwrap_or(i64::MAX)
+}
+
+/// 在 `listen` 上提供 `/metrics`，供 Prometheus 抓取
+pub async fn serve(listen: SocketAddr, state: Arc<ServerState>) -> Result<()> {
+    let app = Router::new()
+        .route("/metrics", get(scrape))
+        .with_state(state);
+    let listener = tokio::net::TcpListener::bind(listen).await
+        .with_context(|| format!("Failed to bind metrics listener {}", listen))?;
+    info!("指标监听地址: http://{}/metrics", listen);
+    axum::serve(listener, app).await.context("Metrics listener failed")?;
+    Ok(())
+}
+
+async fn scrape(State(state): State<Arc<ServerState>>) -> impl IntoResponse {
+    METRICS.record_connections(state.connections().await);
+    match METRICS.encode() {
+        Ok(body) => ([(header::CONTENT_TYPE, TextEncoder::new().format_type().to_string())], body).into_response(),
+        Err(e) => {
+            warn!("导出指标失败: {}", e);
+            axum::http::StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
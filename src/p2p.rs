@@ -0,0 +1,284 @@
+//! 客户端之间的直连。服务器只做会合：交换双方观测到的地址和临时证书的指纹，
+//! 之后双方从连接服务器的同一个 UDP 端口互相发起 QUIC 连接打洞，每一方同时是客户端和服务器。
+//! 直连只传私信；打洞失败或直连断开时，私信仍由服务器转发。
+
+use crate::{
+    client::Client,
+    config::Config,
+    console,
+    crypto::{self, CertConfig},
+    message::*,
+};
+use anyhow::{bail, Context, Result};
+use quinn::{Connecting, Connection, Endpoint};
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{
+    sync::{mpsc, oneshot},
+    time::timeout,
+};
+use tracing::{debug, info, info_span, warn, Instrument};
+
+/// 临时证书中的名称，身份由指纹确定
+const PEER_SERVER_NAME: &str = "t3xt-peer";
+
+/// 会合得到的对方地址和证书指纹，None 表示对方无法直连
+type Rendezvous = Option<(SocketAddr, String)>;
+
+/// 客户端的直连状态，与服务器连接共用同一个 QUIC 端点
+pub(crate) struct Direct {
+    client_id: String,
+    enabled: bool,
+    endpoint: Endpoint,
+    /// 与服务器的连接，用于发送会合请求
+    server: Connection,
+    identity: CertConfig,
+    fingerprint: String,
+    transport: Arc<quinn::TransportConfig>,
+    punch_timeout: Duration,
+    limit: usize,
+    /// 已建立的直连，按对方客户端ID索引
+    links: Mutex<HashMap<String, Connection>>,
+    /// 等待服务器转来对方地址的会合请求
+    waiting: Mutex<HashMap<String, oneshot::Sender<Rendezvous>>>,
+    /// 等待对方发起连接的打洞，按对方证书指纹索引
+    incoming: Mutex<HashMap<String, oneshot::Sender<Connection>>>,
+    messages: mpsc::UnboundedSender<Message>,
+}
+
+impl Direct {
+    /// 开启 `client.p2p` 时端点同时接受连接，只接受正在打洞的对方
+    pub(crate) fn new(
+        config: &Config,
+        endpoint: Endpoint,
+        server: Connection,
+        messages: mpsc::UnboundedSender<Message>,
+    ) -> Result<Arc<Self>> {
+        let identity = CertConfig::generate_ephemeral(PEER_SERVER_NAME)?;
+        let enabled = config.client.p2p;
+        if enabled {
+            let server_config = crypto::create_peer_server_config(&identity)?;
+            endpoint.set_server_config(Some(crypto::create_quinn_server_config(server_config, &config.transport)?));
+        }
+        Ok(Arc::new(Self {
+            client_id: config.client.id.clone(),
+            enabled,
+            endpoint,
+            server,
+            fingerprint: crypto::certificate_hash(&identity.cert),
+            identity,
+            transport: Arc::new(crypto::create_transport_config(&config.transport)?),
+            punch_timeout: Duration::from_millis(config.client.punch_timeout_ms),
+            limit: config.transport.max_message_size,
+            links: Mutex::new(HashMap::new()),
+            waiting: Mutex::new(HashMap::new()),
+            incoming: Mutex::new(HashMap::new()),
+            messages,
+        }))
+    }
+
+    /// 经服务器会合后与 `peer` 打洞直连
+    pub(crate) async fn connect(self: &Arc<Self>, peer: &str) -> Result<()> {
+        if !self.enabled {
+            bail!("Direct connections are disabled (client.p2p)");
+        }
+        if peer == self.client_id {
+            bail!("Cannot connect to yourself");
+        }
+        let (tx, rx) = oneshot::channel();
+        self.waiting.lock().unwrap().insert(peer.to_string(), tx);
+        let reply = async {
+            Client::send_on(&self.server, self.offer(peer, &self.fingerprint)).await?;
+            timeout(self.punch_timeout, rx).await
+                .map_err(|_| anyhow::anyhow!("Rendezvous with {} timed out", peer))?
+                .context("Rendezvous cancelled")
+        }
+        .await;
+        self.waiting.lock().unwrap().remove(peer);
+        let Some((addr, fingerprint)) = reply? else {
+            bail!("{} is not reachable for a direct connection", peer);
+        };
+        self.punch(peer, addr, &fingerprint).await
+    }
+
+    /// 处理服务器转来的对方地址：自己发起的会合交给等待的 [`Direct::connect`]，
+    /// 对方发起的先回复自己的指纹，再开始打洞
+    pub(crate) async fn handle_address(self: Arc<Self>, peer: String, addr: Option<SocketAddr>, fingerprint: String) {
+        if let Some(waiter) = self.waiting.lock().unwrap().remove(&peer) {
+            let _ = waiter.send(addr.map(|addr| (addr, fingerprint)));
+            return;
+        }
+        let Some(addr) = addr else {
+            return;
+        };
+        // 关闭直连时回复空指纹，对方立即改经服务器转发
+        let own = if self.enabled { self.fingerprint.as_str() } else { "" };
+        if let Err(e) = Client::send_on(&self.server, self.offer(&peer, own)).await {
+            warn!("回复直连请求失败: {:#}", e);
+            return;
+        }
+        if !self.enabled {
+            info!("拒绝 {} 的直连请求", peer);
+            return;
+        }
+        console::line(format_args!("{} 请求直连", peer));
+        if let Err(e) = self.punch(&peer, addr, &fingerprint).await {
+            console::line(format_args!("无法与 {} 直连，私信继续经服务器转发: {:#}", peer, e));
+        }
+    }
+
+    /// 双方同时向对方发起连接，发出的 Initial 包在各自的 NAT 上打开映射。
+    /// 两条连接都可能建立，约定使用ID较小一方发起的那条，另一条只用于打洞
+    async fn punch(self: &Arc<Self>, peer: &str, addr: SocketAddr, fingerprint: &str) -> Result<()> {
+        info!("与 {} 打洞 {}", peer, addr);
+        let dialer = self.client_id.as_str() < peer;
+        let rustls_config = crypto::create_peer_client_config(&self.identity, fingerprint)?;
+        let mut client_config = quinn::ClientConfig::new(Arc::new(rustls_config));
+        client_config.transport_config(Arc::clone(&self.transport));
+        let (tx, incoming) = oneshot::channel();
+        if !dialer {
+            self.incoming.lock().unwrap().insert(crypto::normalize_fingerprint(fingerprint), tx);
+        }
+        let outgoing = match self.endpoint.connect_with(client_config, addr, PEER_SERVER_NAME) {
+            Ok(outgoing) => outgoing,
+            Err(e) => {
+                self.incoming.lock().unwrap().remove(&crypto::normalize_fingerprint(fingerprint));
+                return Err(e.into());
+            }
+        };
+
+        let result = if dialer {
+            timeout(self.punch_timeout, outgoing).await
+                .map(|connection| connection.context("Failed to establish direct connection"))
+        } else {
+            let result = timeout(self.punch_timeout, incoming).await
+                .map(|connection| connection.context("Direct connection cancelled"));
+            self.incoming.lock().unwrap().remove(&crypto::normalize_fingerprint(fingerprint));
+            // 发出的连接只用来在本方 NAT 上打开映射，对方的连接胜出或超时后关闭
+            tokio::spawn(discard(outgoing, self.punch_timeout).in_current_span());
+            result
+        };
+        let connection = result.map_err(|_| anyhow::anyhow!("Hole punching to {} timed out", addr))??;
+        self.add_link(peer, connection);
+        Ok(())
+    }
+
+    /// 接受正在打洞的对方发起的连接，其他连接直接关闭
+    pub(crate) async fn accept(self: Arc<Self>) {
+        while let Some(connecting) = self.endpoint.accept().await {
+            let direct = Arc::clone(&self);
+            tokio::spawn(async move {
+                let connection = match connecting.await {
+                    Ok(connection) => connection,
+                    Err(e) => {
+                        debug!("直连握手失败: {}", e);
+                        return;
+                    }
+                };
+                let waiter = peer_fingerprint(&connection)
+                    .and_then(|fingerprint| direct.incoming.lock().unwrap().remove(&fingerprint));
+                match waiter {
+                    Some(waiter) => {
+                        let _ = waiter.send(connection);
+                    }
+                    None => connection.close(0u32.into(), b"unexpected"),
+                }
+            });
+        }
+    }
+
+    fn add_link(self: &Arc<Self>, peer: &str, connection: Connection) {
+        info!("已与 {} 直连 {}", peer, connection.remote_address());
+        console::line(format_args!("已与 {} 建立直连 ({})", peer, connection.remote_address()));
+        if let Some(old) = self.links.lock().unwrap().insert(peer.to_string(), connection.clone()) {
+            old.close(0u32.into(), b"replaced");
+        }
+        let span = info_span!("direct", peer = %peer, remote = %connection.remote_address());
+        tokio::spawn(Arc::clone(self).receive(peer.to_string(), connection).instrument(span));
+    }
+
+    /// 直连只接受发给自己的私信，发送者以握手时确认的身份为准
+    async fn receive(self: Arc<Self>, peer: String, connection: Connection) {
+        while let Ok(mut recv) = connection.accept_uni().await {
+            let mut message = match Client::receive_message(&mut recv, self.limit).await {
+                Ok(message) => message,
+                Err(e) => {
+                    warn!("Failed to receive message: {}", e);
+                    continue;
+                }
+            };
+            if !matches!(&message.message_type, MessageType::Direct { recipient, .. } if *recipient == self.client_id) {
+                warn!("忽略直连上的非私信消息");
+                continue;
+            }
+            message.sender_id = peer.clone();
+            if self.messages.send(message).is_err() {
+                break;
+            }
+        }
+        let removed = {
+            let mut links = self.links.lock().unwrap();
+            let current = links.get(&peer).is_some_and(|link| link.stable_id() == connection.stable_id());
+            current && links.remove(&peer).is_some()
+        };
+        if removed {
+            console::line(format_args!("与 {} 的直连已断开，私信改由服务器转发", peer));
+        }
+    }
+
+    /// 有直连时经直连发送私信。没有直连或发送失败时返回 false，由调用方改经服务器发送
+    pub(crate) async fn send(&self, message: &Message) -> bool {
+        let MessageType::Direct { recipient, .. } = &message.message_type else {
+            return false;
+        };
+        let Some(connection) = self.links.lock().unwrap().get(recipient).cloned() else {
+            return false;
+        };
+        match Client::send_on(&connection, message.clone()).await {
+            Ok(()) => true,
+            Err(e) => {
+                warn!("经直连发送私信失败，改由服务器转发: {:#}", e);
+                self.links.lock().unwrap().remove(recipient);
+                connection.close(0u32.into(), b"send failed");
+                false
+            }
+        }
+    }
+
+    /// 当前直连的客户端ID
+    pub(crate) fn peers(&self) -> Vec<String> {
+        let mut peers: Vec<String> = self.links.lock().unwrap().keys().cloned().collect();
+        peers.sort();
+        peers
+    }
+
+    pub(crate) fn close(&self) {
+        for (_, connection) in self.links.lock().unwrap().drain() {
+            connection.close(0u32.into(), b"Goodbye");
+        }
+    }
+
+    fn offer(&self, peer: &str, fingerprint: &str) -> Message {
+        Message::new(self.client_id.clone(), MessageType::PeerAddress {
+            peer: peer.to_string(),
+            addr: None,
+            fingerprint: fingerprint.to_string(),
+        })
+    }
+}
+
+fn peer_fingerprint(connection: &Connection) -> Option<String> {
+    let certs = connection.peer_identity()?.downcast::<Vec<rustls::Certificate>>().ok()?;
+    Some(crypto::certificate_hash(certs.first()?))
+}
+
+/// 等待不再需要的出站连接握手结束后关闭；超时则丢弃，quinn 会隐式关闭
+async fn discard(connecting: Connecting, wait: Duration) {
+    if let Ok(Ok(connection)) = timeout(wait, connecting).await {
+        connection.close(0u32.into(), b"superseded");
+    }
+}
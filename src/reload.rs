@@ -0,0 +1,134 @@
+use crate::{config::Config, console, crypto, server::ServerState, webtransport};
+use anyhow::{Context, Result};
+use quinn::Endpoint;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use tokio::signal::unix::{signal, SignalKind};
+use tracing::{error, info, warn};
+
+/// 检查配置和证书文件是否变化的间隔
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+/// 重新读取配置文件、环境变量并叠加启动时的命令行参数
+pub type ConfigLoader = Arc<dyn Fn() -> Result<Config> + Send + Sync>;
+
+/// 收到 SIGHUP 或监视的文件变化时重新加载，失败时保留当前配置
+pub async fn watch(endpoint: Endpoint, state: Arc<ServerState>, loader: ConfigLoader, config_path: PathBuf) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => Some(hangup),
+        Err(e) => {
+            warn!("无法监听 SIGHUP: {}", e);
+            None
+        }
+    };
+    let mut interval = tokio::time::interval(POLL_INTERVAL);
+    let mut stamps = modified_times(&watched_files(&state, &config_path).await);
+
+    loop {
+        let triggered_by_signal = tokio::select! {
+            Some(()) = async { hangup.as_mut()?.recv().await } => true,
+            _ = interval.tick() => false,
+        };
+        let files = watched_files(&state, &config_path).await;
+        let current = modified_times(&files);
+        if !triggered_by_signal && current == stamps {
+            continue;
+        }
+        // 证书可能正在被逐个替换，稍等片刻再读取
+        if !triggered_by_signal {
+            tokio::time::sleep(Duration::from_millis(200)).await;
+        }
+
+        match reload(&endpoint, &state, &loader).await {
+            Ok(()) => console::notice("配置已重新加载"),
+            Err(e) => {
+                error!("重新加载配置失败: {:#}", e);
+                console::error(format_args!("重新加载配置失败，继续使用当前配置: {:#}", e));
+            }
+        }
+        stamps = modified_times(&watched_files(&state, &config_path).await);
+    }
+}
+
+/// 配置文件、证书、私钥和信任的对端证书
+async fn watched_files(state: &ServerState, config_path: &Path) -> Vec<PathBuf> {
+    let config = state.config.read().await;
+    let mut files = vec![config_path.to_path_buf(), config.tls.cert.clone(), config.tls.key.clone()];
+    files.extend(config.server.peer_certs.iter().cloned());
+    files
+}
+
+fn modified_times(files: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    files
+        .iter()
+        .map(|path| fs::metadata(path).and_then(|meta| meta.modified()).ok())
+        .collect()
+}
+
+/// 换上新证书供之后的握手使用，已建立的连接不受影响；访问控制和限流即时生效
+async fn reload(endpoint: &Endpoint, state: &ServerState, loader: &ConfigLoader) -> Result<()> {
+    let mut config = loader()?;
+    let current = state.config.read().await.clone();
+
+    // 以下设置在启动时已经生效，改动需要重启
+    let restart_only = [
+        ("server.id", config.server.id != current.server.id),
+        ("server.bind", config.server.bind != current.server.bind),
+        ("server.port", config.server.port != current.server.port),
+        ("server.peers", config.server.peers != current.server.peers),
+        ("server.history_capacity", config.server.history_capacity != current.server.history_capacity),
+        ("server.tcp", config.server.tcp != current.server.tcp),
+        ("transport", config.transport != current.transport),
+        ("metrics", config.metrics != current.metrics),
+        ("api.enabled", config.api.enabled != current.api.enabled),
+        ("api.listen", config.api.listen != current.api.listen),
+        ("gateway", config.gateway != current.gateway),
+        ("webtransport", config.webtransport != current.webtransport),
+        ("irc", config.irc != current.irc),
+        ("discovery.announce", config.discovery.announce != current.discovery.announce),
+        ("log", config.log != current.log),
+        ("telemetry", config.telemetry != current.telemetry),
+    ];
+    for (name, _) in restart_only.iter().filter(|(_, changed)| *changed) {
+        warn!("{} 的修改需要重启服务器才能生效", name);
+    }
+    config.server.id = current.server.id;
+    config.server.bind = current.server.bind;
+    config.server.port = current.server.port;
+    config.server.peers = current.server.peers;
+    config.server.history_capacity = current.server.history_capacity;
+    config.server.tcp = current.server.tcp;
+    config.transport = current.transport;
+    config.metrics = current.metrics;
+    config.api.enabled = current.api.enabled;
+    config.api.listen = current.api.listen;
+    config.gateway = current.gateway;
+    config.webtransport = current.webtransport;
+    config.irc = current.irc;
+    config.discovery.announce = current.discovery.announce;
+    config.log = current.log;
+    config.telemetry = current.telemetry;
+
+    let cert_config = crypto::CertConfig::load_from_files(&config.tls)
+        .context("Failed to reload certificate")?;
+    let federation_roots = crypto::federation_root_store(&cert_config, &config.server.peer_certs)?;
+    let federation_config = crypto::create_federation_client_config(&cert_config, federation_roots.clone())?;
+    let server_config =
+        crypto::create_server_config(cert_config.clone(), federation_roots, Arc::clone(&state.tls_sessions))?;
+
+    *state.tcp_tls.write().await = Arc::new(server_config.clone());
+    endpoint.set_server_config(Some(crypto::create_quinn_server_config(server_config, &config.transport)?));
+    *state.federation_client.write().await =
+        crypto::create_quinn_client_config(federation_config, &config.transport)?;
+    *state.certificate.write().await = cert_config;
+    *state.config.write().await = config;
+    webtransport::refresh(state).await;
+    info!("证书和配置已重新加载");
+
+    state.enforce_bans().await;
+    Ok(())
+}
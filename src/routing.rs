@@ -0,0 +1,123 @@
+use crate::message::Route;
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// 去重时记住的消息ID数量
+const SEEN_CAPACITY: usize = 10_000;
+
+/// 从各联邦链路学到的远端客户端路由。
+///
+/// 采用路径向量：每条路由携带到达客户端所在服务器的完整路径，
+/// 路径中包含本服务器的路由直接丢弃，因此链路断开后不会出现无穷计数。
+#[derive(Default)]
+pub struct RoutingTable {
+    /// 每条链路最近一次通告的路由，按对端服务器ID索引
+    learned: HashMap<String, Vec<Route>>,
+    /// 最近一次发给每条链路的通告，内容不变时不重复发送
+    advertised: HashMap<String, Vec<Route>>,
+}
+
+impl RoutingTable {
+    /// 用链路的完整通告替换之前学到的路由
+    pub fn update(&mut self, link: &str, routes: Vec<Route>, server_id: &str) {
+        let routes = routes
+            .into_iter()
+            .filter(|route| route.path.first().map(String::as_str) == Some(link))
+            .filter(|route| !route.path.iter().any(|hop| hop == server_id))
+            .collect();
+        self.learned.insert(link.to_string(), routes);
+    }
+
+    /// 链路断开：丢弃经由它学到的路由
+    pub fn remove_link(&mut self, link: &str) {
+        self.learned.remove(link);
+        self.advertised.remove(link);
+    }
+
+    /// 每个远端客户端的最短路由，返回（下一跳链路, 路由）
+    pub fn best_routes(&self) -> HashMap<&str, (&str, &Route)> {
+        let mut best: HashMap<&str, (&str, &Route)> = HashMap::new();
+        for (link, routes) in &self.learned {
+            for route in routes {
+                let better = match best.get(route.client_id.as_str()) {
+                    Some((best_link, best_route)) => {
+                        (route.path.len(), link.as_str()) < (best_route.path.len(), *best_link)
+                    }
+                    None => true,
+                };
+                if better {
+                    best.insert(&route.client_id, (link, route));
+                }
+            }
+        }
+        best
+    }
+
+    /// 远端是否有该客户端的路由
+    pub fn is_reachable(&self, client_id: &str) -> bool {
+        self.learned
+            .values()
+            .any(|routes| routes.iter().any(|route| route.client_id == client_id))
+    }
+
+    /// 计算发给某条链路的通告：本地客户端加上经由其他链路学到的最佳路由
+    pub fn advertisement(&self, link: &str, server_id: &str, local: &[(String, String)]) -> Vec<Route> {
+        let mut routes: Vec<Route> = local
+            .iter()
+            .map(|(client_id, room)| Route {
+                client_id: client_id.clone(),
+                room: room.clone(),
+                path: vec![server_id.to_string()],
+            })
+            .collect();
+        let local_ids: HashSet<&str> = local.iter().map(|(client_id, _)| client_id.as_str()).collect();
+        for (client_id, (next_hop, route)) in self.best_routes() {
+            // 水平分割：不把从该链路学到的路由再通告回去
+            if next_hop == link || local_ids.contains(client_id) || route.path.iter().any(|hop| hop == link) {
+                continue;
+            }
+            let mut path = Vec::with_capacity(route.path.len() + 1);
+            path.push(server_id.to_string());
+            path.extend(route.path.iter().cloned());
+            routes.push(Route {
+                client_id: client_id.to_string(),
+                room: route.room.clone(),
+                path,
+            });
+        }
+        routes.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        routes
+    }
+
+    /// 记录将要发送的通告；与上次相同时返回 false
+    pub fn mark_advertised(&mut self, link: &str, routes: &[Route]) -> bool {
+        if self.advertised.get(link).is_some_and(|last| last.as_slice() == routes) {
+            return false;
+        }
+        self.advertised.insert(link.to_string(), routes.to_vec());
+        true
+    }
+}
+
+/// 最近见过的消息ID，用于在网状拓扑中丢弃重复消息
+#[derive(Default)]
+pub struct SeenMessages {
+    ids: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl SeenMessages {
+    /// 记录消息ID；已经见过时返回 false
+    pub fn insert(&mut self, id: &str) -> bool {
+        if self.ids.contains(id) {
+            return false;
+        }
+        if self.order.len() == SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        self.ids.insert(id.to_string());
+        self.order.push_back(id.to_string());
+        true
+    }
+}
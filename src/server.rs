@@ -1,75 +1,1001 @@
-use crate::{crypto, message::*};
+use crate::{
+    config::{Config, PolicySettings},
+    console, crypto, federation,
+    history::History,
+    hooks::{Action, MessageHook, PeerInfo, Pipeline},
+    message::*,
+    metrics::METRICS,
+    reload,
+    telemetry,
+    routing::{RoutingTable, SeenMessages},
+    webhooks, webtransport,
+};
 use anyhow::{Context, Result};
-use quinn::{Connection, Endpoint, ServerConfig};
-use std::sync::Arc;
-use tokio::{io::{AsyncBufReadExt, BufReader}, sync::RwLock};
-use tracing::{error, info, warn};
+use bytes::Bytes;
+use chrono::Utc;
+use serde::Serialize;
+use quinn::{ClientConfig, Connection, Endpoint};
+use rustls::{server::ServerSessionMemoryCache, ServerConfig as RustlsServerConfig};
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    path::PathBuf,
+    sync::Arc,
+    time::Instant,
+};
+use tokio::sync::{mpsc, Mutex, RwLock};
+use tokio_stream::{Stream, StreamExt};
+use tracing::{error, info, info_span, instrument, warn, Instrument, Span};
 
+pub use crate::reload::ConfigLoader;
+
+/// 每个用户保留的离线提及条数，响应按消息大小上限分成多条发送
+const MENTIONS_PER_USER: usize = 20;
+
+/// 为发送前才写入的 trace context 等字段预留的字节数
+const ENVELOPE_RESERVE: usize = 256;
+
+/// 按序列化后的大小把列表装进若干条由 `build` 生成的消息，每条不超过 `limit` 字节。
+/// 单独一项就放不下的元素被跳过，与生成的消息一起返回
+fn fit_message_size<T: Serialize>(
+    items: Vec<T>,
+    limit: usize,
+    build: impl Fn(Vec<T>) -> Message,
+) -> (Vec<Message>, Vec<T>) {
+    let empty = build(Vec::new()).to_bytes().map_or(0, |bytes| bytes.len());
+    let budget = limit.saturating_sub(empty + ENVELOPE_RESERVE);
+    let mut messages = Vec::new();
+    let mut chunk = Vec::new();
+    let mut used = 0;
+    let mut skipped = Vec::new();
+    for item in items {
+        // 每项之间还有一个逗号
+        let size = serde_json::to_vec(&item).map_or(usize::MAX, |bytes| bytes.len() + 1);
+        if size > budget {
+            skipped.push(item);
+            continue;
+        }
+        if used + size > budget {
+            messages.push(build(std::mem::take(&mut chunk)));
+            used = 0;
+        }
+        chunk.push(item);
+        used += size;
+    }
+    if !chunk.is_empty() || messages.is_empty() {
+        messages.push(build(chunk));
+    }
+    (messages, skipped)
+}
+
+/// 经由网关或 TCP 连接的客户端的发送队列，由连接任务写入 WebSocket、WebTransport 会话或 TCP 流
+pub(crate) enum Outgoing {
+    Message(Box<Message>),
+    Signal(Signal),
+    Close(&'static str),
+}
+
+/// 客户端连接：QUIC，或经由网关的 WebSocket、WebTransport 和 TCP+TLS 备用传输
+#[derive(Clone)]
+pub(crate) enum PeerConnection {
+    Quic(Connection),
+    Gateway {
+        /// 连接方式，如 "ws"、"wt"、"tcp"
+        transport: &'static str,
+        remote: SocketAddr,
+        outgoing: mpsc::UnboundedSender<Outgoing>,
+    },
+}
+
+impl PeerConnection {
+    pub(crate) fn remote_address(&self) -> SocketAddr {
+        match self {
+            PeerConnection::Quic(connection) => connection.remote_address(),
+            PeerConnection::Gateway { remote, .. } => *remote,
+        }
+    }
+
+    /// 在客户端列表中标识该连接，网关连接加上连接方式前缀以免与 QUIC 地址相同
+    pub(crate) fn key(&self) -> String {
+        match self {
+            PeerConnection::Quic(connection) => connection.remote_address().to_string(),
+            PeerConnection::Gateway { transport, remote, .. } => format!("{}/{}", transport, remote),
+        }
+    }
+
+    pub(crate) async fn send(&self, message: Message) -> Result<()> {
+        match self {
+            PeerConnection::Quic(connection) => Server::send_message(connection, message).await,
+            PeerConnection::Gateway { outgoing, .. } => outgoing
+                .send(Outgoing::Message(Box::new(message)))
+                .map_err(|_| anyhow::anyhow!("Gateway connection closed")),
+        }
+    }
+
+    /// 发送短暂信号，失败直接丢弃
+    fn send_signal(&self, signal: &Signal) {
+        match self {
+            PeerConnection::Quic(connection) => {
+                if let Ok(data) = signal.to_bytes() {
+                    let _ = connection.send_datagram(Bytes::from(data));
+                }
+            }
+            PeerConnection::Gateway { outgoing, .. } => {
+                let _ = outgoing.send(Outgoing::Signal(signal.clone()));
+            }
+        }
+    }
+
+    pub(crate) fn close(&self, reason: &'static str) {
+        match self {
+            PeerConnection::Quic(connection) => connection.close(0u32.into(), reason.as_bytes()),
+            PeerConnection::Gateway { outgoing, .. } => {
+                let _ = outgoing.send(Outgoing::Close(reason));
+            }
+        }
+    }
+}
+
+/// 已连接的客户端及其在线状态
+pub(crate) struct Peer {
+    connection: PeerConnection,
+    addr: String,
+    /// 收到 Join 之前为 None
+    client_id: Option<String>,
+    room: String,
+    away: bool,
+    /// 登录时声明为机器人
+    bot: bool,
+    status: Option<String>,
+    last_active: Instant,
+    /// 限流令牌桶：剩余令牌数及上次补充的时间
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Peer {
+    pub(crate) fn new(connection: PeerConnection) -> Self {
+        Self {
+            addr: connection.key(),
+            connection,
+            client_id: None,
+            room: DEFAULT_ROOM.to_string(),
+            away: false,
+            bot: false,
+            status: None,
+            last_active: Instant::now(),
+            // 首次取令牌时截断为突发上限，即新连接从满桶开始
+            tokens: f64::INFINITY,
+            refilled: Instant::now(),
+        }
+    }
+
+    /// 按令牌桶限流，未开启限流时总是允许
+    fn take_token(&mut self, policy: &PolicySettings) -> bool {
+        if policy.rate_limit_per_sec == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let burst = f64::from(policy.rate_limit_burst);
+        let elapsed = now.duration_since(self.refilled).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * f64::from(policy.rate_limit_per_sec)).min(burst);
+        self.refilled = now;
+        if self.tokens < 1.0 {
+            return false;
+        }
+        self.tokens -= 1.0;
+        true
+    }
+
+    /// 指标中标识该客户端：登录后用客户端ID，之前用地址
+    fn label(&self) -> &str {
+        self.client_id.as_deref().unwrap_or(&self.addr)
+    }
+
+    /// 连接地址或客户端ID是否被禁止
+    fn is_banned(&self, policy: &PolicySettings) -> bool {
+        policy.banned_ips.contains(&self.connection.remote_address().ip())
+            || self.client_id.as_ref().is_some_and(|id| policy.banned_ids.contains(id))
+    }
+
+    fn member_info(&self) -> Option<MemberInfo> {
+        Some(MemberInfo {
+            client_id: self.client_id.clone()?,
+            away: self.away,
+            status: self.status.clone(),
+            idle_secs: self.last_active.elapsed().as_secs(),
+            bot: self.bot,
+            server: None,
+        })
+    }
+
+    /// 登录之前为 None
+    fn info(&self) -> Option<PeerInfo> {
+        Some(PeerInfo {
+            addr: self.addr.clone(),
+            client_id: self.client_id.clone()?,
+            room: self.room.clone(),
+            bot: self.bot,
+        })
+    }
+}
+
+/// 与另一台 t3xt 服务器之间已认证的联邦链路
+pub(crate) struct Link {
+    pub(crate) connection: Connection,
+    pub(crate) server_id: String,
+}
+
+/// 各连接任务共享的服务器状态
+pub(crate) struct ServerState {
+    pub(crate) server_id: String,
+    peers: RwLock<Vec<Peer>>,
+    pub(crate) links: RwLock<Vec<Link>>,
+    /// 从联邦链路学到的远端客户端
+    pub(crate) routing: Mutex<RoutingTable>,
+    /// 已处理的房间消息ID，网状拓扑中同一消息可能从多条链路到达
+    seen: Mutex<SeenMessages>,
+    history: RwLock<History>,
+    /// 离线期间收到的 @提及，按被提及的客户端ID索引
+    mentions: RwLock<HashMap<String, VecDeque<Message>>>,
+    /// 生效的配置，重新加载时整体替换
+    pub(crate) config: RwLock<Config>,
+    /// 连接对端服务器时使用的客户端配置，随证书一起重新加载
+    pub(crate) federation_client: RwLock<ClientConfig>,
+    /// TCP 备用传输的 TLS 配置，与 QUIC 使用同一张证书，随证书一起重新加载
+    pub(crate) tcp_tls: RwLock<Arc<RustlsServerConfig>>,
+    /// 主端点当前的证书，WebTransport 据此选择证书，随证书一起重新加载
+    pub(crate) certificate: RwLock<crypto::CertConfig>,
+    /// TLS 会话缓存，属于这个服务器实例，重新加载证书时沿用
+    pub(crate) tls_sessions: Arc<ServerSessionMemoryCache>,
+    /// WebTransport 监听的端口和当前证书，监听启动后才有
+    pub(crate) webtransport: RwLock<Option<webtransport::Listener>>,
+    hooks: Hooks,
+    /// 处理本地客户端消息前执行的钩子
+    pipeline: Pipeline,
+    /// 交给 Webhook 任务推送的消息
+    webhooks: mpsc::UnboundedSender<Message>,
+}
+
+type MessageCallback = Arc<dyn Fn(&Message) + Send + Sync>;
+type PresenceCallback = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// 嵌入方注册的回调，在服务器处理完相应事件后同步调用
+#[derive(Default)]
+struct Hooks {
+    on_message: Vec<MessageCallback>,
+    on_join: Vec<PresenceCallback>,
+    on_leave: Vec<PresenceCallback>,
+}
+
+impl ServerState {
+    /// 查询已登录连接的客户端ID和当前房间
+    pub(crate) async fn identity(&self, peer_addr: &str) -> Option<PeerInfo> {
+        let peers = self.peers.read().await;
+        peers.iter().find(|peer| peer.addr == peer_addr)?.info()
+    }
+
+    /// 客户端是否在线，包括通过联邦连接在其他服务器上的客户端
+    pub(crate) async fn is_online(&self, client_id: &str) -> bool {
+        let local = {
+            let peers = self.peers.read().await;
+            peers.iter().any(|peer| peer.client_id.as_deref() == Some(client_id))
+        };
+        local || self.routing.lock().await.is_reachable(client_id)
+    }
+
+    /// HTTP API 以这些ID发送消息，客户端占用它们就能冒充 API，反过来 API 也能冒充客户端
+    pub(crate) async fn is_reserved(&self, client_id: &str) -> bool {
+        let config = self.config.read().await;
+        config.api.enabled && (config.api.sender_id == client_id || config.api.senders.iter().any(|id| id == client_id))
+    }
+
+    /// 为当前不在线的被提及者记录提及
+    async fn record_mentions(&self, message: &Message) {
+        for client_id in message.mentions() {
+            if client_id == message.sender_id || self.is_online(&client_id).await {
+                continue;
+            }
+            let mut mentions = self.mentions.write().await;
+            let inbox = mentions.entry(client_id).or_default();
+            if inbox.len() == MENTIONS_PER_USER {
+                inbox.pop_front();
+            }
+            inbox.push_back(message.clone());
+        }
+    }
+
+    /// 从收件箱中移除已处理的提及
+    async fn remove_mentions(&self, client_id: &str, messages: &[Message]) {
+        let mut mentions = self.mentions.write().await;
+        let Some(inbox) = mentions.get_mut(client_id) else {
+            return;
+        };
+        inbox.retain(|pending| !messages.iter().any(|message| message.id == pending.id));
+        if inbox.is_empty() {
+            mentions.remove(client_id);
+        }
+    }
+
+    /// 通知已被接受的房间消息，包括来自联邦链路和服务器公告的消息
+    fn notify_message(&self, message: &Message) {
+        if message.is_room_traffic() {
+            let _ = self.webhooks.send(message.clone());
+            for hook in &self.hooks.on_message {
+                hook(message);
+            }
+        }
+    }
+
+    fn notify_presence(&self, event: PresenceEvent, client_id: &str, room: &str) {
+        let hooks = match event {
+            PresenceEvent::Join => &self.hooks.on_join,
+            PresenceEvent::Leave => &self.hooks.on_leave,
+            _ => return,
+        };
+        for hook in hooks {
+            hook(client_id, room);
+        }
+    }
+
+    /// 客户端是否未超出发送频率限制
+    async fn take_token(&self, peer_addr: &str) -> bool {
+        let config = self.config.read().await;
+        let mut peers = self.peers.write().await;
+        peers
+            .iter_mut()
+            .find(|peer| peer.addr == peer_addr)
+            .is_none_or(|peer| peer.take_token(&config.policy))
+    }
+
+    /// 断开已被禁止的客户端，重新加载配置后调用
+    pub(crate) async fn enforce_bans(&self) {
+        let config = self.config.read().await;
+        let peers = self.peers.read().await;
+        for peer in peers.iter().filter(|peer| peer.is_banned(&config.policy)) {
+            info!(peer = %peer.addr, "断开被禁止的客户端");
+            console::notice(format_args!("断开被禁止的客户端: {}", peer.label()));
+            peer.connection.close("banned");
+        }
+    }
+
+    /// 更新客户端的最近活跃时间
+    async fn touch(&self, peer_addr: &str) {
+        let mut peers = self.peers.write().await;
+        if let Some(peer) = peers.iter_mut().find(|peer| peer.addr == peer_addr) {
+            peer.last_active = Instant::now();
+        }
+    }
+
+    /// 某房间的在线成员，包括路由表中的远端成员
+    pub(crate) async fn members(&self, room: Option<&str>) -> Vec<MemberInfo> {
+        let mut members: Vec<MemberInfo> = {
+            let peers = self.peers.read().await;
+            peers
+                .iter()
+                .filter(|peer| room.is_none_or(|room| peer.room == room))
+                .filter_map(Peer::member_info)
+                .collect()
+        };
+        let routing = self.routing.lock().await;
+        let mut remote: Vec<MemberInfo> = routing
+            .best_routes()
+            .into_values()
+            .filter(|(_, route)| room.is_none_or(|room| route.room == room))
+            .map(|(_, route)| MemberInfo {
+                client_id: route.client_id.clone(),
+                away: false,
+                status: None,
+                idle_secs: 0,
+                bot: false,
+                server: route.path.last().cloned(),
+            })
+            .collect();
+        remote.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        members.extend(remote);
+        members
+    }
+
+    /// 向每条联邦链路发送最新的路由通告，内容未变化的链路跳过
+    pub(crate) async fn advertise_routes(&self) {
+        let local: Vec<(String, String)> = {
+            let peers = self.peers.read().await;
+            peers
+                .iter()
+                .filter_map(|peer| Some((peer.client_id.clone()?, peer.room.clone())))
+                .collect()
+        };
+        let links = self.links.read().await;
+        for link in links.iter() {
+            let routes = {
+                let mut routing = self.routing.lock().await;
+                let routes = routing.advertisement(&link.server_id, &self.server_id, &local);
+                if !routing.mark_advertised(&link.server_id, &routes) {
+                    continue;
+                }
+                routes
+            };
+            let update = Message::new(self.server_id.clone(), MessageType::RouteUpdate { routes });
+            if let Err(e) = Server::send_message(&link.connection, update).await {
+                warn!("发送路由通告到 {} 失败: {}", link.server_id, e);
+            }
+        }
+    }
+
+    /// 广播消息给同一房间的客户端，可排除某个地址
+    async fn broadcast(&self, message: &Message, exclude: Option<&str>) {
+        let _timer = METRICS.broadcast_seconds.start_timer();
+        let peers = self.peers.read().await;
+        for peer in peers.iter() {
+            if peer.room == message.room && Some(peer.addr.as_str()) != exclude {
+                if let Err(e) = peer.connection.send(message.clone()).await {
+                    METRICS.send_failures.with_label_values(&[peer.label()]).inc();
+                    warn!("发送消息到 {} 失败: {}", peer.label(), e);
+                }
+            }
+        }
+    }
+
+    /// 所有 QUIC 客户端连接和联邦链路，附带指标标签和连接种类
+    pub(crate) async fn connections(&self) -> Vec<(String, &'static str, Connection)> {
+        let mut connections: Vec<_> = {
+            let peers = self.peers.read().await;
+            peers
+                .iter()
+                .filter_map(|peer| match &peer.connection {
+                    PeerConnection::Quic(connection) => Some((peer.label().to_string(), "client", connection.clone())),
+                    PeerConnection::Gateway { .. } => None,
+                })
+                .collect()
+        };
+        let links = self.links.read().await;
+        connections.extend(links.iter().map(|link| (link.server_id.clone(), "federation", link.connection.clone())));
+        connections
+    }
+
+    /// 把房间消息转发给联邦对端，跳过已经经过的服务器
+    pub(crate) async fn relay(&self, message: &Message) {
+        if !message.is_room_traffic() {
+            return;
+        }
+        let mut message = message.clone();
+        self.seen.lock().await.insert(&message.id);
+        message.origin.get_or_insert_with(|| self.server_id.clone());
+        if !message.via.contains(&self.server_id) {
+            message.via.push(self.server_id.clone());
+        }
+        let span = Span::current();
+        let links = self.links.read().await;
+        for link in links.iter() {
+            if message.via.contains(&link.server_id) {
+                continue;
+            }
+            // 每条链路单独发送，失联的对端在空闲超时前不会阻塞其他链路
+            let connection = link.connection.clone();
+            let server_id = link.server_id.clone();
+            let message = message.clone();
+            tokio::spawn(async move {
+                let kind = message.kind();
+                match Server::send_message(&connection, message).await {
+                    Ok(()) => METRICS.messages_relayed.with_label_values(&[kind]).inc(),
+                    Err(e) => {
+                        METRICS.send_failures.with_label_values(&[&server_id]).inc();
+                        warn!("转发消息到 {} 失败: {}", server_id, e);
+                    }
+                }
+            }.instrument(span.clone()));
+        }
+    }
+
+    /// 处理从联邦链路收到的房间消息：更新本地状态，投递给本地客户端，再继续转发
+    pub(crate) async fn handle_remote(&self, message: Message) {
+        if !message.is_room_traffic() {
+            warn!("忽略来自联邦链路的非房间消息");
+            return;
+        }
+        if message.via.contains(&self.server_id) || message.origin.as_deref() == Some(self.server_id.as_str()) {
+            return;
+        }
+        // 网状拓扑中同一消息会从多条链路到达，只处理第一次
+        if !self.seen.lock().await.insert(&message.id) {
+            return;
+        }
+        match &message.message_type {
+            MessageType::Text { .. } => {
+                let mut history = self.history.write().await;
+                if history.get(&message.id).is_some() {
+                    return;
+                }
+                history.push(message.clone());
+                drop(history);
+                self.record_mentions(&message).await;
+            }
+            MessageType::Edit { .. } | MessageType::Delete { .. } | MessageType::Reaction { .. } => {
+                // 权限已由来源服务器检查
+                self.history.write().await.apply(message.clone());
+            }
+            MessageType::Direct { .. } => {
+                self.notify_message(&message);
+                self.deliver_direct(&message).await;
+                self.relay(&message).await;
+                return;
+            }
+            _ => {}
+        }
+        console::message(&message, None);
+        self.notify_message(&message);
+        self.broadcast(&message, None).await;
+        self.relay(&message).await;
+    }
+
+    /// 登记新连接，收到 Join 之后才算登录
+    pub(crate) async fn add_peer(&self, peer: Peer) {
+        self.peers.write().await.push(peer);
+        METRICS.connected_peers.inc();
+    }
+
+    /// 连接断开后移出客户端列表，已登录的客户端广播离开事件
+    pub(crate) async fn remove_peer(&self, peer_addr: &str) {
+        let departed = {
+            let mut peers_guard = self.peers.write().await;
+            let departed = peers_guard
+                .iter()
+                .find(|peer| peer.addr == peer_addr)
+                .and_then(Peer::info);
+            peers_guard.retain(|peer| peer.addr != peer_addr);
+            departed
+        };
+        METRICS.connected_peers.dec();
+        info!("客户端断开连接");
+
+        if let Some(peer) = departed {
+            self.announce_leave(peer).await;
+        }
+    }
+
+    /// 退出登录但保持连接，之后重新 Join 才能收发消息，如 IRC 的 PART
+    pub(crate) async fn log_out(&self, peer_addr: &str) {
+        let departed = {
+            let mut peers_guard = self.peers.write().await;
+            peers_guard
+                .iter_mut()
+                .find(|peer| peer.addr == peer_addr)
+                .and_then(|peer| {
+                    let info = peer.info();
+                    peer.client_id = None;
+                    info
+                })
+        };
+        if let Some(peer) = departed {
+            self.announce_leave(peer).await;
+        }
+    }
+
+    /// 在客户端原来的房间宣布离开
+    async fn announce_leave(&self, peer: PeerInfo) {
+        self.pipeline.disconnect(&peer);
+        let mut leave = Message::new_presence(peer.client_id, PresenceEvent::Leave, None);
+        leave.room = peer.room;
+        leave.bot = peer.bot;
+        self.notify_message(&leave);
+        self.notify_presence(PresenceEvent::Leave, &leave.sender_id, &leave.room);
+        self.broadcast(&leave, Some(&peer.addr)).await;
+        self.relay(&leave).await;
+        self.advertise_routes().await;
+    }
+
+    /// 把输入提示等信号转发给同房间的其他客户端
+    pub(crate) async fn forward_signal(&self, peer_addr: &str, signal: Signal) {
+        // 以登录时的ID为准，未登录的连接不处理
+        let Some(sender) = self.identity(peer_addr).await else {
+            return;
+        };
+        // 输入提示和心跳都算作活动
+        self.touch(peer_addr).await;
+        match signal.kind {
+            SignalKind::Typing => {
+                let signal = Signal::new(sender.client_id, signal.kind);
+                let peers_read = self.peers.read().await;
+                for peer in peers_read.iter().filter(|peer| peer.addr != peer_addr && peer.room == sender.room) {
+                    peer.connection.send_signal(&signal);
+                }
+            }
+            SignalKind::Ping => {}
+        }
+    }
+
+    /// 把新的文本消息写入历史，消息ID重复时返回 false
+    async fn store_text(&self, message: &mut Message) -> bool {
+        let mut history = self.history.write().await;
+        if history.get(&message.id).is_some() {
+            return false;
+        }
+        // 只能回复历史中存在的消息，保证讨论串不会成环
+        if message.reply_to.as_deref().is_some_and(|parent| history.get(parent).is_none()) {
+            message.reply_to = None;
+        }
+        message.reactions.clear();
+        history.push(message.clone());
+        true
+    }
+
+    /// 发布不经过 QUIC 连接的文本消息（如 HTTP API），与客户端消息一样经过钩子、
+    /// 写入历史、广播并转发给联邦对端。被钩子丢弃时返回 None
+    pub(crate) async fn publish(&self, sender: &PeerInfo, mut message: Message) -> Option<Message> {
+        message.sender_id = sender.client_id.clone();
+        message.room = sender.room.clone();
+        message.bot = sender.bot;
+        if !self.pipeline.is_empty() {
+            let (action, replies) = self.pipeline.message(&self.server_id, sender, &mut message);
+            if !replies.is_empty() {
+                info!("发送者不在线，忽略钩子的 {} 条回复", replies.len());
+            }
+            if action == Action::Drop {
+                info!("消息 {} 被钩子丢弃", message.id);
+                return None;
+            }
+        }
+        if !self.store_text(&mut message).await {
+            return None;
+        }
+        if let MessageType::Text { content } = &message.message_type {
+            console::line(format_args!("[{}]: {}", message.sender_id, content));
+        }
+        self.record_mentions(&message).await;
+        self.notify_message(&message);
+        self.broadcast(&message, None).await;
+        self.relay(&message).await;
+        Some(message)
+    }
+
+    /// 某房间最近的历史消息，从旧到新
+    pub(crate) async fn recent(&self, room: &str, count: usize) -> Vec<Message> {
+        self.history.read().await.recent(room, count).into_iter().cloned().collect()
+    }
+
+    /// 把私信投递给本服务器上的收件人，收件人不在本服务器时什么也不做
+    async fn deliver_direct(&self, message: &Message) {
+        let MessageType::Direct { recipient, .. } = &message.message_type else {
+            return;
+        };
+        let peers = self.peers.read().await;
+        for peer in peers.iter().filter(|peer| peer.client_id.as_ref() == Some(recipient)) {
+            if let Err(e) = peer.connection.send(message.clone()).await {
+                METRICS.send_failures.with_label_values(&[peer.label()]).inc();
+                warn!("发送私信到 {} 失败: {}", peer.label(), e);
+            }
+        }
+    }
+
+    /// 直连会合：服务器只把发送者观测到的地址和证书指纹转给对方，之后双方自行打洞，
+    /// 私信不再经过服务器。指纹为空表示拒绝直连。对方不是本服务器上的 QUIC 客户端、
+    /// 发送者不是 QUIC 连接或关闭了会合时，回复发送者无法直连
+    async fn rendezvous(&self, connection: &PeerConnection, sender: &str, peer: &str, fingerprint: &str) {
+        let target = if self.config.read().await.server.rendezvous {
+            let peers = self.peers.read().await;
+            peers
+                .iter()
+                .find(|p| p.client_id.as_deref() == Some(peer) && matches!(p.connection, PeerConnection::Quic(_)))
+                .map(|p| p.connection.clone())
+        } else {
+            None
+        };
+        let (Some(target), PeerConnection::Quic(quic)) = (target, connection) else {
+            info!("{} 无法与 {} 直连", sender, peer);
+            let reply = Message::new(self.server_id.clone(), MessageType::PeerAddress {
+                peer: peer.to_string(),
+                addr: None,
+                fingerprint: String::new(),
+            });
+            let _ = connection.send(reply).await;
+            return;
+        };
+        let addr = (!fingerprint.is_empty()).then(|| quic.remote_address());
+        info!("直连会合 {} ({:?}) -> {}", sender, addr, peer);
+        let message = Message::new(self.server_id.clone(), MessageType::PeerAddress {
+            peer: sender.to_string(),
+            addr,
+            fingerprint: fingerprint.to_string(),
+        });
+        if let Err(e) = target.send(message).await {
+            warn!("转发直连地址到 {} 失败: {}", peer, e);
+        }
+    }
+
+    /// 服务器公告：发到默认房间，并发给所有房间的本地客户端
+    async fn announce(&self, content: &str) {
+        let message = Message::new_text(self.server_id.clone(), content.to_string());
+        self.history.write().await.push(message.clone());
+        self.record_mentions(&message).await;
+        self.notify_message(&message);
+
+        {
+            let peers_read = self.peers.read().await;
+            if peers_read.is_empty() {
+                console::line("没有连接的客户端");
+            } else {
+                console::line(format_args!("发送消息给 {} 个客户端", peers_read.len()));
+                for peer in peers_read.iter() {
+                    if let Err(e) = peer.connection.send(message.clone()).await {
+                        METRICS.send_failures.with_label_values(&[peer.label()]).inc();
+                        warn!("发送消息失败: {}", e);
+                    }
+                }
+            }
+        }
+        self.relay(&message).await;
+    }
+}
+
+/// 配置并创建 [`Server`]，可以注册事件回调
+pub struct ServerBuilder {
+    config: Config,
+    reload: Option<(ConfigLoader, PathBuf)>,
+    hooks: Hooks,
+    pipeline: Pipeline,
+}
+
+impl ServerBuilder {
+    /// 收到 SIGHUP 或配置、证书文件变化时用 `loader` 重新加载配置
+    pub fn with_reload(mut self, loader: ConfigLoader, config_path: PathBuf) -> Self {
+        self.reload = Some((loader, config_path));
+        self
+    }
+
+    /// 房间消息被接受后调用，包括文本、编辑、删除、回应和上下线事件
+    pub fn on_message(mut self, hook: impl Fn(&Message) + Send + Sync + 'static) -> Self {
+        self.hooks.on_message.push(Arc::new(hook));
+        self
+    }
+
+    /// 添加消息处理钩子，按添加顺序执行
+    pub fn hook(mut self, hook: impl MessageHook + 'static) -> Self {
+        self.pipeline.push(Box::new(hook));
+        self
+    }
+
+    /// 本地客户端登录或进入房间后调用，参数为客户端ID和房间
+    pub fn on_join(mut self, hook: impl Fn(&str, &str) + Send + Sync + 'static) -> Self {
+        self.hooks.on_join.push(Arc::new(hook));
+        self
+    }
+
+    /// 本地客户端断开或离开房间后调用，参数为客户端ID和房间
+    pub fn on_leave(mut self, hook: impl Fn(&str, &str) + Send + Sync + 'static) -> Self {
+        self.hooks.on_leave.push(Arc::new(hook));
+        self
+    }
+
+    /// 加载证书并绑定监听地址，需要在 tokio 运行时中调用
+    pub fn build(self) -> Result<Server> {
+        Server::create(self)
+    }
+}
+
+/// t3xt 服务器：接受客户端和联邦对端的连接
 pub struct Server {
     server_id: String,
-    port: u16,
+    bind_addr: SocketAddr,
     endpoint: Endpoint,
-    peers: Arc<RwLock<Vec<Connection>>>,
+    state: Arc<ServerState>,
+    /// 重新加载配置的方式及要监视的配置文件
+    reload: Option<(ConfigLoader, PathBuf)>,
+    /// 由 run 取出交给 TCP 备用传输的任务，server.tcp 关闭时为 None
+    tcp_listener: std::sync::Mutex<Option<tokio::net::TcpListener>>,
+    /// 由 run 取出交给 Webhook 任务
+    webhook_events: std::sync::Mutex<Option<mpsc::UnboundedReceiver<Message>>>,
 }
 
 impl Server {
-    pub fn new(server_id: String, port: u16) -> Result<Self> {
-        let cert_config = crypto::CertConfig::get_or_create()
+    /// 使用默认设置创建服务器，等同于 `Server::builder(config).build()`
+    pub fn new(config: Config) -> Result<Self> {
+        Self::builder(config).build()
+    }
+
+    pub fn builder(config: Config) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            reload: None,
+            hooks: Hooks::default(),
+            pipeline: Pipeline::default(),
+        }
+    }
+
+    fn create(builder: ServerBuilder) -> Result<Self> {
+        let ServerBuilder { config, reload, hooks, pipeline } = builder;
+        let server_id = config.server.id.clone();
+        let cert_config = crypto::CertConfig::get_or_create(&config.tls)
             .context("Failed to get or create certificate")?;
+        console::line(format_args!("🔏 证书指纹 (SHA-256): {}", crypto::certificate_hash(&cert_config.cert)));
+
+        let federation_roots = crypto::federation_root_store(&cert_config, &config.server.peer_certs)?;
+        let federation_config = crypto::create_federation_client_config(&cert_config, federation_roots.clone())?;
 
-        let server_config = crypto::create_server_config(cert_config)
+        let certificate = cert_config.clone();
+        let tls_sessions = crypto::server_sessions();
+        let server_config = crypto::create_server_config(cert_config, federation_roots, Arc::clone(&tls_sessions))
             .context("Failed to create server config")?;
 
-        let bind_addr = format!("0.0.0.0:{}", port);
+        let bind_addr = SocketAddr::new(config.server.bind, config.server.port);
+        // 同一个端点也用于主动连接对端服务器
         let endpoint = Endpoint::server(
-            ServerConfig::with_crypto(Arc::new(server_config)),
-            bind_addr.parse()?,
+            crypto::create_quinn_server_config(server_config.clone(), &config.transport)?,
+            bind_addr,
         ).context("Failed to create server endpoint")?;
+        let federation_client = crypto::create_quinn_client_config(federation_config, &config.transport)?;
+        let tcp_tls = Arc::new(server_config.clone());
+        // TCP 与 QUIC 使用相同的端口号，端口为 0 时跟随系统为 UDP 分配的端口
+        let tcp_listener = if config.server.tcp {
+            let tcp_addr = endpoint.local_addr()?;
+            let listener = std::net::TcpListener::bind(tcp_addr)
+                .with_context(|| format!("Failed to bind TCP listener {}", tcp_addr))?;
+            listener.set_nonblocking(true)?;
+            Some(tokio::net::TcpListener::from_std(listener)?)
+        } else {
+            None
+        };
 
         info!("服务器 {} 启动，监听地址: {}", server_id, bind_addr);
 
+        let (webhooks, webhook_events) = mpsc::unbounded_channel();
         Ok(Self {
+            state: Arc::new(ServerState {
+                server_id: server_id.clone(),
+                peers: RwLock::new(Vec::new()),
+                links: RwLock::new(Vec::new()),
+                routing: Mutex::new(RoutingTable::default()),
+                seen: Mutex::new(SeenMessages::default()),
+                history: RwLock::new(History::new(config.server.history_capacity)),
+                mentions: RwLock::new(HashMap::new()),
+                config: RwLock::new(config),
+                federation_client: RwLock::new(federation_client),
+                tcp_tls: RwLock::new(tcp_tls),
+                certificate: RwLock::new(certificate),
+                tls_sessions,
+                webtransport: RwLock::new(None),
+                hooks,
+                pipeline,
+                webhooks,
+            }),
             server_id,
-            port,
+            bind_addr,
             endpoint,
-            peers: Arc::new(RwLock::new(Vec::new())),
+            reload,
+            tcp_listener: std::sync::Mutex::new(tcp_listener),
+            webhook_events: std::sync::Mutex::new(Some(webhook_events)),
         })
     }
 
+    /// 实际监听的地址，配置端口为 0 时由系统分配
+    pub fn local_addr(&self) -> Result<SocketAddr> {
+        Ok(self.endpoint.local_addr()?)
+    }
+
+    /// 发送服务器公告
+    pub async fn announce(&self, content: &str) {
+        self.state.announce(content).await;
+    }
+
+    /// 运行服务器直到监听端点关闭，不读取标准输入
     pub async fn run(&self) -> Result<()> {
-        println!("服务器 '{}' 启动在端口 {}", self.server_id, self.port);
-        println!("等待客户端连接...");
-        println!("输入消息开始广播，输入 '/quit' 退出");
-        println!("─────────────────────────────");
+        console::line(format_args!("服务器 '{}' 启动在 {}", self.server_id, self.bind_addr));
+        console::line("等待客户端连接...");
+
+        if let Some((loader, config_path)) = &self.reload {
+            let endpoint = self.endpoint.clone();
+            let state = Arc::clone(&self.state);
+            let loader = Arc::clone(loader);
+            let config_path = config_path.clone();
+            tokio::spawn(async move {
+                reload::watch(endpoint, state, loader, config_path).await;
+            });
+        }
+
+        let peers = self.state.config.read().await.server.peers.clone();
+        for addr in peers {
+            let endpoint = self.endpoint.clone();
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                federation::dial(endpoint, state, addr).await;
+            });
+        }
 
         let accept_task = {
             let endpoint = self.endpoint.clone();
-            let peers = Arc::clone(&self.peers);
+            let state = Arc::clone(&self.state);
             tokio::spawn(async move {
-                Self::handle_incoming_connections(endpoint, peers).await;
+                Self::handle_incoming_connections(endpoint, state).await;
             })
         };
 
-        let input_task = {
-            let peers = Arc::clone(&self.peers);
-            let server_id = self.server_id.clone();
+        if let Some(listener) = self.tcp_listener.lock().unwrap().take() {
+            tokio::spawn(crate::tcp::serve(listener, Arc::clone(&self.state)));
+        }
+
+        if let Some(events) = self.webhook_events.lock().unwrap().take() {
+            tokio::spawn(webhooks::run(Arc::clone(&self.state), events));
+        }
+
+        let api = self.state.config.read().await.api.clone();
+        if api.enabled {
+            let state = Arc::clone(&self.state);
             tokio::spawn(async move {
-                Self::handle_user_input(peers, server_id).await;
-            })
+                if let Err(e) = crate::api::serve(api.listen, state).await {
+                    error!("HTTP API 错误: {:#}", e);
+                }
+            });
+        }
+
+        let gateway = self.state.config.read().await.gateway.clone();
+        if gateway.enabled {
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                if let Err(e) = crate::gateway::serve(gateway.listen, state).await {
+                    error!("WebSocket 网关错误: {:#}", e);
+                }
+            });
+        }
+
+        let webtransport = self.state.config.read().await.webtransport.clone();
+        if webtransport.enabled {
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                if let Err(e) = webtransport::serve(webtransport.listen, state).await {
+                    error!("WebTransport 错误: {:#}", e);
+                }
+            });
+        }
+
+        let irc = self.state.config.read().await.irc.clone();
+        if irc.enabled {
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                if let Err(e) = crate::irc::serve(irc.listen, state).await {
+                    error!("IRC 协议桥错误: {:#}", e);
+                }
+            });
+        }
+
+        let (discovery, bind) = {
+            let config = self.state.config.read().await;
+            (config.discovery.clone(), config.server.bind)
         };
+        if discovery.announce {
+            let announcement = crate::discovery::Announcement {
+                server_id: self.server_id.clone(),
+                bind,
+                port: self.local_addr()?.port(),
+            };
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                // 局域网发现只是便利功能，失败不影响服务
+                if let Err(e) = crate::discovery::announce(announcement, state).await {
+                    warn!("局域网宣告失败: {:#}", e);
+                }
+            });
+        }
+
+        let metrics = self.state.config.read().await.metrics.clone();
+        if metrics.enabled {
+            let state = Arc::clone(&self.state);
+            tokio::spawn(async move {
+                if let Err(e) = crate::metrics::serve(metrics.listen, state).await {
+                    error!("指标服务错误: {:#}", e);
+                }
+            });
+        }
 
-        let _ = tokio::try_join!(accept_task, input_task);
+        accept_task.await?;
         Ok(())
     }
 
-    async fn handle_incoming_connections(
-        endpoint: Endpoint,
-        peers: Arc<RwLock<Vec<Connection>>>,
-    ) {
+    async fn handle_incoming_connections(endpoint: Endpoint, state: Arc<ServerState>) {
         while let Some(conn) = endpoint.accept().await {
+            // 等握手完成再读取流。客户端的 0-RTT 数据可能被重放，重放者无法完成握手，这些数据不会被处理
             let connection = match conn.await {
                 Ok(conn) => conn,
                 Err(e) => {
+                    METRICS.connections_rejected.with_label_values(&["handshake"]).inc();
                     error!("连接失败: {}", e);
                     continue;
                 }
@@ -77,49 +1003,81 @@ impl Server {
 
             let remote_addr = connection.remote_address();
             info!("新连接来自: {}", remote_addr);
+
+            if state.config.read().await.policy.banned_ips.contains(&remote_addr.ip()) {
+                METRICS.connections_rejected.with_label_values(&["banned"]).inc();
+                warn!("拒绝被禁止的地址: {}", remote_addr);
+                connection.close(0u32.into(), b"banned");
+                continue;
+            }
+            METRICS.connections_accepted.inc();
+
+            // 出示了受信任证书的是联邦对端服务器
+            if connection.peer_identity().is_some() {
+                let state = Arc::clone(&state);
+                tokio::spawn(async move {
+                    if let Err(e) = federation::run_link(connection, state).await {
+                        warn!("联邦链路 {} 断开: {}", remote_addr, e);
+                    }
+                });
+                continue;
+            }
             
-            // 将连接加入 peers
+            // 将连接加入 peers，客户端发送 Join 后才会广播上线事件
+            state.add_peer(Peer::new(PeerConnection::Quic(connection.clone()))).await;
+            // 该连接上的所有日志都带有对端地址、连接ID，登录后还有客户端ID
+            let span = info_span!(
+                "connection",
+                peer = %remote_addr,
+                conn_id = connection.stable_id(),
+                client_id = tracing::field::Empty,
+            );
+            span.in_scope(|| info!("新客户端连接"));
+
+            // 数据报通道：转发输入提示等短暂事件
             {
-                let mut peers_guard = peers.write().await;
-                peers_guard.push(connection.clone());
+                let connection = connection.clone();
+                let state = Arc::clone(&state);
+                let peer_addr = remote_addr.to_string();
+                tokio::spawn(
+                    async move {
+                        Self::handle_datagrams(connection, state, peer_addr).await;
+                    }
+                    .instrument(span.clone()),
+                );
             }
-            println!("新客户端连接: {}", remote_addr);
 
             // 启动处理该连接的任务
-            let peers = Arc::clone(&peers);
+            let state = Arc::clone(&state);
             let peer_addr = remote_addr.to_string();
-            tokio::spawn(async move {
-                if let Err(e) = Self::handle_connection(connection, peers, peer_addr).await {
-                    error!("处理连接错误: {}", e);
+            tokio::spawn(
+                async move {
+                    if let Err(e) = Self::handle_connection(connection, state, peer_addr).await {
+                        error!("处理连接错误: {}", e);
+                    }
                 }
-            });
+                .instrument(span),
+            );
         }
     }
 
     async fn handle_connection(
         connection: Connection,
-        peers: Arc<RwLock<Vec<Connection>>>,
+        state: Arc<ServerState>,
         peer_addr: String,
     ) -> Result<()> {
+        let peer_connection = PeerConnection::Quic(connection.clone());
         loop {
             match connection.accept_uni().await {
                 Ok(mut recv) => {
-                    match Self::receive_message(&mut recv).await {
+                    let limit = state.config.read().await.transport.max_message_size;
+                    match Self::receive_message(&mut recv, limit).await {
                         Ok(message) => {
-                            // 只处理文本消息
-                            match &message.message_type {
-                                MessageType::Text { content } => {
-                                    println!("[{}]: {}", message.sender_id, content);
-
-                                    // 广播给其他连接的客户端（不包括发送者）
-                                    let peers_read = peers.read().await;
-                                    for other_conn in peers_read.iter() {
-                                        if other_conn.remote_address().to_string() != peer_addr {
-                                            let _ = Self::send_message(other_conn, message.clone()).await;
-                                        }
-                                    }
-                                }
-                            }
+                            let span = info_span!("handle_message", message_id = %message.id, kind = message.kind());
+                            telemetry::set_parent(&span, &message);
+                            Self::handle_message(&peer_connection, &state, &peer_addr, message)
+                                .instrument(span)
+                                .await;
                         }
                         Err(e) => {
                             warn!("解析消息失败 from {}: {}", peer_addr, e);
@@ -133,51 +1091,372 @@ impl Server {
             }
         }
         
-        {
-            let mut peers_guard = peers.write().await;
-            peers_guard.retain(|conn| conn.remote_address().to_string() != peer_addr);
+        state.remove_peer(&peer_addr).await;
+        Ok(())
+    }
+
+    async fn handle_datagrams(connection: Connection, state: Arc<ServerState>, peer_addr: String) {
+        while let Ok(datagram) = connection.read_datagram().await {
+            let signal = match Signal::from_bytes(&datagram) {
+                Ok(signal) => signal,
+                Err(e) => {
+                    warn!("解析数据报失败 from {}: {}", peer_addr, e);
+                    continue;
+                }
+            };
+            state.forward_signal(&peer_addr, signal).await;
         }
-        println!("客户端 '{}' 断开连接", peer_addr);
+    }
 
-        Ok(())
+    pub(crate) async fn handle_message(
+        connection: &PeerConnection,
+        state: &ServerState,
+        peer_addr: &str,
+        mut message: Message,
+    ) {
+        METRICS.messages_received.with_label_values(&[message.kind()]).inc();
+        if let MessageType::Presence { event: PresenceEvent::Join, .. } = &message.message_type {
+            Self::handle_join(connection, state, peer_addr, message).await;
+            return;
+        }
+
+        // 登录之后才处理其他消息，发送者和房间以服务器记录为准
+        let Some(peer) = state.identity(peer_addr).await else {
+            warn!("忽略未登录连接的消息: {}", peer_addr);
+            return;
+        };
+        // 服务器消息不经过钩子，也不通知和转发
+        if message.is_server_only() {
+            warn!("忽略来自客户端的服务器消息: {}", peer_addr);
+            return;
+        }
+        if !state.take_token(peer_addr).await {
+            warn!("{} 发送过于频繁，丢弃消息", peer.client_id);
+            let notice = Message::new_text(state.server_id.clone(), "发送过于频繁，消息已丢弃".to_string());
+            let _ = connection.send(notice).await;
+            return;
+        }
+        message.sender_id = peer.client_id.clone();
+        message.room = peer.room.clone();
+        message.bot = peer.bot;
+        // 发送时间以服务器收到时为准，不信任客户端的时钟
+        message.timestamp = Utc::now();
+        message.origin = None;
+        message.via.clear();
+
+        if !state.pipeline.is_empty() {
+            let (action, replies) = state.pipeline.message(&state.server_id, &peer, &mut message);
+            for reply in replies {
+                if let Err(e) = connection.send(reply).await {
+                    warn!("发送钩子回复失败 to {}: {}", peer_addr, e);
+                }
+            }
+            if action == Action::Drop {
+                info!("消息 {} 被钩子丢弃", message.id);
+                return;
+            }
+        }
+
+        match &message.message_type {
+            MessageType::Text { content } => {
+                let content = content.clone();
+                if !state.store_text(&mut message).await {
+                    warn!("忽略重复的消息ID {} from {}", message.id, peer_addr);
+                    return;
+                }
+                info!(id = %message.id, "收到消息");
+                console::line(format_args!("[{}]: {}", message.sender_id, content));
+                state.touch(peer_addr).await;
+                state.record_mentions(&message).await;
+
+                // 广播给其他连接的客户端（不包括发送者）
+                state.broadcast(&message, Some(peer_addr)).await;
+            }
+            MessageType::Presence { event, status } => {
+                let event = match event {
+                    // 客户端不能代替服务器宣布离线，断开连接时由服务器生成
+                    PresenceEvent::Leave | PresenceEvent::Join => return,
+                    event => *event,
+                };
+                {
+                    let mut peers_guard = state.peers.write().await;
+                    let Some(peer) = peers_guard.iter_mut().find(|peer| peer.addr == peer_addr) else {
+                        return;
+                    };
+                    peer.away = event == PresenceEvent::Away;
+                    peer.status = status.clone();
+                    peer.last_active = Instant::now();
+                }
+                console::message(&message, None);
+
+                let mut presence = Message::new_presence(message.sender_id.clone(), event, status.clone());
+                presence.room = message.room.clone();
+                presence.bot = message.bot;
+                state.notify_message(&presence);
+                state.broadcast(&presence, Some(peer_addr)).await;
+                state.relay(&presence).await;
+                return;
+            }
+            MessageType::Direct { recipient, content } => {
+                if content.is_empty() || recipient == &message.sender_id {
+                    return;
+                }
+                if !state.is_online(recipient).await {
+                    let notice = Message::new_text(state.server_id.clone(), format!("{} 不在线，私信未送达", recipient));
+                    let _ = connection.send(notice).await;
+                    return;
+                }
+                info!("私信 {} -> {}", message.sender_id, recipient);
+                state.touch(peer_addr).await;
+                state.deliver_direct(&message).await;
+            }
+            MessageType::PeerAddress { peer, fingerprint, .. } => {
+                state.rendezvous(connection, &message.sender_id, peer, fingerprint).await;
+                return;
+            }
+            MessageType::WhoRequest => {
+                let members = state.members(Some(&message.room)).await;
+                let limit = state.config.read().await.transport.max_message_size;
+                // 成员多时分成几条响应，每条都在消息大小上限之内
+                let (chunks, _) = fit_message_size(members, limit, |members| {
+                    Message::new(String::new(), MessageType::WhoResponse { members })
+                });
+                for response in chunks {
+                    if let Err(e) = connection.send(response).await {
+                        warn!("发送 /who 响应失败 to {}: {}", peer_addr, e);
+                        break;
+                    }
+                }
+            }
+            MessageType::MentionsRequest => {
+                let pending: Vec<Message> = state.mentions.read().await
+                    .get(&message.sender_id)
+                    .map(|inbox| inbox.iter().cloned().collect())
+                    .unwrap_or_default();
+                let limit = state.config.read().await.transport.max_message_size;
+                let (pages, oversized) = fit_message_size(pending, limit, |messages| {
+                    Message::new(String::new(), MessageType::MentionsResponse { messages })
+                });
+                if !oversized.is_empty() {
+                    warn!("{} 条提及超过消息大小上限，不再投递给 {}", oversized.len(), message.sender_id);
+                    state.remove_mentions(&message.sender_id, &oversized).await;
+                }
+                // 发送成功后才从收件箱移除，发送失败的留到下次请求
+                for page in pages {
+                    let MessageType::MentionsResponse { messages } = &page.message_type else {
+                        continue;
+                    };
+                    let delivered = messages.clone();
+                    if let Err(e) = connection.send(page).await {
+                        warn!("发送 /mentions 响应失败 to {}: {}", peer_addr, e);
+                        break;
+                    }
+                    state.remove_mentions(&message.sender_id, &delivered).await;
+                }
+            }
+            MessageType::Edit { target_id, content } => {
+                {
+                    let config = state.config.read().await;
+                    let mut history = state.history.write().await;
+                    if !Self::may_modify(&config, &history, target_id, &message.sender_id) {
+                        warn!("{} 无权编辑消息 {}", message.sender_id, target_id);
+                        return;
+                    }
+                    // 发给原消息所在的房间，而不是发送者当前的房间
+                    if let Some(target) = history.get(target_id) {
+                        message.room = target.room.clone();
+                    }
+                    history.edit(target_id, content);
+                }
+                console::message(&message, None);
+                state.touch(peer_addr).await;
+                state.broadcast(&message, Some(peer_addr)).await;
+            }
+            MessageType::Delete { target_id } => {
+                {
+                    let config = state.config.read().await;
+                    let mut history = state.history.write().await;
+                    if !Self::may_modify(&config, &history, target_id, &message.sender_id) {
+                        warn!("{} 无权删除消息 {}", message.sender_id, target_id);
+                        return;
+                    }
+                    if let Some(target) = history.get(target_id) {
+                        message.room = target.room.clone();
+                    }
+                    history.delete(target_id);
+                }
+                console::message(&message, None);
+                state.broadcast(&message, Some(peer_addr)).await;
+            }
+            MessageType::Reaction { target_id, emoji } => {
+                if emoji.is_empty() || emoji.chars().count() > 8 {
+                    return;
+                }
+                {
+                    let mut history = state.history.write().await;
+                    if !history.react(target_id, emoji, &message.sender_id) {
+                        return;
+                    }
+                    // 与编辑一样发给原消息所在的房间
+                    if let Some(target) = history.get(target_id) {
+                        message.room = target.room.clone();
+                    }
+                }
+                console::message(&message, None);
+                state.broadcast(&message, Some(peer_addr)).await;
+            }
+            MessageType::WhoResponse { .. }
+            | MessageType::MentionsResponse { .. }
+            | MessageType::ServerHello { .. }
+            | MessageType::RouteUpdate { .. }
+            | MessageType::Tombstone => return,
+        }
+
+        state.notify_message(&message);
+        state.relay(&message).await;
     }
 
-    async fn handle_user_input(
-        peers: Arc<RwLock<Vec<Connection>>>,
-        server_id: String,
+    /// 客户端登录或切换房间：记录ID，回放房间历史，并广播上线事件
+    async fn handle_join(
+        connection: &PeerConnection,
+        state: &ServerState,
+        peer_addr: &str,
+        message: Message,
     ) {
-        let stdin = tokio::io::stdin();
-        let mut lines = BufReader::new(stdin).lines();
-        
-        while let Ok(Some(line)) = lines.next_line().await {
+        let MessageType::Presence { status, .. } = message.message_type else {
+            return;
+        };
+        if state.config.read().await.policy.banned_ids.contains(&message.sender_id) {
+            warn!("拒绝被禁止的客户端: {} ({})", message.sender_id, peer_addr);
+            connection.close("banned");
+            return;
+        }
+        if !is_valid_id(&message.sender_id) {
+            warn!("拒绝无效的客户端ID: {:?} ({})", message.sender_id, peer_addr);
+            let notice = Message::new_text(state.server_id.clone(), format!("ID {:?} 无效", message.sender_id));
+            let _ = connection.send(notice).await;
+            connection.close("invalid id");
+            return;
+        }
+        if state.is_reserved(&message.sender_id).await {
+            warn!("拒绝使用 API 发送者ID登录: {} ({})", message.sender_id, peer_addr);
+            let notice = Message::new_text(state.server_id.clone(), format!("ID {} 保留给 HTTP API", message.sender_id));
+            let _ = connection.send(notice).await;
+            connection.close("reserved id");
+            return;
+        }
+        // 同一 ID 只能有一个在线连接，编辑、删除等权限都以 ID 判断；同一连接重新登录（切换房间）不受影响
+        let remote = message.sender_id == state.server_id
+            || state.routing.lock().await.is_reachable(&message.sender_id);
+        let room = message.room;
+        let previous = {
+            let mut peers_guard = state.peers.write().await;
+            let taken = peers_guard.iter().any(|peer| {
+                peer.addr != peer_addr && peer.client_id.as_deref() == Some(message.sender_id.as_str())
+            });
+            if remote || taken {
+                drop(peers_guard);
+                warn!("拒绝重复的客户端ID: {} ({})", message.sender_id, peer_addr);
+                let notice = Message::new_text(state.server_id.clone(), format!("ID {} 已在线", message.sender_id));
+                let _ = connection.send(notice).await;
+                connection.close("duplicate id");
+                return;
+            }
+            let Some(peer) = peers_guard.iter_mut().find(|peer| peer.addr == peer_addr) else {
+                return;
+            };
+            let previous = peer.info();
+            peer.client_id = Some(message.sender_id.clone());
+            peer.room = room.clone();
+            peer.bot = message.bot;
+            peer.status = status.clone();
+            peer.last_active = Instant::now();
+            previous
+        };
+        Span::current().record("client_id", message.sender_id.as_str());
+        if previous.is_none() {
+            state.pipeline.connect(&PeerInfo {
+                addr: peer_addr.to_string(),
+                client_id: message.sender_id.clone(),
+                room: room.clone(),
+                bot: message.bot,
+            });
+        }
+
+        // 切换房间时先在原房间宣布离开
+        if let Some(previous) = previous {
+            let mut leave = Message::new_presence(previous.client_id, PresenceEvent::Leave, None);
+            leave.room = previous.room;
+            leave.bot = previous.bot;
+            state.notify_message(&leave);
+            state.notify_presence(PresenceEvent::Leave, &leave.sender_id, &leave.room);
+            state.broadcast(&leave, Some(peer_addr)).await;
+            state.relay(&leave).await;
+        }
+
+        let replay_count = state.config.read().await.server.history_replay;
+        for message in state.recent(&room, replay_count).await {
+            if let Err(e) = connection.send(message).await {
+                warn!("回放历史失败 to {}: {}", peer_addr, e);
+                break;
+            }
+        }
+
+        let mut presence = Message::new_presence(message.sender_id, PresenceEvent::Join, status);
+        presence.room = room;
+        presence.bot = message.bot;
+        console::message(&presence, None);
+        state.notify_message(&presence);
+        state.notify_presence(PresenceEvent::Join, &presence.sender_id, &presence.room);
+        state.broadcast(&presence, Some(peer_addr)).await;
+        state.relay(&presence).await;
+        state.advertise_routes().await;
+    }
+
+    /// 只有原发送者或管理员可以修改消息，且目标必须是未删除的文本消息
+    fn may_modify(config: &Config, history: &History, target_id: &str, client_id: &str) -> bool {
+        let Some(target) = history.get(target_id) else {
+            return false;
+        };
+        if !matches!(target.message_type, MessageType::Text { .. }) {
+            return false;
+        }
+        target.sender_id == client_id || config.server.moderators.iter().any(|id| id == client_id)
+    }
+
+    /// 管理员控制台：`/who` 查看在线成员，其他输入作为公告发送。
+    /// 输入 /quit 时返回；输入结束（如后台运行）后不再返回，服务器继续运行
+    pub async fn console(&self, mut input: impl Stream<Item = String> + Unpin) {
+        console::line("输入消息开始广播，输入 '/who' 查看在线成员，输入 '/quit' 退出");
+        console::line("─────────────────────────────");
+
+        while let Some(line) = input.next().await {
             let input = line.trim();
-            
+
             if input == "/quit" {
                 info!("服务器退出");
-                std::process::exit(0);
+                return;
             }
-            
-            if input.is_empty() {
+
+            if input == "/who" {
+                let members = self.state.members(None).await;
+                console::message(&Message::new(self.server_id.clone(), MessageType::WhoResponse { members }), None);
                 continue;
             }
-            
-            let message = Message::new_text(server_id.clone(), input.to_string());
-            
-            let peers_read = peers.read().await;
-            if peers_read.is_empty() {
-                println!("没有连接的客户端");
-            } else {
-                println!("发送消息给 {} 个客户端", peers_read.len());
-                for connection in peers_read.iter() {
-                    if let Err(e) = Self::send_message(connection, message.clone()).await {
-                        warn!("发送消息失败: {}", e);
-                    }
-                }
+
+            if input.is_empty() {
+                continue;
             }
+
+            self.announce(input).await;
         }
+        std::future::pending::<()>().await;
     }
 
-    async fn send_message(connection: &Connection, message: Message) -> Result<()> {
+    /// 每次发送是一个 span，接收方以它为父级，从而得到每一跳的投递延迟
+    #[instrument(name = "deliver", skip_all, fields(peer = %connection.remote_address(), message_id = %message.id))]
+    pub(crate) async fn send_message(connection: &Connection, mut message: Message) -> Result<()> {
+        telemetry::inject(&mut message);
         let mut send = connection.open_uni().await
             .context("Failed to open stream")?;
         
@@ -187,14 +1466,16 @@ impl Server {
         
         send.finish().await
             .context("Failed to finish stream")?;
+        METRICS.bytes_sent.inc_by(data.len() as u64);
         
         Ok(())
     }
 
-    async fn receive_message(recv: &mut quinn::RecvStream) -> Result<Message> {
-        let data = recv.read_to_end(8192).await
+    pub(crate) async fn receive_message(recv: &mut quinn::RecvStream, limit: usize) -> Result<Message> {
+        let data = recv.read_to_end(limit).await
             .context("Failed to read message")?;
+        METRICS.bytes_received.inc_by(data.len() as u64);
         
         Message::from_bytes(&data)
     }
-}
\ No newline at end of file
+}
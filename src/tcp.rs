@@ -0,0 +1,238 @@
+use crate::{
+    message::*,
+    metrics::METRICS,
+    server::{Outgoing, Peer, PeerConnection, Server, ServerState},
+    telemetry,
+};
+use anyhow::{bail, Context, Result};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    time::timeout,
+};
+use tokio_rustls::TlsAcceptor;
+use tracing::{info, info_span, warn, Instrument};
+
+/// 等待 TLS 握手完成的时间
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// TCP 备用传输上的一帧：1 字节类型、4 字节大端长度，之后是内容。
+///
+/// 消息和信号的内容与 QUIC 上的 JSON 相同；TCP 没有 QUIC 的空闲超时，由双方定期发送 Ping 保活
+#[derive(Debug)]
+pub(crate) enum Frame {
+    Message(Vec<u8>),
+    Signal(Vec<u8>),
+    Ping,
+    /// 服务器主动断开，附带原因
+    Close(String),
+}
+
+impl Frame {
+    fn kind(&self) -> u8 {
+        match self {
+            Frame::Message(_) => 0,
+            Frame::Signal(_) => 1,
+            Frame::Ping => 2,
+            Frame::Close(_) => 3,
+        }
+    }
+
+    fn payload(&self) -> &[u8] {
+        match self {
+            Frame::Message(data) | Frame::Signal(data) => data,
+            Frame::Ping => &[],
+            Frame::Close(reason) => reason.as_bytes(),
+        }
+    }
+}
+
+/// 写入一帧，返回写入的字节数
+pub(crate) async fn write_frame(writer: &mut (impl AsyncWrite + Unpin), frame: &Frame) -> Result<usize> {
+    let payload = frame.payload();
+    let mut data = Vec::with_capacity(5 + payload.len());
+    data.push(frame.kind());
+    data.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    data.extend_from_slice(payload);
+    writer.write_all(&data).await.context("Failed to write frame")?;
+    writer.flush().await.context("Failed to write frame")?;
+    Ok(data.len())
+}
+
+/// 读取一帧，对端在帧边界关闭时返回 None
+pub(crate) async fn read_frame(reader: &mut (impl AsyncRead + Unpin), limit: usize) -> Result<Option<Frame>> {
+    let mut header = [0u8; 5];
+    match reader.read_exact(&mut header).await {
+        Ok(_) => {}
+        Err(e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(e) => return Err(e).context("Failed to read frame"),
+    }
+    let len = u32::from_be_bytes([header[1], header[2], header[3], header[4]]) as usize;
+    if len > limit {
+        bail!("Frame exceeds {} bytes", limit);
+    }
+    let mut payload = vec![0u8; len];
+    reader.read_exact(&mut payload).await.context("Failed to read frame")?;
+    METRICS.bytes_received.inc_by(payload.len() as u64);
+    Ok(Some(match header[0] {
+        0 => Frame::Message(payload),
+        1 => Frame::Signal(payload),
+        2 => Frame::Ping,
+        3 => Frame::Close(String::from_utf8_lossy(&payload).into_owned()),
+        kind => bail!("Unknown frame type {}", kind),
+    }))
+}
+
+/// 在与 QUIC 相同的端口上接受 TCP+TLS 连接，供 UDP 被阻断的客户端使用
+pub(crate) async fn serve(listener: TcpListener, state: Arc<ServerState>) {
+    if let Ok(addr) = listener.local_addr() {
+        info!("TCP 备用传输监听地址: {}", addr);
+    }
+    loop {
+        let (stream, remote) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("接受 TCP 连接失败: {}", e);
+                continue;
+            }
+        };
+        if state.config.read().await.policy.banned_ips.contains(&remote.ip()) {
+            METRICS.connections_rejected.with_label_values(&["banned"]).inc();
+            warn!("拒绝被禁止的地址: {}", remote);
+            continue;
+        }
+        tokio::spawn(handle_connection(stream, Arc::clone(&state), remote));
+    }
+}
+
+/// 完成 TLS 握手后与 QUIC 客户端一样登记到客户端列表
+async fn handle_connection(stream: TcpStream, state: Arc<ServerState>, remote: SocketAddr) {
+    let _ = stream.set_nodelay(true);
+    let acceptor = TlsAcceptor::from(Arc::clone(&*state.tcp_tls.read().await));
+    let stream = match timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+        Ok(Ok(stream)) => stream,
+        Ok(Err(e)) => {
+            METRICS.connections_rejected.with_label_values(&["handshake"]).inc();
+            warn!("TCP 连接失败 from {}: {}", remote, e);
+            return;
+        }
+        Err(_) => {
+            METRICS.connections_rejected.with_label_values(&["handshake"]).inc();
+            warn!("TCP 握手超时 from {}", remote);
+            return;
+        }
+    };
+    METRICS.connections_accepted.inc();
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let connection = PeerConnection::Gateway {
+        transport: "tcp",
+        remote,
+        outgoing: tx,
+    };
+    let peer_addr = connection.key();
+    state.add_peer(Peer::new(connection.clone())).await;
+    let span = info_span!(
+        "connection",
+        peer = %peer_addr,
+        transport = "tcp",
+        client_id = tracing::field::Empty,
+    );
+    span.in_scope(|| info!("新客户端连接"));
+
+    async {
+        if let Err(e) = run(stream, rx, &connection, &state, &peer_addr).await {
+            warn!("TCP 连接错误: {:#}", e);
+        }
+        state.remove_peer(&peer_addr).await;
+    }
+    .instrument(span)
+    .await;
+}
+
+async fn run(
+    stream: tokio_rustls::server::TlsStream<TcpStream>,
+    mut outgoing: mpsc::UnboundedReceiver<Outgoing>,
+    connection: &PeerConnection,
+    state: &ServerState,
+    peer_addr: &str,
+) -> Result<()> {
+    let (limit, idle_timeout, keep_alive) = {
+        let config = state.config.read().await;
+        (
+            config.transport.max_message_size,
+            Duration::from_secs(config.transport.idle_timeout_secs),
+            Duration::from_secs(config.transport.keep_alive_secs),
+        )
+    };
+    let (mut reader, mut writer) = tokio::io::split(stream);
+    // 读取放在单独的任务中，避免 select! 取消读到一半的帧
+    let (frames_tx, mut frames) = mpsc::channel(32);
+    let read_task = tokio::spawn(async move {
+        loop {
+            let frame = match timeout(idle_timeout, read_frame(&mut reader, limit)).await {
+                Ok(frame) => frame,
+                Err(_) => Err(anyhow::anyhow!("Idle timeout")),
+            };
+            let done = !matches!(frame, Ok(Some(_)));
+            if frames_tx.send(frame).await.is_err() || done {
+                return;
+            }
+        }
+    });
+    let mut ping = tokio::time::interval(keep_alive);
+
+    let result = async {
+        loop {
+            tokio::select! {
+                frame = frames.recv() => {
+                    let Some(frame) = frame.transpose()? else {
+                        return Ok(());
+                    };
+                    match frame {
+                        Some(Frame::Message(data)) => match Message::from_bytes(&data) {
+                            Ok(message) => {
+                                let span = info_span!("handle_message", message_id = %message.id, kind = message.kind());
+                                telemetry::set_parent(&span, &message);
+                                Server::handle_message(connection, state, peer_addr, message)
+                                    .instrument(span)
+                                    .await;
+                            }
+                            Err(e) => warn!("解析消息失败 from {}: {}", peer_addr, e),
+                        },
+                        Some(Frame::Signal(data)) => match Signal::from_bytes(&data) {
+                            Ok(signal) => state.forward_signal(peer_addr, signal).await,
+                            Err(e) => warn!("解析信号失败 from {}: {}", peer_addr, e),
+                        },
+                        Some(Frame::Ping) => {}
+                        Some(Frame::Close(_)) | None => return Ok(()),
+                    }
+                }
+                Some(outgoing) = outgoing.recv() => {
+                    let frame = match outgoing {
+                        Outgoing::Message(mut message) => {
+                            telemetry::inject(&mut message);
+                            Frame::Message(message.to_bytes()?)
+                        }
+                        Outgoing::Signal(signal) => Frame::Signal(signal.to_bytes()?),
+                        Outgoing::Close(reason) => {
+                            write_frame(&mut writer, &Frame::Close(reason.to_string())).await?;
+                            let _ = writer.shutdown().await;
+                            return Ok(());
+                        }
+                    };
+                    let sent = write_frame(&mut writer, &frame).await?;
+                    METRICS.bytes_sent.inc_by(sent as u64);
+                }
+                _ = ping.tick() => {
+                    write_frame(&mut writer, &Frame::Ping).await?;
+                }
+            }
+        }
+    }
+    .await;
+    read_task.abort();
+    result
+}
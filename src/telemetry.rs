@@ -0,0 +1,46 @@
+use crate::{config::TelemetrySettings, message::Message};
+use anyhow::{Context, Result};
+use opentelemetry::{global, KeyValue};
+use opentelemetry_otlp::{SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{propagation::TraceContextPropagator, trace::SdkTracerProvider, Resource};
+use tracing::{warn, Span};
+use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+/// 通过 OTLP/HTTP 导出 span，并启用 W3C trace context 传播。`instance_id` 为服务器或客户端ID
+pub fn tracer_provider(settings: &TelemetrySettings, instance_id: &str) -> Result<SdkTracerProvider> {
+    let exporter = SpanExporter::builder()
+        .with_http()
+        .with_endpoint(settings.endpoint.clone())
+        .build()
+        .context("Failed to create OTLP exporter")?;
+    let resource = Resource::builder()
+        .with_service_name(settings.service_name.clone())
+        .with_attribute(KeyValue::new("service.instance.id", instance_id.to_string()))
+        .build();
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_resource(resource)
+        .build();
+
+    global::set_text_map_propagator(TraceContextPropagator::new());
+    Ok(provider)
+}
+
+/// 把当前 span 写入消息，下一跳的处理 span 会成为它的子 span。未启用导出时不写入任何内容
+pub fn inject(message: &mut Message) {
+    let context = Span::current().context();
+    global::get_text_map_propagator(|propagator| {
+        propagator.inject_context(&context, &mut message.trace_context);
+    });
+}
+
+/// 以消息携带的 trace context 作为 `span` 的父级
+pub fn set_parent(span: &Span, message: &Message) {
+    if message.trace_context.is_empty() {
+        return;
+    }
+    let parent = global::get_text_map_propagator(|propagator| propagator.extract(&message.trace_context));
+    if let Err(e) = span.set_parent(parent) {
+        warn!("设置 trace 父级失败: {}", e);
+    }
+}
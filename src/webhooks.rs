@@ -0,0 +1,197 @@
+use crate::{
+    config::{WebhookSettings, WebhookTarget},
+    message::*,
+    metrics::METRICS,
+    server::ServerState,
+};
+use anyhow::{Context, Result};
+use chrono::Utc;
+use reqwest::{header::CONTENT_TYPE, StatusCode};
+use serde::Serialize;
+use std::{path::Path, sync::Arc, time::Duration};
+use tokio::{fs, io::AsyncWriteExt, sync::mpsc};
+use tracing::{error, info, warn};
+use uuid::Uuid;
+
+/// 请求体的 HMAC-SHA256 签名，格式为 "sha256=<十六进制>"
+pub const SIGNATURE_HEADER: &str = "X-T3xt-Signature";
+/// 事件类型：message、join 或 leave
+pub const EVENT_HEADER: &str = "X-T3xt-Event";
+/// 每个事件的唯一ID，重试时不变，接收方可据此去重
+pub const DELIVERY_HEADER: &str = "X-T3xt-Delivery";
+
+/// POST 给 Webhook 的 JSON
+#[derive(Serialize)]
+struct Payload<'a> {
+    event: &'a str,
+    server_id: &'a str,
+    message: &'a Message,
+}
+
+/// 写入死信文件的一行
+#[derive(Serialize)]
+struct DeadLetter<'a> {
+    failed_at: String,
+    url: &'a str,
+    event: &'a str,
+    delivery: &'a str,
+    attempts: u32,
+    error: &'a str,
+    payload: serde_json::Value,
+}
+
+/// 一个事件发往一个地址的投递任务
+struct Delivery {
+    target: WebhookTarget,
+    event: &'static str,
+    id: String,
+    body: Arc<Vec<u8>>,
+}
+
+/// 会推送给 Webhook 的事件类型，私信和其他消息不推送
+fn event_name(message: &Message) -> Option<&'static str> {
+    match &message.message_type {
+        MessageType::Text { .. } => Some("message"),
+        MessageType::Presence { event: PresenceEvent::Join, .. } => Some("join"),
+        MessageType::Presence { event: PresenceEvent::Leave, .. } => Some("leave"),
+        _ => None,
+    }
+}
+
+fn matches(target: &WebhookTarget, event: &str, message: &Message) -> bool {
+    if !target.rooms.is_empty() && !target.rooms.contains(&message.room) {
+        return false;
+    }
+    let MessageType::Text { content } = &message.message_type else {
+        return target.presence && event != "message";
+    };
+    if target.mentions.is_empty() && target.keywords.is_empty() {
+        return true;
+    }
+    let content = content.to_lowercase();
+    target.mentions.iter().any(|id| message.mentions_user(id))
+        || target.keywords.iter().any(|keyword| content.contains(&keyword.to_lowercase()))
+}
+
+/// 计算 `SIGNATURE_HEADER` 的值，密钥为空时不签名
+pub fn sign(secret: &str, body: &[u8]) -> Option<String> {
+    if secret.is_empty() {
+        return None;
+    }
+    let key = ring::hmac::Key::new(ring::hmac::HMAC_SHA256, secret.as_bytes());
+    let tag = ring::hmac::sign(&key, body);
+    let hex: String = tag.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some(format!("sha256={}", hex))
+}
+
+/// 从队列中取出已被服务器接受的消息，按当前配置推送给匹配的地址
+pub(crate) async fn run(state: Arc<ServerState>, mut events: mpsc::UnboundedReceiver<Message>) {
+    let client = reqwest::Client::new();
+    while let Some(message) = events.recv().await {
+        let Some(event) = event_name(&message) else {
+            continue;
+        };
+        let settings = Arc::new(state.config.read().await.webhooks.clone());
+        let targets: Vec<WebhookTarget> = settings
+            .targets
+            .iter()
+            .filter(|target| matches(target, event, &message))
+            .cloned()
+            .collect();
+        if targets.is_empty() {
+            continue;
+        }
+        let payload = Payload {
+            event,
+            server_id: &state.server_id,
+            message: &message,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => Arc::new(body),
+            Err(e) => {
+                error!("序列化 Webhook 事件失败: {}", e);
+                continue;
+            }
+        };
+        let id = Uuid::new_v4().to_string();
+        for target in targets {
+            let delivery = Delivery {
+                target,
+                event,
+                id: id.clone(),
+                body: Arc::clone(&body),
+            };
+            // 每个地址单独重试，慢的地址不影响其他地址
+            tokio::spawn(deliver(client.clone(), Arc::clone(&settings), delivery));
+        }
+    }
+}
+
+/// 投递一次事件，失败时按指数退避重试，用尽次数后写入死信文件
+async fn deliver(client: reqwest::Client, settings: Arc<WebhookSettings>, delivery: Delivery) {
+    let url = delivery.target.url.as_str();
+    let signature = sign(&delivery.target.secret, &delivery.body);
+    let mut backoff = Duration::from_millis(settings.retry_backoff_ms);
+    let mut attempts = 0;
+    let error = loop {
+        attempts += 1;
+        let mut request = client
+            .post(url)
+            .timeout(Duration::from_secs(settings.timeout_secs))
+            .header(CONTENT_TYPE, "application/json")
+            .header(EVENT_HEADER, delivery.event)
+            .header(DELIVERY_HEADER, &delivery.id)
+            .body(delivery.body.to_vec());
+        if let Some(signature) = &signature {
+            request = request.header(SIGNATURE_HEADER, signature);
+        }
+        let (error, retryable) = match request.send().await {
+            Ok(response) if response.status().is_success() => {
+                METRICS.webhook_deliveries.with_label_values(&["delivered"]).inc();
+                info!(url, delivery = %delivery.id, "Webhook 已投递");
+                return;
+            }
+            // 除限流外的 4xx 重试也不会成功
+            Ok(response) => {
+                let status = response.status();
+                let retryable = status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS;
+                (format!("HTTP {}", status), retryable)
+            }
+            Err(e) => (e.to_string(), true),
+        };
+        if !retryable || attempts >= settings.max_attempts {
+            break error;
+        }
+        METRICS.webhook_deliveries.with_label_values(&["retried"]).inc();
+        warn!(url, attempt = attempts, "投递 Webhook 失败，{}ms 后重试: {}", backoff.as_millis(), error);
+        tokio::time::sleep(backoff).await;
+        backoff *= 2;
+    };
+
+    METRICS.webhook_deliveries.with_label_values(&["dead_letter"]).inc();
+    error!(url, attempts, "投递 Webhook 失败，写入死信文件: {}", error);
+    let line = DeadLetter {
+        failed_at: Utc::now().to_rfc3339(),
+        url,
+        event: delivery.event,
+        delivery: &delivery.id,
+        attempts,
+        error: &error,
+        payload: serde_json::from_slice(&delivery.body).unwrap_or_default(),
+    };
+    if let Err(e) = append_dead_letter(&settings.dead_letter, &line).await {
+        error!("写入死信文件 {} 失败: {:#}", settings.dead_letter.display(), e);
+    }
+}
+
+async fn append_dead_letter(path: &Path, line: &DeadLetter<'_>) -> Result<()> {
+    if let Some(parent) = path.parent().filter(|parent| !parent.as_os_str().is_empty()) {
+        fs::create_dir_all(parent).await.context("Failed to create directory")?;
+    }
+    let mut data = serde_json::to_vec(line)?;
+    data.push(b'\n');
+    // 一次写入整行，并发追加时各行不会交错
+    let mut file = fs::OpenOptions::new().create(true).append(true).open(path).await?;
+    file.write_all(&data).await?;
+    Ok(())
+}
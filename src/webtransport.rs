@@ -0,0 +1,374 @@
+use crate::{
+    crypto::{self, CertConfig},
+    http3,
+    message::*,
+    metrics::METRICS,
+    server::{Outgoing, Peer, PeerConnection, Server, ServerState},
+    telemetry,
+};
+use anyhow::{Context, Result};
+use bytes::Bytes;
+use quinn::{Connection, Endpoint, RecvStream, ServerConfig};
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+use tokio::{sync::mpsc, time};
+use tracing::{info, info_span, warn, Instrument};
+
+/// 短期证书的有效期。浏览器只对 ECDSA 且有效期不超过 14 天的证书接受 `serverCertificateHashes`
+const CERT_LIFETIME: Duration = Duration::from_secs(13 * 24 * 3600);
+
+/// 更换短期证书的间隔，新连接使用新证书，已建立的会话不受影响
+const CERT_ROTATION: Duration = Duration::from_secs(24 * 3600);
+
+/// 请求流之外最多同时打开的双向流，WebTransport 双向流不受支持，打开后立即拒绝
+const MAX_BIDI_STREAMS: u32 = 4;
+
+/// 网关告诉浏览器如何连接：实际监听的端口和当前证书的 SHA-256
+pub(crate) struct Listener {
+    pub(crate) port: u16,
+    /// 只在证书可以按哈希固定时提供，CA 签发的证书由浏览器按常规方式验证
+    pub(crate) certificate_hash: Option<String>,
+    /// 是否为定期更换的短期证书
+    short_lived: bool,
+    endpoint: Endpoint,
+}
+
+/// 会话建立后的 WebTransport 连接。
+///
+/// 与 QUIC 客户端协议相同：每条消息一个单向流，输入提示等信号走数据报
+struct Session {
+    connection: Connection,
+    /// 会话ID，即 CONNECT 请求流的ID
+    id: u64,
+}
+
+/// 在 `listen` 上提供 HTTP/3 WebTransport，证书的选择见 [`select_certificate`]。
+/// 可以按哈希固定时，当前证书的哈希通过网关的 `/webtransport` 提供给浏览器
+pub async fn serve(listen: SocketAddr, state: Arc<ServerState>) -> Result<()> {
+    let (server_config, certificate_hash, short_lived) = server_config(&state).await?;
+    let endpoint = Endpoint::server(server_config, listen)
+        .with_context(|| format!("Failed to bind WebTransport listener {}", listen))?;
+    let port = endpoint.local_addr()?.port();
+    log_certificate(&certificate_hash, short_lived);
+    *state.webtransport.write().await = Some(Listener {
+        port,
+        certificate_hash,
+        short_lived,
+        endpoint: endpoint.clone(),
+    });
+    info!("WebTransport 监听地址: https://{}", listen);
+    tokio::spawn(rotate_certificate(Arc::clone(&state)).in_current_span());
+
+    while let Some(connecting) = endpoint.accept().await {
+        let remote = connecting.remote_address();
+        if state.config.read().await.policy.banned_ips.contains(&remote.ip()) {
+            METRICS.connections_rejected.with_label_values(&["banned"]).inc();
+            warn!("拒绝被禁止的地址: {}", remote);
+            // 握手完成前丢弃即关闭连接
+            drop(connecting);
+            continue;
+        }
+        let state = Arc::clone(&state);
+        tokio::spawn(async move {
+            let connection = match connecting.await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    METRICS.connections_rejected.with_label_values(&["handshake"]).inc();
+                    warn!("WebTransport 连接失败 from {}: {}", remote, e);
+                    return;
+                }
+            };
+            METRICS.connections_accepted.inc();
+            if let Err(e) = handle_connection(connection, state).await {
+                warn!("WebTransport 连接错误 from {}: {:#}", remote, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// 选择 WebTransport 使用的证书，返回证书和它是否为短期证书。
+///
+/// 主端点的证书可以按哈希固定（ECDSA P-256、有效期不超过 14 天），或由 CA 签发、
+/// 浏览器能按常规方式验证时，直接使用它。开发时自动生成的自签名证书两者都不满足，
+/// 这时改用只在内存中的短期证书并定期更换，浏览器按哈希固定
+fn select_certificate(configured: &CertConfig, server_name: &str) -> Result<(CertConfig, bool)> {
+    if crypto::is_hash_pinnable(&configured.cert)? || !crypto::is_self_signed(&configured.cert)? {
+        return Ok((configured.clone(), false));
+    }
+    Ok((CertConfig::generate_short_lived(server_name, CERT_LIFETIME)?, true))
+}
+
+/// 选择证书并生成对应的 HTTP/3 配置，传输参数与主端点相同
+async fn server_config(state: &ServerState) -> Result<(ServerConfig, Option<String>, bool)> {
+    let (server_name, transport) = {
+        let config = state.config.read().await;
+        (config.tls.server_name.clone(), config.transport.clone())
+    };
+    let (cert_config, short_lived) = select_certificate(&*state.certificate.read().await, &server_name)?;
+    let hash = crypto::is_hash_pinnable(&cert_config.cert)?.then(|| crypto::certificate_hash(&cert_config.cert));
+    let mut tls = rustls::ServerConfig::builder()
+        .with_safe_default_cipher_suites()
+        .with_safe_default_kx_groups()
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .context("Failed to select TLS versions")?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert_config.cert], cert_config.key)
+        .context("Failed to create WebTransport TLS config")?;
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+
+    let mut transport = crypto::create_transport_config(&transport)?;
+    transport.max_concurrent_bidi_streams(MAX_BIDI_STREAMS.into());
+    let mut config = ServerConfig::with_crypto(Arc::new(tls));
+    config.transport_config(Arc::new(transport));
+    Ok((config, hash, short_lived))
+}
+
+fn log_certificate(certificate_hash: &Option<String>, short_lived: bool) {
+    match (certificate_hash, short_lived) {
+        (Some(hash), true) => info!("WebTransport 使用短期证书，SHA-256: {}", hash),
+        (Some(hash), false) => info!("WebTransport 使用配置的证书，SHA-256: {}", hash),
+        (None, _) => info!("WebTransport 使用配置的证书，由浏览器按 CA 验证"),
+    }
+}
+
+/// 重新选择证书，新连接使用新证书，新的哈希立即对网关可见。重新加载证书后调用
+pub(crate) async fn refresh(state: &ServerState) {
+    let mut listener = state.webtransport.write().await;
+    let Some(listener) = listener.as_mut() else {
+        return;
+    };
+    match server_config(state).await {
+        Ok((config, certificate_hash, short_lived)) => {
+            listener.endpoint.set_server_config(Some(config));
+            log_certificate(&certificate_hash, short_lived);
+            listener.certificate_hash = certificate_hash;
+            listener.short_lived = short_lived;
+        }
+        Err(e) => warn!("更换 WebTransport 证书失败: {:#}", e),
+    }
+}
+
+/// 定期更换短期证书，使用配置的证书时什么也不做
+async fn rotate_certificate(state: Arc<ServerState>) {
+    let mut interval = time::interval_at(time::Instant::now() + CERT_ROTATION, CERT_ROTATION);
+    loop {
+        interval.tick().await;
+        let short_lived = state.webtransport.read().await.as_ref().is_some_and(|listener| listener.short_lived);
+        if short_lived {
+            refresh(&state).await;
+        }
+    }
+}
+
+/// 等待 WebTransport 的 CONNECT 请求，之后与 QUIC 客户端一样登记到客户端列表
+async fn handle_connection(connection: Connection, state: Arc<ServerState>) -> Result<()> {
+    // 控制流在整个连接期间保持打开
+    let _control = http3::open_control_stream(&connection).await?;
+
+    // CONNECT 请求流在整个会话期间保持打开，其他请求一律 404。会话建立前浏览器只会打开控制流和 QPACK 流
+    let (mut request_send, mut request_recv) = loop {
+        let (mut send, mut recv) = tokio::select! {
+            stream = connection.accept_bi() => stream?,
+            stream = connection.accept_uni() => {
+                if let Some((_, mut recv)) = accept_stream(&connection, stream?).await {
+                    let _ = recv.stop(http3::H3_STREAM_CREATION_ERROR.into());
+                }
+                continue;
+            }
+        };
+        let webtransport = match http3::read_request(&mut recv).await {
+            Ok(fields) => http3::is_webtransport_request(&fields),
+            Err(e) => {
+                warn!("解析请求失败 from {}: {:#}", connection.remote_address(), e);
+                false
+            }
+        };
+        if !webtransport {
+            send.write_all(&http3::response(404)).await?;
+            send.finish().await?;
+            continue;
+        }
+        send.write_all(&http3::response(200)).await?;
+        break (send, recv);
+    };
+    let session = Arc::new(Session {
+        connection: connection.clone(),
+        id: quinn::VarInt::from(request_recv.id()).into_inner(),
+    });
+
+    let (tx, rx) = mpsc::unbounded_channel();
+    let peer_connection = PeerConnection::Gateway {
+        transport: "wt",
+        remote: connection.remote_address(),
+        outgoing: tx,
+    };
+    let peer_addr = peer_connection.key();
+    state.add_peer(Peer::new(peer_connection.clone())).await;
+    let span = info_span!(
+        "connection",
+        peer = %peer_addr,
+        transport = "webtransport",
+        client_id = tracing::field::Empty,
+    );
+    span.in_scope(|| info!("新客户端连接"));
+
+    let writer = tokio::spawn(write(Arc::clone(&session), rx).instrument(span.clone()));
+    let result = async {
+        let limit = state.config.read().await.transport.max_message_size;
+        loop {
+            tokio::select! {
+                stream = connection.accept_uni() => {
+                    let Some(recv) = session.accept_stream(stream?).await else {
+                        continue;
+                    };
+                    let message = match receive_message(recv, limit).await {
+                        Ok(message) => message,
+                        Err(e) => {
+                            warn!("解析消息失败 from {}: {}", peer_addr, e);
+                            continue;
+                        }
+                    };
+                    let span = info_span!("handle_message", message_id = %message.id, kind = message.kind());
+                    telemetry::set_parent(&span, &message);
+                    Server::handle_message(&peer_connection, &state, &peer_addr, message)
+                        .instrument(span)
+                        .await;
+                }
+                stream = connection.accept_bi() => {
+                    let (mut send, mut recv) = stream?;
+                    let _ = send.reset(http3::H3_REQUEST_REJECTED.into());
+                    let _ = recv.stop(http3::H3_REQUEST_REJECTED.into());
+                }
+                datagram = connection.read_datagram() => {
+                    if let Some(signal) = session.decode_signal(datagram?) {
+                        state.forward_signal(&peer_addr, signal).await;
+                    }
+                }
+                // 浏览器关闭会话时 CONNECT 流结束，流上的 capsule 不需要处理
+                data = request_recv.read_chunk(usize::MAX, true) => {
+                    if !matches!(data, Ok(Some(_))) {
+                        break;
+                    }
+                }
+            }
+        }
+        anyhow::Ok(())
+    }
+    .instrument(span.clone())
+    .await;
+    if let Err(e) = result {
+        span.in_scope(|| warn!("WebTransport 会话错误: {:#}", e));
+    }
+
+    writer.abort();
+    state.remove_peer(&peer_addr).await;
+    let _ = request_send.finish().await;
+    connection.close(http3::H3_NO_ERROR.into(), b"session closed");
+    Ok(())
+}
+
+async fn receive_message(mut recv: RecvStream, limit: usize) -> Result<Message> {
+    let data = recv.read_to_end(limit).await
+        .context("Failed to read message")?;
+    METRICS.bytes_received.inc_by(data.len() as u64);
+    Message::from_bytes(&data)
+}
+
+/// 把发送队列写入会话：消息各开一个单向流，信号作为数据报
+async fn write(session: Arc<Session>, mut outgoing: mpsc::UnboundedReceiver<Outgoing>) {
+    while let Some(outgoing) = outgoing.recv().await {
+        let result = match outgoing {
+            Outgoing::Message(mut message) => {
+                telemetry::inject(&mut message);
+                session.send_message(&message).await
+            }
+            Outgoing::Signal(signal) => session.send_signal(&signal),
+            Outgoing::Close(reason) => {
+                session.connection.close(http3::H3_NO_ERROR.into(), reason.as_bytes());
+                return;
+            }
+        };
+        if let Err(e) = result {
+            warn!("发送消息失败: {:#}", e);
+        }
+    }
+}
+
+/// 按流类型分拣浏览器打开的单向流，返回 WebTransport 流和它所属的会话ID。
+/// 控制流检查开头的 SETTINGS 后读完丢弃，QPACK 流在后台读完丢弃，其他类型的流拒绝
+async fn accept_stream(connection: &Connection, mut recv: RecvStream) -> Option<(u64, RecvStream)> {
+    let stream_type = http3::read_varint(&mut recv).await.ok()?;
+    match stream_type {
+        http3::STREAM_WEBTRANSPORT_UNI => {
+            let session_id = http3::read_varint(&mut recv).await.ok()?;
+            return Some((session_id, recv));
+        }
+        http3::STREAM_CONTROL => {
+            tokio::spawn(read_control_stream(connection.clone(), recv));
+            return None;
+        }
+        http3::STREAM_QPACK_ENCODER | http3::STREAM_QPACK_DECODER => {
+            tokio::spawn(async move { while let Ok(Some(_)) = recv.read_chunk(usize::MAX, true).await {} });
+            return None;
+        }
+        _ => {}
+    }
+    let _ = recv.stop(http3::H3_STREAM_CREATION_ERROR.into());
+    None
+}
+
+/// 控制流的第一个帧必须是合法的 SETTINGS，否则按 HTTP/3 关闭整个连接。之后的帧读完丢弃
+async fn read_control_stream(connection: Connection, mut recv: RecvStream) {
+    if let Err(e) = http3::read_settings(&mut recv).await {
+        warn!("HTTP/3 控制流错误 from {}: {}", connection.remote_address(), e);
+        connection.close(e.code.into(), e.reason.as_bytes());
+        return;
+    }
+    while let Ok(Some(_)) = recv.read_chunk(usize::MAX, true).await {}
+}
+
+impl Session {
+    /// 返回属于本会话的 WebTransport 流，其他会话的流拒绝
+    async fn accept_stream(&self, recv: RecvStream) -> Option<RecvStream> {
+        let (session_id, mut recv) = accept_stream(&self.connection, recv).await?;
+        if session_id == self.id {
+            return Some(recv);
+        }
+        let _ = recv.stop(http3::H3_STREAM_CREATION_ERROR.into());
+        None
+    }
+
+    async fn send_message(&self, message: &Message) -> Result<()> {
+        let data = message.to_bytes()?;
+        let mut header = Vec::new();
+        http3::encode_varint(http3::STREAM_WEBTRANSPORT_UNI, &mut header);
+        http3::encode_varint(self.id, &mut header);
+        let mut send = self.connection.open_uni().await
+            .context("Failed to open stream")?;
+        send.write_all(&header).await
+            .context("Failed to send message")?;
+        send.write_all(&data).await
+            .context("Failed to send message")?;
+        send.finish().await.context("Failed to finish stream")?;
+        METRICS.bytes_sent.inc_by(data.len() as u64);
+        Ok(())
+    }
+
+    fn send_signal(&self, signal: &Signal) -> Result<()> {
+        let mut datagram = Vec::new();
+        http3::encode_varint(self.id / 4, &mut datagram);
+        datagram.extend_from_slice(&signal.to_bytes()?);
+        self.connection.send_datagram(Bytes::from(datagram))?;
+        Ok(())
+    }
+
+    /// HTTP/3 数据报以会话的 quarter stream ID 开头，之后是信号 JSON
+    fn decode_signal(&self, datagram: Bytes) -> Option<Signal> {
+        let mut payload = &datagram[..];
+        let quarter_id = http3::decode_varint(&mut payload)?;
+        if quarter_id != self.id / 4 || payload.is_empty() {
+            return None;
+        }
+        Signal::from_bytes(payload).ok()
+    }
+}
@@ -0,0 +1,83 @@
+//! HTTP API 发消息：发送者限于配置的机器人ID，超过消息大小上限的请求被拒绝，
+//! 客户端也不能用这些ID登录来冒充 API。
+
+use reqwest::StatusCode;
+use serde_json::json;
+use std::{
+    net::{IpAddr, Ipv4Addr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    client::Client,
+    config::{ClientTransport, Config},
+    message::MessageType,
+    server::Server,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const TOKEN: &str = "test-token";
+
+/// 启动开启 API 的服务器，返回发消息的 URL 和连接它的客户端配置
+async fn start_server() -> (String, Config) {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    config.api.enabled = true;
+    config.api.listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    config.api.tokens = vec![TOKEN.to_string()];
+    config.api.senders = vec!["deploybot".to_string()];
+    let url = format!("http://{}/rooms/lobby/messages", config.api.listen);
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+    (url, config)
+}
+
+async fn post(url: &str, body: serde_json::Value) -> StatusCode {
+    // 监听在后台启动，连接失败时稍后重试
+    for _ in 0..50 {
+        let response = reqwest::Client::new()
+            .post(url)
+            .bearer_auth(TOKEN)
+            .header(reqwest::header::CONTENT_TYPE, "application/json")
+            .body(body.to_string())
+            .send()
+            .await;
+        match response {
+            Ok(response) => return response.status(),
+            Err(_) => time::sleep(Duration::from_millis(100)).await,
+        }
+    }
+    panic!("API did not start");
+}
+
+#[tokio::test]
+async fn sender_must_be_allowed_and_message_must_fit() {
+    let (url, _) = start_server().await;
+
+    assert_eq!(post(&url, json!({ "content": "hello" })).await, StatusCode::CREATED);
+    assert_eq!(post(&url, json!({ "content": "hello", "sender": "deploybot" })).await, StatusCode::CREATED);
+    assert_eq!(post(&url, json!({ "content": "hello", "sender": "alice" })).await, StatusCode::FORBIDDEN);
+
+    let content = "x".repeat(Config::default().transport.max_message_size);
+    assert_eq!(post(&url, json!({ "content": content })).await, StatusCode::PAYLOAD_TOO_LARGE);
+}
+
+#[tokio::test]
+async fn clients_cannot_log_in_as_api_senders() {
+    let (_, mut config) = start_server().await;
+    for id in ["api", "deploybot"] {
+        config.client.id = id.to_string();
+        // QUIC 连接关闭时未读的流会丢失，用 TCP 确保能读到提示
+        config.client.transport = ClientTransport::Tcp;
+        let (_client, mut incoming) = Client::connect(&config).await.unwrap();
+        let notice = time::timeout(Duration::from_secs(10), incoming.next()).await.unwrap().expect("no notice");
+        let MessageType::Text { content } = notice.message_type else { panic!("expected notice") };
+        assert!(content.contains("HTTP API"), "unexpected notice: {content}");
+        let closed = time::timeout(Duration::from_secs(10), incoming.next()).await.unwrap();
+        assert!(closed.is_none(), "{id} stays connected");
+    }
+}
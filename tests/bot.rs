@@ -0,0 +1,68 @@
+//! 机器人只忽略启动前的历史回放，发送者时钟偏慢的新命令照常处理。
+
+use chrono::{Duration as ChronoDuration, Utc};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    bot::Bot,
+    client::Client,
+    config::Config,
+    message::{Message, MessageType, PresenceEvent},
+    server::Server,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[tokio::test]
+async fn bot_skips_replay_but_answers_commands_from_slow_clocks() {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+
+    let mut alice_config = config.clone();
+    alice_config.client.id = "alice".to_string();
+    let (alice, mut incoming) = Client::connect(&alice_config).await.unwrap();
+    let old = alice.send_text("/ping").await.unwrap();
+
+    let mut bot_config = config.clone();
+    bot_config.client.id = "pingbot".to_string();
+    let bot = Bot::new(bot_config).command("ping", |ctx| async move { ctx.reply("pong").await });
+    tokio::spawn(bot.run());
+    time::timeout(TIMEOUT, async {
+        while let Some(message) = incoming.next().await {
+            if matches!(message.message_type, MessageType::Presence { event: PresenceEvent::Join, .. }) {
+                return;
+            }
+        }
+    })
+    .await
+    .expect("bot did not join");
+
+    let mut command = Message::new_text("alice".to_string(), "/ping".to_string());
+    command.timestamp = Utc::now() - ChronoDuration::hours(1);
+    alice.send(command.clone()).await.unwrap();
+
+    let reply = time::timeout(TIMEOUT, async {
+        loop {
+            let message = incoming.next().await.expect("connection closed");
+            if message.reply_to.is_some() {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("bot did not answer");
+    assert_ne!(reply.reply_to.as_deref(), Some(old.id.as_str()), "bot answered replayed history");
+    assert_eq!(reply.reply_to.as_deref(), Some(command.id.as_str()));
+
+    alice.disconnect().await;
+}
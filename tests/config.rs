@@ -0,0 +1,102 @@
+//! `t3xt config check`：无效的配置被拒绝并说明原因，有效的配置打印叠加后的结果；
+//! 选中的 `[profiles.<名称>]` 覆盖在配置文件之上，未选中时不生效。
+
+use std::{
+    fs,
+    path::PathBuf,
+    process::{Command, Output},
+};
+
+use t3xt::config::{CongestionController, Config};
+
+const BIN: &str = env!("CARGO_BIN_EXE_t3xt");
+
+/// 把配置写入临时文件，返回运行 `t3xt config check` 的命令
+fn command(name: &str, toml: &str, args: &[&str]) -> Command {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{name}.toml"));
+    fs::write(&path, toml).unwrap();
+    let mut command = Command::new(BIN);
+    command.args(args).args(["config", "check"]).arg(&path).env_remove("T3XT_PROFILE");
+    command
+}
+
+fn check(name: &str, toml: &str, args: &[&str]) -> Output {
+    command(name, toml, args).output().expect("failed to run t3xt")
+}
+
+/// 校验失败时返回标准错误中的原因
+fn rejection(name: &str, toml: &str) -> String {
+    let output = check(name, toml, &[]);
+    assert!(!output.status.success(), "{name} was accepted");
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn history_capacity_must_not_be_zero() {
+    let error = rejection("zero-history", "[server]\nhistory_capacity = 0\nhistory_replay = 0\n");
+    assert!(error.contains("server.history_capacity"), "{error}");
+}
+
+#[test]
+fn ids_must_be_valid() {
+    let error = rejection("server-id", "[server]\nid = \"hub one\"\n");
+    assert!(error.contains("server.id"), "{error}");
+    let error = rejection("empty-server-id", "[server]\nid = \"\"\n");
+    assert!(error.contains("server.id"), "{error}");
+    let error = rejection("client-id", "[client]\nid = \"a@b\"\n");
+    assert!(error.contains("client.id"), "{error}");
+}
+
+#[test]
+fn unknown_variables_are_reported_once() {
+    let output = command("unknown-env", "", &[]).env("T3XT_NO_SUCH_SETTING", "1").output().unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(stderr.matches("T3XT_NO_SUCH_SETTING").count(), 1, "{stderr}");
+}
+
+#[test]
+fn valid_config_is_printed() {
+    let output = check("valid", "[server]\nid = \"hub\"\nhistory_capacity = 10\nhistory_replay = 5\n", &[]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("id = \"hub\""), "{stdout}");
+    assert!(stdout.contains("history_capacity = 10"), "{stdout}");
+}
+
+const CONFIG: &str = r#"
+[client]
+target = "10.0.0.1"
+
+[transport]
+initial_rtt_ms = 200
+
+[profiles.lan.client]
+target = "192.168.1.10"
+
+[profiles.lan.transport]
+initial_rtt_ms = 10
+congestion_controller = "bbr"
+"#;
+
+#[test]
+fn profile_overrides_the_config_file() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("profiles.toml");
+    fs::write(&path, CONFIG).unwrap();
+
+    let config = Config::load_with_profile(Some(&path), Some("lan")).unwrap();
+    assert_eq!(config.client.target, "192.168.1.10");
+    assert_eq!(config.transport.initial_rtt_ms, 10);
+    assert_eq!(config.transport.congestion_controller, CongestionController::Bbr);
+    // 方案中没有写的项保持配置文件和默认值
+    assert_eq!(config.transport.max_message_size, Config::default().transport.max_message_size);
+
+    // 空名称表示不使用配置方案，也不读取 T3XT_PROFILE
+    let config = Config::load_with_profile(Some(&path), Some("")).unwrap();
+    assert_eq!(config.client.target, "10.0.0.1");
+    assert_eq!(config.transport.initial_rtt_ms, 200);
+    let config = Config::load(Some(&path)).unwrap();
+    assert_eq!(config.transport.initial_rtt_ms, 200);
+
+    assert!(Config::load_with_profile(Some(&path), Some("missing")).is_err());
+}
@@ -0,0 +1,138 @@
+//! WebSocket 网关：浏览器发送 Join 登录后与 QUIC 客户端双向收发房间消息，
+//! 无法解析的帧被忽略，超过消息大小上限的帧断开连接。
+
+use futures_util::SinkExt;
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    client::{Client, Incoming},
+    config::Config,
+    message::{Message, MessageType, PresenceEvent},
+    server::Server,
+};
+use tokio::{net::TcpStream, time};
+use tokio_stream::StreamExt;
+use tokio_tungstenite::{tungstenite::Message as Frame, MaybeTlsStream, WebSocketStream};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+async fn start_server() -> (Config, SocketAddr) {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    config.gateway.enabled = true;
+    config.gateway.listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    let gateway = config.gateway.listen;
+    tokio::spawn(async move { server.run().await });
+    (config, gateway)
+}
+
+/// 连接 `/ws` 并以 `id` 登录
+async fn connect_ws(gateway: SocketAddr, id: &str) -> Socket {
+    let url = format!("ws://{}/ws", gateway);
+    // 监听在后台启动，连接失败时稍后重试
+    for _ in 0..50 {
+        if let Ok((mut socket, _)) = tokio_tungstenite::connect_async(&url).await {
+            let join = Message::new_presence(id.to_string(), PresenceEvent::Join, None);
+            send(&mut socket, &join).await;
+            return socket;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("gateway did not start");
+}
+
+async fn send(socket: &mut Socket, message: &Message) {
+    let text = String::from_utf8(message.to_bytes().unwrap()).unwrap();
+    socket.send(Frame::text(text)).await.unwrap();
+}
+
+/// 跳过上下线和信号，返回下一条文本消息
+async fn next_ws_text(socket: &mut Socket) -> Message {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let frame = socket.next().await.expect("connection closed").unwrap();
+            let Frame::Text(text) = frame else { continue };
+            let Ok(message) = Message::from_bytes(text.as_bytes()) else { continue };
+            if matches!(message.message_type, MessageType::Text { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no text message received")
+}
+
+async fn next_text(incoming: &mut Incoming) -> Message {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let message = incoming.next().await.expect("connection closed");
+            if matches!(message.message_type, MessageType::Text { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no text message received")
+}
+
+fn content(message: &Message) -> &str {
+    match &message.message_type {
+        MessageType::Text { content } => content,
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn browser_talks_to_quic_clients_and_bad_frames_are_handled() {
+    let (config, gateway) = start_server().await;
+    let mut carol = connect_ws(gateway, "carol").await;
+    let mut bob_config = config.clone();
+    bob_config.client.id = "bob".to_string();
+    let (bob, mut bob_incoming) = Client::connect(&bob_config).await.unwrap();
+
+    // 发送者以服务器记录的登录ID为准
+    send(&mut carol, &Message::new_text("mallory".to_string(), "hello from the browser".to_string())).await;
+    let message = next_text(&mut bob_incoming).await;
+    assert_eq!(message.sender_id, "carol");
+    assert_eq!(content(&message), "hello from the browser");
+
+    bob.send_text("hello from quic").await.unwrap();
+    let message = next_ws_text(&mut carol).await;
+    assert_eq!(message.sender_id, "bob");
+    assert_eq!(content(&message), "hello from quic");
+
+    // 无法解析的帧只记录日志，连接继续可用
+    carol.send(Frame::text("not a message")).await.unwrap();
+    carol.send(Frame::binary(vec![0xff, 0x00])).await.unwrap();
+    send(&mut carol, &Message::new_text("carol".to_string(), "still here".to_string())).await;
+    assert_eq!(content(&next_text(&mut bob_incoming).await), "still here");
+
+    // 超过消息大小上限的帧断开连接，消息不会投递
+    let oversized = "x".repeat(config.transport.max_message_size + 1);
+    send(&mut carol, &Message::new_text("carol".to_string(), oversized)).await;
+    let closed = time::timeout(TIMEOUT, async {
+        loop {
+            match carol.next().await {
+                None | Some(Err(_)) | Some(Ok(Frame::Close(_))) => return,
+                Some(Ok(_)) => continue,
+            }
+        }
+    })
+    .await;
+    assert!(closed.is_ok(), "oversized frame did not close the connection");
+    // 断开后ID被释放，重新登录发出的消息是 bob 收到的下一条
+    let mut carol = connect_ws(gateway, "carol").await;
+    send(&mut carol, &Message::new_text("carol".to_string(), "back".to_string())).await;
+    assert_eq!(content(&next_text(&mut bob_incoming).await), "back");
+
+    bob.disconnect().await;
+}
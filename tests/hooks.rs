@@ -0,0 +1,145 @@
+//! 消息钩子的隔离：某个钩子的回调 panic 时只跳过它本次的处理，
+//! 它改了一半的消息被丢弃，后面的钩子照常执行，消息照常投递。
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use t3xt::{
+    client::{Client, Incoming},
+    config::Config,
+    hooks::{Action, Context, MessageHook, PeerInfo},
+    message::{Message, MessageType},
+    server::Server,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 每个回调都 panic，on_message 在 panic 前先改掉消息
+struct Panicking;
+
+impl MessageHook for Panicking {
+    fn name(&self) -> &str {
+        "panicking"
+    }
+
+    fn on_connect(&self, _peer: &PeerInfo) {
+        panic!("on_connect failed");
+    }
+
+    fn on_message(&self, _ctx: &mut Context<'_>, message: &mut Message) -> Action {
+        message.message_type = MessageType::Text { content: "tampered".to_string() };
+        panic!("on_message failed");
+    }
+
+    fn on_disconnect(&self, _peer: &PeerInfo) {
+        panic!("on_disconnect failed");
+    }
+}
+
+#[derive(Default)]
+struct Counts {
+    connects: AtomicUsize,
+    disconnects: AtomicUsize,
+}
+
+/// 排在 panic 的钩子之后，记录调用次数并给文本加上标记
+struct Counting(Arc<Counts>);
+
+impl MessageHook for Counting {
+    fn on_connect(&self, _peer: &PeerInfo) {
+        self.0.connects.fetch_add(1, Ordering::SeqCst);
+    }
+
+    fn on_message(&self, ctx: &mut Context<'_>, message: &mut Message) -> Action {
+        if let MessageType::Text { content } = &mut message.message_type {
+            content.push_str(" [checked]");
+            ctx.reply("checked");
+        }
+        Action::Continue
+    }
+
+    fn on_disconnect(&self, _peer: &PeerInfo) {
+        self.0.disconnects.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+async fn connect(config: &Config, id: &str) -> (Client, Incoming) {
+    let mut config = config.clone();
+    config.client.id = id.to_string();
+    Client::connect(&config).await.unwrap()
+}
+
+async fn next_text(incoming: &mut Incoming) -> Message {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let message = incoming.next().await.expect("connection closed");
+            if matches!(message.message_type, MessageType::Text { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no text message received")
+}
+
+/// 等待计数达到 `expected`，钩子在服务器任务中异步调用
+async fn wait_for(counter: &AtomicUsize, expected: usize) {
+    time::timeout(TIMEOUT, async {
+        while counter.load(Ordering::SeqCst) < expected {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("hook was not called");
+}
+
+fn content(message: &Message) -> &str {
+    match &message.message_type {
+        MessageType::Text { content } => content,
+        _ => unreachable!(),
+    }
+}
+
+#[tokio::test]
+async fn panicking_hook_does_not_stop_later_hooks_or_delivery() {
+    let counts = Arc::new(Counts::default());
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    let server = Server::builder(config.clone())
+        .hook(Panicking)
+        .hook(Counting(Arc::clone(&counts)))
+        .build()
+        .unwrap();
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+
+    let (alice, mut alice_incoming) = connect(&config, "alice").await;
+    let (bob, mut bob_incoming) = connect(&config, "bob").await;
+    alice.send_text("hello").await.unwrap();
+
+    // panic 的钩子的修改被丢弃，后面的钩子的修改生效
+    let delivered = next_text(&mut bob_incoming).await;
+    assert_eq!(delivered.sender_id, "alice");
+    assert_eq!(content(&delivered), "hello [checked]");
+    let reply = next_text(&mut alice_incoming).await;
+    assert_eq!(content(&reply), "checked");
+    wait_for(&counts.connects, 2).await;
+
+    alice.disconnect().await;
+    wait_for(&counts.disconnects, 1).await;
+
+    // 服务器仍然正常处理后续消息
+    bob.send_text("still working").await.unwrap();
+    let reply = next_text(&mut bob_incoming).await;
+    assert_eq!(content(&reply), "checked");
+    bob.disconnect().await;
+}
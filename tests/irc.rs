@@ -0,0 +1,143 @@
+//! IRC 协议桥：单独的 \r 也作为行尾，消息内容中的换行不能注入额外的 IRC 命令，
+//! 无效的ID无论从哪种传输登录都被拒绝，登录被拒绝时回复错误并断开而不是加入频道。
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    client::Client,
+    config::{ClientTransport, Config},
+    message::MessageType,
+    server::Server,
+};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines},
+    net::{tcp::OwnedReadHalf, TcpStream},
+    time,
+};
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn start_server() -> (Config, SocketAddr) {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    config.irc.enabled = true;
+    config.irc.listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    let irc = config.irc.listen;
+    tokio::spawn(async move { server.run().await });
+    (config, irc)
+}
+
+async fn connect_irc(listen: SocketAddr) -> TcpStream {
+    // 监听在后台启动，连接失败时稍后重试
+    for _ in 0..50 {
+        if let Ok(stream) = TcpStream::connect(listen).await {
+            return stream;
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("IRC bridge did not start");
+}
+
+/// 跳过其他行，返回第一行包含 `needle` 的回复
+async fn expect_line(lines: &mut Lines<BufReader<OwnedReadHalf>>, needle: &str) -> String {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let line = lines.next_line().await.unwrap().expect("connection closed");
+            if line.contains(needle) {
+                return line;
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("no line containing {needle:?}"))
+}
+
+#[tokio::test]
+async fn bridge_does_not_let_content_or_ids_inject_commands() {
+    let (config, irc) = start_server().await;
+    let (read, mut write) = connect_irc(irc).await.into_split();
+    let mut lines = BufReader::new(read).lines();
+
+    // 只用 \r 分隔的命令同样逐条处理
+    write.write_all(b"NICK bad:nick\rNICK carol\rUSER carol 0 * :Carol\r").await.unwrap();
+    expect_line(&mut lines, " 432 ").await;
+    expect_line(&mut lines, " 001 carol ").await;
+    write.write_all(b"JOIN #lobby\r\n").await.unwrap();
+    expect_line(&mut lines, " 366 ").await;
+
+    let mut bob_config = config.clone();
+    bob_config.client.id = "bob".to_string();
+    let (bob, _bob_incoming) = Client::connect(&bob_config).await.unwrap();
+    bob.send_text("first\rQUIT :injected\0\nsecond").await.unwrap();
+    assert!(expect_line(&mut lines, "PRIVMSG").await.ends_with(":first"));
+    assert!(expect_line(&mut lines, "PRIVMSG").await.ends_with(":QUIT :injected"));
+    assert!(expect_line(&mut lines, "PRIVMSG").await.ends_with(":second"));
+
+    let mut evil_config = config.clone();
+    evil_config.client.id = "evil!x@y".to_string();
+    // QUIC 连接关闭时未读的流会丢失，用 TCP 确保能读到提示
+    evil_config.client.transport = ClientTransport::Tcp;
+    let (_evil, mut evil_incoming) = Client::connect(&evil_config).await.unwrap();
+    let notice = time::timeout(TIMEOUT, evil_incoming.next()).await.unwrap().expect("no notice");
+    let MessageType::Text { content } = notice.message_type else { panic!("expected notice") };
+    assert!(content.contains("无效"), "unexpected notice: {content}");
+    let closed = time::timeout(TIMEOUT, evil_incoming.next()).await.unwrap();
+    assert!(closed.is_none(), "invalid ID stays connected");
+
+    bob.disconnect().await;
+}
+
+#[tokio::test]
+async fn rejected_login_closes_instead_of_joining() {
+    let (config, irc) = start_server().await;
+    let (read, mut write) = connect_irc(irc).await.into_split();
+    let mut lines = BufReader::new(read).lines();
+    write.write_all(b"NICK dave\r\nUSER dave 0 * :Dave\r\n").await.unwrap();
+    expect_line(&mut lines, " 001 dave ").await;
+
+    // 注册之后、加入频道之前，另一个客户端用同一个ID登录
+    let mut dave_config = config.clone();
+    dave_config.client.id = "dave".to_string();
+    let (dave, _dave_incoming) = Client::connect(&dave_config).await.unwrap();
+    time::timeout(TIMEOUT, async {
+        loop {
+            write.write_all(b"WHO dave\r\n").await.unwrap();
+            let mut found = false;
+            loop {
+                let line = lines.next_line().await.unwrap().expect("connection closed");
+                found |= line.contains(" 352 ");
+                if line.contains(" 315 ") {
+                    break;
+                }
+            }
+            if found {
+                return;
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("dave did not log in");
+    write.write_all(b"JOIN #lobby\r\n").await.unwrap();
+    expect_line(&mut lines, " 433 dave ").await;
+    let rest = time::timeout(TIMEOUT, async {
+        let mut rest = Vec::new();
+        while let Ok(Some(line)) = lines.next_line().await {
+            rest.push(line);
+        }
+        rest
+    })
+    .await
+    .expect("connection stays open");
+    assert!(rest.iter().all(|line| !line.contains(" JOIN ")), "joined after rejection: {rest:?}");
+
+    dave.disconnect().await;
+}
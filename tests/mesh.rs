@@ -0,0 +1,144 @@
+//! 在本机回环地址上启动 5 个服务器进程组成环形网状拓扑，
+//! 验证消息多跳转发、按ID去重，以及链路断开后的路由收敛。
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_t3xt");
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 子进程及其收集到的标准输出，drop 时结束进程
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl Process {
+    fn spawn(args: &[&str]) -> Self {
+        let mut child = Command::new(BIN)
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            // 日志写到被丢弃的标准错误，不在仓库中留下日志文件
+            .env("T3XT_LOG_DIRECTORY", "")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start t3xt");
+        let stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let lines = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lock().unwrap().push(line);
+            }
+        });
+        Self {
+            stdin: child.stdin.take(),
+            child,
+            output,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().unwrap();
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn count(&self, needle: &str) -> usize {
+        self.output.lock().unwrap().iter().filter(|line| line.contains(needle)).count()
+    }
+
+    /// 等待输出中出现 needle，超时则失败
+    fn wait_for(&self, needle: &str) {
+        self.wait_for_within(needle, TIMEOUT);
+    }
+
+    fn wait_for_within(&self, needle: &str, timeout: Duration) {
+        let start = Instant::now();
+        while self.count(needle) == 0 {
+            assert!(start.elapsed() < timeout, "timed out waiting for {:?}", needle);
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// 反复发送 /who，直到所有成员都出现在同一次响应中
+    fn wait_for_members(&mut self, members: &[&str]) {
+        let start = Instant::now();
+        loop {
+            let seen = self.output.lock().unwrap().len();
+            self.send("/who");
+            thread::sleep(Duration::from_millis(300));
+            let output = self.output.lock().unwrap();
+            let response = &output[seen..];
+            if members.iter().all(|member| response.iter().any(|line| line.trim_start().starts_with(member))) {
+                return;
+            }
+            drop(output);
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for members {:?}", members);
+            thread::sleep(Duration::from_millis(300));
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn five_node_mesh_relays_once_and_converges() {
+    let base = 40000 + (std::process::id() % 2000) as u16 * 5;
+    let port = |node: u16| (base + node - 1).to_string();
+    let peer = |node: u16| format!("127.0.0.1:{}", base + node - 1);
+
+    // 环形拓扑 1-2-3-4-5-1，每条链路只由一端发起连接
+    let mut servers: Vec<Option<Process>> = (1..=5u16)
+        .map(|node| {
+            let upstream = if node == 1 { 5 } else { node - 1 };
+            let id = format!("n{}", node);
+            Some(Process::spawn(&[
+                "serve", "-i", &id, "-p", &port(node), "--peer", &peer(upstream),
+            ]))
+        })
+        .collect();
+    for server in servers.iter().flatten() {
+        server.wait_for("联邦链路已建立");
+    }
+
+    let mut alice = Process::spawn(&["run", "-p", &port(1), "-i", "alice"]);
+    let mut bob = Process::spawn(&["run", "-p", &port(3), "-i", "bob"]);
+    let carol = Process::spawn(&["run", "-p", &port(4), "-i", "carol"]);
+    bob.wait_for_members(&["alice", "bob", "carol"]);
+
+    alice.send("hello mesh");
+    bob.wait_for("alice: hello mesh");
+    carol.wait_for("alice: hello mesh");
+    // 环中每条消息都会沿两个方向到达，去重后只显示一次
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(bob.count("alice: hello mesh"), 1);
+    assert_eq!(carol.count("alice: hello mesh"), 1);
+
+    // 杀掉 n2 后，邻居在 QUIC 空闲超时后发现链路断开，
+    // n1 与 n3 之间的路由应收敛到 1-5-4-3
+    servers[1] = None;
+    let idle_timeout = Duration::from_secs(45);
+    servers[0].as_ref().unwrap().wait_for_within("联邦链路断开: n2", idle_timeout);
+    servers[2].as_ref().unwrap().wait_for_within("联邦链路断开: n2", idle_timeout);
+    bob.wait_for_members(&["alice", "bob", "carol"]);
+
+    alice.send("after failure");
+    bob.wait_for("alice: after failure");
+    thread::sleep(Duration::from_secs(1));
+    assert_eq!(bob.count("alice: after failure"), 1);
+    assert_eq!(carol.count("alice: after failure"), 1);
+}
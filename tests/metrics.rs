@@ -0,0 +1,148 @@
+//! 指标：`serve --metrics` 提供 Prometheus 文本格式的 `/metrics`，
+//! 包括连接数、按类型统计的消息数和每个 QUIC 连接的统计。
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, UdpSocket},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_t3xt");
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 子进程及其收集到的标准输出，drop 时结束进程
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl Process {
+    fn spawn(args: &[&str]) -> Self {
+        let mut child = Command::new(BIN)
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            // 日志写到被丢弃的标准错误，不在仓库中留下日志文件
+            .env("T3XT_LOG_DIRECTORY", "")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start t3xt");
+        let stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let lines = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lock().unwrap().push(line);
+            }
+        });
+        Self {
+            stdin: child.stdin.take(),
+            child,
+            output,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().unwrap();
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn count(&self, needle: &str) -> usize {
+        self.output.lock().unwrap().iter().filter(|line| line.contains(needle)).count()
+    }
+
+    /// 等待输出中出现 needle，超时则失败
+    fn wait_for(&self, needle: &str) {
+        let start = Instant::now();
+        while self.count(needle) == 0 {
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {:?}", needle);
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// 反复发送 /who，直到所有成员都出现在同一次响应中
+    fn wait_for_members(&mut self, members: &[&str]) {
+        let start = Instant::now();
+        loop {
+            let seen = self.output.lock().unwrap().len();
+            self.send("/who");
+            thread::sleep(Duration::from_millis(300));
+            let output = self.output.lock().unwrap();
+            let response = &output[seen..];
+            if members.iter().all(|member| response.iter().any(|line| line.trim_start().starts_with(member))) {
+                return;
+            }
+            drop(output);
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for members {:?}", members);
+            thread::sleep(Duration::from_millis(300));
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 用最简单的 HTTP/1.0 请求抓取一次指标
+fn scrape(listen: &str) -> String {
+    let mut stream = TcpStream::connect(listen).unwrap();
+    stream.write_all(b"GET /metrics HTTP/1.0\r\nHost: localhost\r\n\r\n").unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.0 200") || response.starts_with("HTTP/1.1 200"), "{}", response);
+    response
+}
+
+/// 等待某个指标出现期望的取值，超时则失败并打印最后一次抓取的结果
+fn wait_for_metric(listen: &str, line: &str) -> String {
+    let start = Instant::now();
+    loop {
+        let metrics = scrape(listen);
+        if metrics.lines().any(|metric| metric == line) {
+            return metrics;
+        }
+        assert!(start.elapsed() < TIMEOUT, "timed out waiting for {:?} in\n{}", line, metrics);
+        thread::sleep(Duration::from_millis(100));
+    }
+}
+
+#[test]
+fn metrics_endpoint_reports_connections_and_messages() {
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+    let listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().to_string();
+    let _server = Process::spawn(&["serve", "-p", &port, "--metrics", &listen]);
+    let mut alice = Process::spawn(&["run", "-p", &port, "-i", "alice"]);
+    alice.wait_for_members(&["alice"]);
+    let mut bob = Process::spawn(&["run", "-p", &port, "-i", "bob"]);
+    bob.wait_for_members(&["alice", "bob"]);
+
+    alice.send("hello");
+    bob.wait_for("alice: hello");
+
+    wait_for_metric(&listen, "t3xt_connected_peers 2");
+    let metrics = wait_for_metric(&listen, "t3xt_messages_received_total{type=\"text\"} 1");
+    assert!(metrics.lines().any(|line| line == "t3xt_connections_accepted_total 2"), "{}", metrics);
+    for peer in ["alice", "bob"] {
+        let label = format!("peer=\"{}\"", peer);
+        assert!(
+            metrics.lines().any(|line| line.starts_with("t3xt_quic_rtt_seconds{") && line.contains(&label)),
+            "no RTT for {} in\n{}",
+            peer,
+            metrics
+        );
+    }
+
+    // 退出的客户端不再出现在连接统计中
+    bob.send("/quit");
+    let metrics = wait_for_metric(&listen, "t3xt_connected_peers 1");
+    assert!(!metrics.contains("peer=\"bob\""), "{}", metrics);
+}
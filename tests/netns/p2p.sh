@@ -0,0 +1,162 @@
+#!/bin/sh
+# 用网络命名空间模拟两台 NAT 后面的客户端，验证经服务器会合后的打洞直连，
+# 以及一侧为对称 NAT（每个目标随机映射端口）时打洞失败、私信改经服务器转发。
+#
+#   alice (10.0.1.2) ── nat-a (203.0.113.1) ─┐
+#                                             ├─ br0 ── server (203.0.113.10)
+#   bob   (10.0.2.2) ── nat-b (203.0.113.2) ─┘
+#
+# NAT 由 nftables masquerade 实现，并丢弃外网主动发来的新连接（端口受限锥形 NAT）。
+# 需要 root、iproute2 和 nftables：
+#
+#   cargo build && sudo tests/netns/p2p.sh [target/debug/t3xt]
+set -eu
+
+BIN=$(realpath "${1:-target/debug/t3xt}")
+WORK=$(mktemp -d)
+NAMESPACES="t3-srv t3-nat-a t3-nat-b t3-a t3-b"
+TIMEOUT=20
+PIDS=""
+
+cleanup() {
+    trap - EXIT INT TERM
+    for pid in $PIDS; do kill "$pid" 2>/dev/null || true; done
+    wait 2>/dev/null || true
+    for ns in $NAMESPACES; do ip netns del "$ns" 2>/dev/null || true; done
+    rm -rf "$WORK"
+}
+trap cleanup EXIT INT TERM
+
+fail() {
+    echo "FAIL: $*" >&2
+    for log in "$WORK"/*.out "$WORK"/*.log; do
+        [ -f "$log" ] && { echo "── $log" >&2; tail -n 20 "$log" >&2; }
+    done
+    exit 1
+}
+
+# 等待文件中出现某一行
+wait_for() {
+    file=$1 pattern=$2 waited=0
+    until grep -q "$pattern" "$file" 2>/dev/null; do
+        waited=$((waited + 1))
+        [ "$waited" -gt $((TIMEOUT * 10)) ] && fail "$file: timed out waiting for '$pattern'"
+        sleep 0.1
+    done
+}
+
+setup_network() {
+    for ns in $NAMESPACES; do
+        ip netns add "$ns"
+        ip -n "$ns" link set lo up
+    done
+    ip -n t3-srv link add br0 type bridge
+    ip -n t3-srv addr add 203.0.113.10/24 dev br0
+    ip -n t3-srv link set br0 up
+
+    n=1
+    for side in a b; do
+        ip link add wan netns "t3-nat-$side" type veth peer name "wan-$side" netns t3-srv
+        ip -n t3-srv link set "wan-$side" master br0 up
+        ip -n "t3-nat-$side" addr add "203.0.113.$n/24" dev wan
+        ip -n "t3-nat-$side" link set wan up
+
+        ip link add lan netns "t3-nat-$side" type veth peer name eth0 netns "t3-$side"
+        ip -n "t3-nat-$side" addr add "10.0.$n.1/24" dev lan
+        ip -n "t3-nat-$side" link set lan up
+        ip -n "t3-$side" addr add "10.0.$n.2/24" dev eth0
+        ip -n "t3-$side" link set eth0 up
+        ip -n "t3-$side" route add default via "10.0.$n.1"
+        ip netns exec "t3-nat-$side" sysctl -qw net.ipv4.ip_forward=1
+        n=$((n + 1))
+    done
+}
+
+# NAT 规则。对称 NAT 用 random-fully，每个新的目标地址映射到随机端口，服务器观测到的端口对打洞无用
+setup_nat() {
+    ns=$1 mapping=$2
+    ip netns exec "$ns" nft flush ruleset
+    ip netns exec "$ns" nft -f - <<EOF
+table ip nat {
+    chain postrouting {
+        type nat hook postrouting priority srcnat; policy accept;
+        oifname "wan" masquerade $mapping
+    }
+}
+table ip filter {
+    chain input {
+        type filter hook input priority filter; policy accept;
+        iifname "wan" ct state new drop
+    }
+    chain forward {
+        type filter hook forward priority filter; policy accept;
+        iifname "wan" ct state new drop
+    }
+}
+EOF
+}
+
+# 服务器在标准输入结束时退出，用命名管道保持输入打开（文件描述符 5）
+start_server() {
+    cd "$WORK"
+    mkfifo server.in
+    T3XT_LOG_DIRECTORY="" ip netns exec t3-srv "$BIN" serve --bind 203.0.113.10 -p 10005 \
+        <server.in >server.out 2>server.log &
+    PIDS="$PIDS $!"
+    exec 5>server.in
+    wait_for server.out "证书指纹"
+    sleep 1
+}
+
+# 客户端从命名管道读取输入，文件描述符 3 和 4 分别写给 alice 和 bob
+start_clients() {
+    rm -f "$WORK"/alice.* "$WORK"/bob.*
+    mkfifo "$WORK/alice.in" "$WORK/bob.in"
+    for side in a b; do
+        [ "$side" = a ] && id=alice || id=bob
+        T3XT_LOG_DIRECTORY="" T3XT_CLIENT_PUNCH_TIMEOUT_MS=5000 ip netns exec "t3-$side" "$BIN" run \
+            -t 203.0.113.10 -p 10005 -i "$id" --transport quic \
+            <"$WORK/$id.in" >"$WORK/$id.out" 2>"$WORK/$id.log" &
+        PIDS="$PIDS $!"
+    done
+    exec 3>"$WORK/alice.in" 4>"$WORK/bob.in"
+    wait_for "$WORK/alice.out" "connected"
+    wait_for "$WORK/bob.out" "connected"
+}
+
+stop_clients() {
+    echo /quit >&3
+    echo /quit >&4
+    exec 3>&- 4>&-
+    sleep 1
+}
+
+setup_network
+start_server
+
+echo "== 锥形 NAT：打洞直连"
+setup_nat t3-nat-a ""
+setup_nat t3-nat-b ""
+start_clients
+echo "/p2p bob" >&3
+wait_for "$WORK/alice.out" "已与 bob 建立直连"
+wait_for "$WORK/bob.out" "已与 alice 建立直连"
+echo "/msg bob hello-direct" >&3
+wait_for "$WORK/bob.out" "hello-direct"
+echo "/msg alice hello-back" >&4
+wait_for "$WORK/alice.out" "hello-back"
+grep -q "私信 alice -> bob" "$WORK/server.log" && fail "direct message went through the server"
+grep -q "直连会合 alice (Some(203.0.113.1:" "$WORK/server.log" || fail "server did not observe alice's NAT address"
+stop_clients
+
+echo "== 对称 NAT：打洞失败，改经服务器转发"
+setup_nat t3-nat-b "random-fully"
+start_clients
+echo "/p2p bob" >&3
+wait_for "$WORK/alice.out" "无法与 bob 直连"
+echo "/msg bob hello-relay" >&3
+wait_for "$WORK/bob.out" "hello-relay"
+wait_for "$WORK/server.log" "私信 alice -> bob"
+stop_clients
+
+echo "PASS"
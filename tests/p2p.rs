@@ -0,0 +1,117 @@
+//! 客户端直连：经服务器会合后私信不再经过服务器，
+//! 对方关闭直连时改由服务器转发。NAT 环境下的打洞见 tests/netns/p2p.sh。
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use t3xt::{
+    client::{Client, Incoming},
+    config::Config,
+    message::{Message, MessageType},
+    server::Server,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 启动服务器，返回客户端配置和服务器处理过的私信数
+async fn start_server() -> (Config, Arc<AtomicUsize>) {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    config.client.target = "127.0.0.1".to_string();
+    let relayed = Arc::new(AtomicUsize::new(0));
+    let counter = Arc::clone(&relayed);
+    let server = Server::builder(config.clone())
+        .on_message(move |message| {
+            if matches!(message.message_type, MessageType::Direct { .. }) {
+                counter.fetch_add(1, Ordering::SeqCst);
+            }
+        })
+        .build()
+        .unwrap();
+    let server = Arc::new(server);
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+    (config, relayed)
+}
+
+async fn connect(config: &Config, id: &str, p2p: bool) -> (Client, Incoming) {
+    let mut config = config.clone();
+    config.client.id = id.to_string();
+    config.client.p2p = p2p;
+    config.client.punch_timeout_ms = 3000;
+    Client::connect(&config).await.unwrap()
+}
+
+/// 跳过上下线等事件，返回下一条私信
+async fn next_direct(incoming: &mut Incoming) -> Message {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let message = incoming.next().await.expect("connection closed");
+            if matches!(message.message_type, MessageType::Direct { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no direct message received")
+}
+
+#[tokio::test]
+async fn direct_messages_bypass_server_after_rendezvous() {
+    let (config, relayed) = start_server().await;
+    let (alice, mut alice_incoming) = connect(&config, "alice", true).await;
+    let (bob, mut bob_incoming) = connect(&config, "bob", true).await;
+
+    alice.connect_direct("bob").await.unwrap();
+    assert_eq!(alice.direct_peers(), ["bob"]);
+    time::timeout(TIMEOUT, async {
+        while bob.direct_peers().is_empty() {
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("bob did not see the direct connection");
+
+    alice.send_direct("bob", "psst").await.unwrap();
+    let message = next_direct(&mut bob_incoming).await;
+    assert_eq!(message.sender_id, "alice");
+    bob.send_direct("alice", "hi").await.unwrap();
+    assert_eq!(next_direct(&mut alice_incoming).await.sender_id, "bob");
+    assert_eq!(relayed.load(Ordering::SeqCst), 0);
+
+    alice.disconnect().await;
+    bob.disconnect().await;
+}
+
+#[tokio::test]
+async fn falls_back_to_relay_when_peer_declines() {
+    let (config, relayed) = start_server().await;
+    let (alice, _alice_incoming) = connect(&config, "alice", true).await;
+    let (carol, mut carol_incoming) = connect(&config, "carol", false).await;
+
+    assert!(alice.connect_direct("carol").await.is_err());
+    assert!(alice.connect_direct("nobody").await.is_err());
+    assert!(alice.direct_peers().is_empty());
+
+    alice.send_direct("carol", "via server").await.unwrap();
+    assert_eq!(next_direct(&mut carol_incoming).await.sender_id, "alice");
+    // 服务器投递之后才调用回调
+    time::timeout(TIMEOUT, async {
+        while relayed.load(Ordering::SeqCst) == 0 {
+            time::sleep(Duration::from_millis(20)).await;
+        }
+    })
+    .await
+    .expect("direct message was not relayed");
+
+    carol.disconnect().await;
+    alice.disconnect().await;
+}
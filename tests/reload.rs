@@ -0,0 +1,163 @@
+//! 热重载：收到 SIGHUP 后换上新证书，之后的握手使用新证书，已建立的连接不受影响；
+//! 需要重启才能生效的设置（如监听端口）保持启动时的值。
+
+use std::{
+    fs,
+    io::{BufRead, BufReader, Write},
+    net::UdpSocket,
+    path::Path,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_t3xt");
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 子进程及其收集到的标准输出，drop 时结束进程
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl Process {
+    fn spawn(args: &[&str], envs: &[(&str, &str)]) -> Self {
+        let mut child = Command::new(BIN)
+            .args(args)
+            .envs(envs.iter().copied())
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            // 日志写到被丢弃的标准错误，不在仓库中留下日志文件
+            .env("T3XT_LOG_DIRECTORY", "")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start t3xt");
+        let stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let lines = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lock().unwrap().push(line);
+            }
+        });
+        Self {
+            stdin: child.stdin.take(),
+            child,
+            output,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().unwrap();
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn count(&self, needle: &str) -> usize {
+        self.output.lock().unwrap().iter().filter(|line| line.contains(needle)).count()
+    }
+
+    /// 等待输出中出现 needle，超时则失败
+    fn wait_for(&self, needle: &str) {
+        let start = Instant::now();
+        while self.count(needle) == 0 {
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {:?}", needle);
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// 反复发送 /who，直到所有成员都出现在同一次响应中
+    fn wait_for_members(&mut self, members: &[&str]) {
+        let start = Instant::now();
+        loop {
+            let seen = self.output.lock().unwrap().len();
+            self.send("/who");
+            thread::sleep(Duration::from_millis(300));
+            let output = self.output.lock().unwrap();
+            let response = &output[seen..];
+            if members.iter().all(|member| response.iter().any(|line| line.trim_start().starts_with(member))) {
+                return;
+            }
+            drop(output);
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for members {:?}", members);
+            thread::sleep(Duration::from_millis(300));
+        }
+    }
+}
+
+impl Process {
+    /// 等待进程自行退出，超时返回 false
+    fn exits(&mut self) -> bool {
+        let start = Instant::now();
+        while start.elapsed() < TIMEOUT {
+            if self.child.try_wait().unwrap().is_some() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        false
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+fn write_config(path: &Path, directory: &Path, port: u16) {
+    let config = format!(
+        "[server]\nport = {}\n\n[tls]\ncert = {:?}\nkey = {:?}\n",
+        port,
+        directory.join("server.crt"),
+        directory.join("server.key"),
+    );
+    fs::write(path, config).unwrap();
+}
+
+#[test]
+fn sighup_swaps_certificates_and_keeps_restart_only_settings() {
+    let directory = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("reload-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&directory);
+    fs::create_dir_all(&directory).unwrap();
+    let config_path = directory.join("t3xt.toml");
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+    write_config(&config_path, &directory, port);
+    let config = config_path.to_str().unwrap();
+    let port = port.to_string();
+
+    let server = Process::spawn(&["serve", "--config", config], &[]);
+    // 服务器启动时才生成证书，客户端要在此之后读取
+    server.wait_for("等待客户端连接");
+    let mut alice = Process::spawn(&["run", "--config", config, "-p", &port, "-i", "alice"], &[]);
+    alice.wait_for_members(&["alice"]);
+    let old_cert = directory.join("old.crt");
+    fs::copy(directory.join("server.crt"), &old_cert).unwrap();
+
+    // 换一张新证书，同时改动只能在重启时生效的端口
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    fs::write(directory.join("server.crt"), cert.serialize_pem().unwrap()).unwrap();
+    fs::write(directory.join("server.key"), cert.serialize_private_key_pem()).unwrap();
+    write_config(&config_path, &directory, port.parse::<u16>().unwrap() + 1);
+    let status = Command::new("kill").args(["-HUP", &server.child.id().to_string()]).status().unwrap();
+    assert!(status.success());
+    server.wait_for("配置已重新加载");
+
+    // 新证书在原端口上生效，已有连接仍然可用
+    let mut bob = Process::spawn(&["run", "--config", config, "-p", &port, "-i", "bob"], &[]);
+    bob.wait_for_members(&["alice", "bob"]);
+    alice.send("after reload");
+    bob.wait_for("alice: after reload");
+
+    // 只信任旧证书的客户端无法再连接
+    let old_cert = old_cert.to_str().unwrap();
+    let mut mallory = Process::spawn(
+        &["run", "--config", config, "-p", &port, "-i", "mallory"],
+        &[("T3XT_TLS_CERT", old_cert)],
+    );
+    assert!(mallory.exits(), "client trusting the old certificate stayed connected");
+}
@@ -0,0 +1,49 @@
+//! TLS 会话缓存属于各自的实例：票据只能在签发它的服务器上恢复会话，
+//! 新的客户端会话缓存也不会用到其他缓存中的票据。
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+};
+use t3xt::{
+    client::{Client, SessionCache},
+    config::{ClientTransport, Config},
+    server::Server,
+};
+
+/// 启动一台服务器，返回连接它的客户端配置
+async fn start_server() -> Config {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.port = server.local_addr().unwrap().port();
+    config.client.id = "heidi".to_string();
+    config.client.transport = ClientTransport::Quic;
+    tokio::spawn(async move { server.run().await });
+    config
+}
+
+/// 连接后立即断开，返回是否以 0-RTT 登录
+async fn zero_rtt(config: &Config, sessions: &SessionCache) -> bool {
+    let (client, _incoming) = Client::connect_with_sessions(config, sessions).await.unwrap();
+    let zero_rtt = client.zero_rtt();
+    client.disconnect().await;
+    zero_rtt
+}
+
+#[tokio::test]
+async fn sessions_belong_to_one_server_and_one_cache() {
+    let first = start_server().await;
+    let sessions = SessionCache::default();
+    assert!(!zero_rtt(&first, &sessions).await);
+    assert!(zero_rtt(&first, &sessions).await);
+
+    // 同一进程中的另一台服务器使用同一张证书，但没有第一台签发的会话
+    let second = start_server().await;
+    assert!(!zero_rtt(&second, &sessions).await, "session from another server was accepted");
+
+    // 新的缓存中没有票据
+    assert!(!zero_rtt(&first, &SessionCache::default()).await);
+    assert!(!Client::connect(&first).await.unwrap().0.zero_rtt());
+}
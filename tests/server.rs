@@ -0,0 +1,187 @@
+//! 服务器的登录和响应规则：同一 ID 不能重复在线，`/who` 和 `/mentions` 响应不超过消息大小上限，
+//! 编辑、删除和表情回应发往原消息所在的房间。
+
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    client::{Client, Incoming},
+    config::{ClientTransport, Config},
+    message::{Message, MessageType},
+    server::Server,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn start_server(config: Config) -> Config {
+    let mut config = config;
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+    config
+}
+
+async fn connect(config: &Config, id: &str, transport: ClientTransport) -> (Client, Incoming) {
+    let mut config = config.clone();
+    config.client.id = id.to_string();
+    config.client.transport = transport;
+    Client::connect(&config).await.unwrap()
+}
+
+/// 跳过其他消息，返回第一条满足条件的消息
+async fn next_matching(incoming: &mut Incoming, predicate: impl Fn(&Message) -> bool) -> Message {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let message = incoming.next().await.expect("connection closed");
+            if predicate(&message) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no matching message received")
+}
+
+#[tokio::test]
+async fn duplicate_id_is_rejected() {
+    let config = start_server(Config::default()).await;
+    let (alice, _alice_incoming) = connect(&config, "alice", ClientTransport::Quic).await;
+    let (_impostor, mut impostor_incoming) = connect(&config, "alice", ClientTransport::Tcp).await;
+
+    let notice = next_matching(&mut impostor_incoming, |message| {
+        matches!(message.message_type, MessageType::Text { .. })
+    })
+    .await;
+    let MessageType::Text { content } = notice.message_type else { unreachable!() };
+    assert!(content.contains("已在线"), "unexpected notice: {content}");
+    let closed = time::timeout(TIMEOUT, impostor_incoming.next()).await.unwrap();
+    assert!(closed.is_none(), "duplicate connection stays open");
+
+    // 原连接不受影响
+    let (bob, mut bob_incoming) = connect(&config, "bob", ClientTransport::Quic).await;
+    alice.send_text("still here").await.unwrap();
+    let message = next_matching(&mut bob_incoming, |message| {
+        matches!(message.message_type, MessageType::Text { .. })
+    })
+    .await;
+    assert_eq!(message.sender_id, "alice");
+
+    bob.disconnect().await;
+    alice.disconnect().await;
+}
+
+#[tokio::test]
+async fn who_response_is_split_to_fit_message_size() {
+    let mut config = Config::default();
+    config.transport.max_message_size = 1024;
+    let config = start_server(config).await;
+
+    let mut clients = Vec::new();
+    for i in 0..40 {
+        clients.push(connect(&config, &format!("member-with-a-long-name-{i:02}"), ClientTransport::Quic).await);
+    }
+    let (asker, mut incoming) = connect(&config, "asker", ClientTransport::Quic).await;
+    asker.send(Message::new(String::new(), MessageType::WhoRequest)).await.unwrap();
+
+    let mut seen = 0;
+    let mut responses = 0;
+    while seen < 41 {
+        let response = next_matching(&mut incoming, |message| {
+            matches!(message.message_type, MessageType::WhoResponse { .. })
+        })
+        .await;
+        assert!(response.to_bytes().unwrap().len() <= 1024);
+        let MessageType::WhoResponse { members } = response.message_type else { unreachable!() };
+        seen += members.len();
+        responses += 1;
+    }
+    assert_eq!(seen, 41);
+    assert!(responses > 1);
+
+    asker.disconnect().await;
+    for (client, _) in clients {
+        client.disconnect().await;
+    }
+}
+
+#[tokio::test]
+async fn edits_and_reactions_reach_the_room_of_the_original_message() {
+    let config = start_server(Config::default()).await;
+    let (alice, _alice_incoming) = connect(&config, "alice", ClientTransport::Quic).await;
+    let (bob, mut bob_incoming) = connect(&config, "bob", ClientTransport::Quic).await;
+
+    let original = alice.send_text("typo").await.unwrap();
+    next_matching(&mut bob_incoming, |message| message.id == original.id).await;
+
+    alice.join("elsewhere").await.unwrap();
+    let edit = Message::new(
+        String::new(),
+        MessageType::Edit { target_id: original.id.clone(), content: "fixed".to_string() },
+    );
+    alice.send(edit).await.unwrap();
+    let received = next_matching(&mut bob_incoming, |message| {
+        matches!(message.message_type, MessageType::Edit { .. })
+    })
+    .await;
+    assert_eq!(received.room, bob.room());
+
+    let reaction = Message::new(
+        String::new(),
+        MessageType::Reaction { target_id: original.id.clone(), emoji: "👍".to_string() },
+    );
+    alice.send(reaction).await.unwrap();
+    let received = next_matching(&mut bob_incoming, |message| {
+        matches!(message.message_type, MessageType::Reaction { .. })
+    })
+    .await;
+    assert_eq!(received.room, bob.room());
+
+    bob.disconnect().await;
+    alice.disconnect().await;
+}
+
+#[tokio::test]
+async fn mentions_are_paged_and_kept_until_delivered() {
+    let mut config = Config::default();
+    config.transport.max_message_size = 2048;
+    let config = start_server(config).await;
+
+    let (alice, _alice_incoming) = connect(&config, "alice", ClientTransport::Quic).await;
+    let padding = "x".repeat(300);
+    for i in 0..10 {
+        alice.send_text(format!("@bob {i} {padding}")).await.unwrap();
+    }
+    // 等服务器处理完再让 bob 上线
+    time::sleep(Duration::from_millis(500)).await;
+
+    let (bob, mut incoming) = connect(&config, "bob", ClientTransport::Quic).await;
+    let is_response = |message: &Message| matches!(message.message_type, MessageType::MentionsResponse { .. });
+    bob.send(Message::new(String::new(), MessageType::MentionsRequest)).await.unwrap();
+    let mut received = 0;
+    let mut pages = 0;
+    while received < 10 {
+        let page = next_matching(&mut incoming, is_response).await;
+        assert!(page.to_bytes().unwrap().len() <= 2048);
+        let MessageType::MentionsResponse { messages } = page.message_type else { unreachable!() };
+        received += messages.len();
+        pages += 1;
+    }
+    assert_eq!(received, 10);
+    assert!(pages > 1);
+
+    // 已投递的提及不会再次返回
+    bob.send(Message::new(String::new(), MessageType::MentionsRequest)).await.unwrap();
+    let page = next_matching(&mut incoming, is_response).await;
+    let MessageType::MentionsResponse { messages } = page.message_type else { unreachable!() };
+    assert!(messages.is_empty());
+
+    bob.disconnect().await;
+    alice.disconnect().await;
+}
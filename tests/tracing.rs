@@ -0,0 +1,298 @@
+//! 链路追踪：消息携带 W3C trace context，发送方、服务器和接收方导出的 span
+//! 属于同一条 trace，并且逐跳成为上一跳的子 span。每个进程把 span 导出到各自的
+//! 假 OTLP/HTTP collector，测试直接解析 protobuf 请求体。
+
+use std::{
+    collections::HashMap,
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream, UdpSocket},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_t3xt");
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 子进程及其收集到的标准输出，drop 时结束进程
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl Process {
+    fn spawn(args: &[&str], collector: &Collector) -> Self {
+        let mut child = Command::new(BIN)
+            .args(args)
+            .env("T3XT_TELEMETRY_ENABLED", "true")
+            .env("T3XT_TELEMETRY_ENDPOINT", format!("http://{}/v1/traces", collector.address))
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            // 日志写到被丢弃的标准错误，不在仓库中留下日志文件
+            .env("T3XT_LOG_DIRECTORY", "")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start t3xt");
+        let stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let lines = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lock().unwrap().push(line);
+            }
+        });
+        Self {
+            stdin: child.stdin.take(),
+            child,
+            output,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().unwrap();
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn count(&self, needle: &str) -> usize {
+        self.output.lock().unwrap().iter().filter(|line| line.contains(needle)).count()
+    }
+
+    /// 等待输出中出现 needle，超时则失败
+    fn wait_for(&self, needle: &str) {
+        let start = Instant::now();
+        while self.count(needle) == 0 {
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {:?}", needle);
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// 反复发送 /who，直到所有成员都出现在同一次响应中
+    fn wait_for_members(&mut self, members: &[&str]) {
+        let start = Instant::now();
+        loop {
+            let seen = self.output.lock().unwrap().len();
+            self.send("/who");
+            thread::sleep(Duration::from_millis(300));
+            let output = self.output.lock().unwrap();
+            let response = &output[seen..];
+            if members.iter().all(|member| response.iter().any(|line| line.trim_start().starts_with(member))) {
+                return;
+            }
+            drop(output);
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for members {:?}", members);
+            thread::sleep(Duration::from_millis(300));
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// 导出的 span 中测试关心的字段
+#[derive(Debug, Clone)]
+struct Span {
+    trace_id: Vec<u8>,
+    span_id: Vec<u8>,
+    parent_span_id: Vec<u8>,
+    name: String,
+    attributes: HashMap<String, String>,
+}
+
+/// 只接受 OTLP/HTTP protobuf 导出请求的 collector，收到的 span 留给测试检查
+struct Collector {
+    address: String,
+    spans: Arc<Mutex<Vec<Span>>>,
+}
+
+impl Collector {
+    fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let collected = Arc::clone(&spans);
+        thread::spawn(move || {
+            for stream in listener.incoming().map_while(Result::ok) {
+                let collected = Arc::clone(&collected);
+                thread::spawn(move || serve_exports(stream, collected));
+            }
+        });
+        Self { address, spans }
+    }
+
+    fn find(&self, predicate: impl Fn(&Span) -> bool) -> Option<Span> {
+        self.spans.lock().unwrap().iter().find(|span| predicate(span)).cloned()
+    }
+}
+
+/// 处理一个 keep-alive 连接上的所有导出请求
+fn serve_exports(stream: TcpStream, spans: Arc<Mutex<Vec<Span>>>) {
+    let mut writer = stream.try_clone().unwrap();
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut length = 0;
+        loop {
+            let mut line = String::new();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let line = line.trim_end();
+            if line.is_empty() {
+                break;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-length") {
+                    length = value.trim().parse().unwrap();
+                }
+            }
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        spans.lock().unwrap().extend(parse_export(&body));
+        let response = "HTTP/1.1 200 OK\r\ncontent-type: application/x-protobuf\r\ncontent-length: 0\r\n\r\n";
+        if writer.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+/// 逐个读出 protobuf 消息的字段，返回字段号和长度分隔字段的内容，其他类型的字段跳过
+fn fields(mut data: &[u8]) -> Vec<(u64, &[u8])> {
+    fn varint(data: &mut &[u8]) -> u64 {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = data[0];
+            *data = &data[1..];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        value
+    }
+
+    let mut fields = Vec::new();
+    while !data.is_empty() {
+        let key = varint(&mut data);
+        match key & 7 {
+            0 => {
+                varint(&mut data);
+            }
+            1 => data = &data[8..],
+            2 => {
+                let length = varint(&mut data) as usize;
+                fields.push((key >> 3, &data[..length]));
+                data = &data[length..];
+            }
+            5 => data = &data[4..],
+            wire_type => panic!("unexpected wire type {}", wire_type),
+        }
+    }
+    fields
+}
+
+fn children(data: &[u8], number: u64) -> impl Iterator<Item = &[u8]> {
+    fields(data).into_iter().filter(move |(field, _)| *field == number).map(|(_, value)| value)
+}
+
+/// ExportTraceServiceRequest.resource_spans → ResourceSpans.scope_spans → ScopeSpans.spans
+fn parse_export(body: &[u8]) -> Vec<Span> {
+    let mut spans = Vec::new();
+    for resource_spans in children(body, 1) {
+        for scope_spans in children(resource_spans, 2) {
+            spans.extend(children(scope_spans, 2).map(parse_span));
+        }
+    }
+    spans
+}
+
+fn parse_span(data: &[u8]) -> Span {
+    let mut span = Span {
+        trace_id: Vec::new(),
+        span_id: Vec::new(),
+        parent_span_id: Vec::new(),
+        name: String::new(),
+        attributes: HashMap::new(),
+    };
+    for (number, value) in fields(data) {
+        match number {
+            1 => span.trace_id = value.to_vec(),
+            2 => span.span_id = value.to_vec(),
+            4 => span.parent_span_id = value.to_vec(),
+            5 => span.name = String::from_utf8(value.to_vec()).unwrap(),
+            // KeyValue { key = 1, value = AnyValue { string_value = 1 } }，只保留字符串属性
+            9 => {
+                let key = children(value, 1).next().map(|key| String::from_utf8_lossy(key).into_owned());
+                let text = children(value, 2).flat_map(|any| children(any, 1)).next();
+                if let (Some(key), Some(text)) = (key, text) {
+                    span.attributes.insert(key, String::from_utf8_lossy(text).into_owned());
+                }
+            }
+            _ => {}
+        }
+    }
+    span
+}
+
+/// 等待 collector 收到满足条件的 span，批量导出有几秒延迟
+fn wait_for_span(collector: &Collector, description: &str, predicate: impl Fn(&Span) -> bool) -> Span {
+    let start = Instant::now();
+    loop {
+        if let Some(span) = collector.find(&predicate) {
+            return span;
+        }
+        assert!(
+            start.elapsed() < TIMEOUT,
+            "timed out waiting for {} in {:#?}",
+            description,
+            collector.spans.lock().unwrap()
+        );
+        thread::sleep(Duration::from_millis(200));
+    }
+}
+
+fn is_text(span: &Span) -> bool {
+    span.attributes.get("kind").map(String::as_str) == Some("text")
+}
+
+/// 客户端连接期间发出的消息都在同一个 trace 里，要再按消息ID区分
+fn same_message(span: &Span, other: &Span) -> bool {
+    span.trace_id == other.trace_id && span.attributes.get("message_id") == other.attributes.get("message_id")
+}
+
+#[test]
+fn trace_context_follows_a_message_from_sender_to_recipient() {
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+    let (server_spans, alice_spans, bob_spans) = (Collector::start(), Collector::start(), Collector::start());
+    let _server = Process::spawn(&["serve", "-p", &port], &server_spans);
+    let mut alice = Process::spawn(&["run", "-p", &port, "-i", "alice"], &alice_spans);
+    alice.wait_for_members(&["alice"]);
+    let mut bob = Process::spawn(&["run", "-p", &port, "-i", "bob"], &bob_spans);
+    bob.wait_for_members(&["alice", "bob"]);
+
+    alice.send("traced");
+    bob.wait_for("alice: traced");
+
+    let sent = wait_for_span(&alice_spans, "send_message", |span| span.name == "send_message" && is_text(span));
+    let handled = wait_for_span(&server_spans, "handle_message", |span| {
+        span.name == "handle_message" && same_message(span, &sent)
+    });
+    assert_eq!(handled.parent_span_id, sent.span_id);
+    assert!(is_text(&handled));
+
+    let received = wait_for_span(&bob_spans, "receive_message", |span| {
+        span.name == "receive_message" && same_message(span, &sent)
+    });
+    assert!(is_text(&received));
+    let delivered = wait_for_span(&server_spans, "deliver to bob", |span| {
+        span.name == "deliver" && span.span_id == received.parent_span_id
+    });
+    assert_eq!(delivered.trace_id, sent.trace_id);
+}
@@ -0,0 +1,188 @@
+//! TCP+TLS 备用传输：TCP 客户端与 QUIC 客户端互通，
+//! 以及 UDP 被阻断时 auto 模式改用 TCP 连接；按指纹固定证书；重连时 0-RTT 登录；
+//! 传输参数和连接统计。
+
+use std::{
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    client::{Client, Incoming, SessionCache},
+    config::{ClientTransport, CongestionController, Config},
+    message::{Message, MessageType, SignalKind},
+    server::Server,
+};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    time,
+};
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+async fn start_server() -> Config {
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+    config
+}
+
+async fn connect(config: &Config, id: &str, transport: ClientTransport) -> (Client, Incoming) {
+    let mut config = config.clone();
+    config.client.id = id.to_string();
+    config.client.transport = transport;
+    Client::connect(&config).await.unwrap()
+}
+
+/// 跳过上下线等事件，返回下一条文本消息
+async fn next_text(incoming: &mut Incoming) -> Message {
+    time::timeout(TIMEOUT, async {
+        loop {
+            let message = incoming.next().await.expect("connection closed");
+            if matches!(message.message_type, MessageType::Text { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .expect("no text message received")
+}
+
+#[tokio::test]
+async fn tcp_and_quic_clients_talk_to_each_other() {
+    let config = start_server().await;
+    let (alice, mut alice_incoming) = connect(&config, "alice", ClientTransport::Quic).await;
+    let (bob, mut bob_incoming) = connect(&config, "bob", ClientTransport::Tcp).await;
+    assert_eq!(alice.transport(), "quic");
+    assert_eq!(bob.transport(), "tcp");
+
+    bob.send_text("over tcp").await.unwrap();
+    let message = next_text(&mut alice_incoming).await;
+    assert_eq!(message.sender_id, "bob");
+
+    alice.send_text("over quic").await.unwrap();
+    let message = next_text(&mut bob_incoming).await;
+    assert_eq!(message.sender_id, "alice");
+
+    bob.send_signal(SignalKind::Typing).unwrap();
+    let signal = time::timeout(TIMEOUT, alice_incoming.next_signal()).await.unwrap().unwrap();
+    assert_eq!(signal.sender_id, "bob");
+
+    bob.disconnect().await;
+    alice.disconnect().await;
+}
+
+#[tokio::test]
+async fn auto_falls_back_to_tcp_when_udp_is_blocked() {
+    let mut config = start_server().await;
+    let server_addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), config.client.port);
+
+    // 同一端口上的 UDP 只收不回，TCP 转发到服务器
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let _blackhole = UdpSocket::bind(("127.0.0.1", port)).await.unwrap();
+    tokio::spawn(async move {
+        while let Ok((mut inbound, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut outbound = TcpStream::connect(server_addr).await.unwrap();
+                let _ = tokio::io::copy_bidirectional(&mut inbound, &mut outbound).await;
+            });
+        }
+    });
+
+    config.client.port = port;
+    config.client.fallback_delay_ms = 100;
+    let (carol, mut carol_incoming) = connect(&config, "carol", ClientTransport::Auto).await;
+    assert_eq!(carol.transport(), "tcp");
+
+    config.client.port = server_addr.port();
+    let (dave, _dave_incoming) = connect(&config, "dave", ClientTransport::Auto).await;
+    assert_eq!(dave.transport(), "quic");
+    dave.send_text("hello carol").await.unwrap();
+    assert_eq!(next_text(&mut carol_incoming).await.sender_id, "dave");
+
+    dave.disconnect().await;
+    carol.disconnect().await;
+}
+
+#[tokio::test]
+async fn fingerprint_pins_server_certificate() {
+    let mut config = start_server().await;
+    let pem = std::fs::read(&config.tls.cert).unwrap();
+    let der = rustls_pemfile::certs(&mut pem.as_slice()).unwrap().remove(0);
+    let digest = ring::digest::digest(&ring::digest::SHA256, &der);
+    let fingerprint: String = digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+    // 不依赖证书文件，只用指纹
+    config.tls.cert = "missing.crt".into();
+
+    for transport in [ClientTransport::Quic, ClientTransport::Tcp] {
+        config.client.fingerprint = fingerprint.clone();
+        let (client, _incoming) = connect(&config, "erin", transport).await;
+        client.disconnect().await;
+
+        config.client.fingerprint = "00".repeat(32);
+        let mut wrong = config.clone();
+        wrong.client.transport = transport;
+        assert!(Client::connect(&wrong).await.is_err());
+    }
+}
+
+#[tokio::test]
+async fn reconnect_logs_in_with_zero_rtt() {
+    let config = start_server().await;
+    let (grace, mut grace_incoming) = connect(&config, "grace", ClientTransport::Quic).await;
+    let mut heidi_config = config.clone();
+    heidi_config.client.id = "heidi".to_string();
+    heidi_config.client.transport = ClientTransport::Quic;
+    let sessions = SessionCache::default();
+    let (heidi, _incoming) = Client::connect_with_sessions(&heidi_config, &sessions).await.unwrap();
+    heidi.disconnect().await;
+
+    // 第一次连接拿到的会话票据用于重连，登录随握手发出
+    let (heidi, _incoming) = Client::connect_with_sessions(&heidi_config, &sessions).await.unwrap();
+    assert!(heidi.zero_rtt());
+    heidi.send_text("resumed").await.unwrap();
+    assert_eq!(next_text(&mut grace_incoming).await.sender_id, "heidi");
+    heidi.disconnect().await;
+
+    heidi_config.client.zero_rtt = false;
+    let (heidi, _incoming) = Client::connect_with_sessions(&heidi_config, &sessions).await.unwrap();
+    assert!(!heidi.zero_rtt());
+    heidi.send_text("full handshake").await.unwrap();
+    assert_eq!(next_text(&mut grace_incoming).await.sender_id, "heidi");
+
+    heidi.disconnect().await;
+    grace.disconnect().await;
+}
+
+#[tokio::test]
+async fn tuned_transport_reports_stats() {
+    let mut config = start_server().await;
+    config.transport.congestion_controller = CongestionController::Bbr;
+    config.transport.mtu_discovery = false;
+    config.transport.initial_rtt_ms = 100;
+    config.transport.max_concurrent_streams = 8;
+    let (ivan, mut ivan_incoming) = connect(&config, "ivan", ClientTransport::Quic).await;
+    let (judy, _incoming) = connect(&config, "judy", ClientTransport::Tcp).await;
+
+    // 超过对方允许的并发流数，消息仍能依次发出
+    for i in 0..20 {
+        judy.send_text(format!("burst {}", i)).await.unwrap();
+    }
+    for _ in 0..20 {
+        assert_eq!(next_text(&mut ivan_incoming).await.sender_id, "judy");
+    }
+
+    let stats = ivan.stats().unwrap();
+    assert!(stats.rtt > Duration::ZERO);
+    assert!(stats.sent_bytes > 0 && stats.received_bytes > 0);
+    assert_eq!(stats.mtu_probes, 0);
+    assert!(judy.stats().is_none());
+
+    judy.disconnect().await;
+    ivan.disconnect().await;
+}
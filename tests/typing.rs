@@ -0,0 +1,116 @@
+//! 输入提示：客户端的 /typing 作为 QUIC 数据报经服务器转发给同房间的其他客户端，
+//! 对方显示一次“正在输入”，收到正文后提示结束，再次输入时重新显示。
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::UdpSocket,
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{Arc, Mutex},
+    thread,
+    time::{Duration, Instant},
+};
+
+const BIN: &str = env!("CARGO_BIN_EXE_t3xt");
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// 子进程及其收集到的标准输出，drop 时结束进程
+struct Process {
+    child: Child,
+    stdin: Option<ChildStdin>,
+    output: Arc<Mutex<Vec<String>>>,
+}
+
+impl Process {
+    fn spawn(args: &[&str]) -> Self {
+        let mut child = Command::new(BIN)
+            .args(args)
+            .current_dir(env!("CARGO_MANIFEST_DIR"))
+            // 日志写到被丢弃的标准错误，不在仓库中留下日志文件
+            .env("T3XT_LOG_DIRECTORY", "")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start t3xt");
+        let stdout = child.stdout.take().unwrap();
+        let output = Arc::new(Mutex::new(Vec::new()));
+        let lines = Arc::clone(&output);
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines().map_while(Result::ok) {
+                lines.lock().unwrap().push(line);
+            }
+        });
+        Self {
+            stdin: child.stdin.take(),
+            child,
+            output,
+        }
+    }
+
+    fn send(&mut self, line: &str) {
+        let stdin = self.stdin.as_mut().unwrap();
+        writeln!(stdin, "{}", line).unwrap();
+        stdin.flush().unwrap();
+    }
+
+    fn count(&self, needle: &str) -> usize {
+        self.output.lock().unwrap().iter().filter(|line| line.contains(needle)).count()
+    }
+
+    /// 等待输出中 needle 出现 `times` 次，超时则失败
+    fn wait_for_count(&self, needle: &str, times: usize) {
+        let start = Instant::now();
+        while self.count(needle) < times {
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for {:?}", needle);
+            thread::sleep(Duration::from_millis(100));
+        }
+    }
+
+    /// 反复发送 /who，直到所有成员都出现在同一次响应中
+    fn wait_for_members(&mut self, members: &[&str]) {
+        let start = Instant::now();
+        loop {
+            let seen = self.output.lock().unwrap().len();
+            self.send("/who");
+            thread::sleep(Duration::from_millis(300));
+            let output = self.output.lock().unwrap();
+            let response = &output[seen..];
+            if members.iter().all(|member| response.iter().any(|line| line.trim_start().starts_with(member))) {
+                return;
+            }
+            drop(output);
+            assert!(start.elapsed() < TIMEOUT, "timed out waiting for members {:?}", members);
+            thread::sleep(Duration::from_millis(300));
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[test]
+fn typing_indicator_reaches_the_room_once_per_burst() {
+    let port = UdpSocket::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port().to_string();
+    let _server = Process::spawn(&["serve", "-p", &port]);
+    let alice = Process::spawn(&["run", "-p", &port, "-i", "alice"]);
+    let mut bob = Process::spawn(&["run", "-p", &port, "-i", "bob"]);
+    bob.wait_for_members(&["alice", "bob"]);
+
+    // 提示有效期内重复的信号不再显示
+    bob.send("/typing");
+    bob.send("/typing");
+    alice.wait_for_count("bob is typing", 1);
+    thread::sleep(Duration::from_millis(500));
+    assert_eq!(alice.count("bob is typing"), 1);
+    assert_eq!(bob.count("is typing"), 0, "sender saw its own typing signal");
+
+    // 收到正文后提示结束，之后的输入提示重新显示
+    bob.send("hello");
+    alice.wait_for_count("bob: hello", 1);
+    bob.send("/typing");
+    alice.wait_for_count("bob is typing", 2);
+}
@@ -0,0 +1,117 @@
+//! 用本地 HTTP 服务代替 Webhook 接收方，验证事件过滤、HMAC 签名，
+//! 以及重试用尽后写入死信文件。
+
+use axum::{body::Bytes, http::{HeaderMap, StatusCode}, routing::post, Router};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    sync::Arc,
+    time::Duration,
+};
+use t3xt::{
+    client::Client,
+    config::{Config, WebhookSettings, WebhookTarget},
+    server::Server,
+    webhooks::{self, EVENT_HEADER, SIGNATURE_HEADER},
+};
+use tokio::{net::TcpListener, sync::mpsc, time};
+
+const SECRET: &str = "test-secret";
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 接收方收到的下一个请求：请求头、原始请求体和解析后的 JSON
+async fn next_event(received: &mut mpsc::UnboundedReceiver<(HeaderMap, Bytes)>) -> (HeaderMap, Bytes, serde_json::Value) {
+    let (headers, body) = time::timeout(TIMEOUT, received.recv()).await.unwrap().unwrap();
+    let event = serde_json::from_slice(&body).unwrap();
+    (headers, body, event)
+}
+
+#[tokio::test]
+async fn delivers_signed_events_and_dead_letters_failures() {
+    let (tx, mut received) = mpsc::unbounded_channel::<(HeaderMap, Bytes)>();
+    let app = Router::new()
+        .route(
+            "/hook",
+            post(move |headers: HeaderMap, body: Bytes| async move {
+                let _ = tx.send((headers, body));
+                StatusCode::OK
+            }),
+        )
+        .route("/fail", post(|| async { StatusCode::INTERNAL_SERVER_ERROR }));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let http = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, app).await });
+
+    let dead_letter = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("webhook-dead-letter.jsonl");
+    let _ = std::fs::remove_file(&dead_letter);
+
+    let mut config = Config::default();
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    config.webhooks = WebhookSettings {
+        targets: vec![
+            WebhookTarget {
+                url: format!("http://{}/hook", http),
+                secret: SECRET.to_string(),
+                rooms: vec!["ops".to_string()],
+                keywords: vec!["deploy".to_string()],
+                presence: true,
+                ..Default::default()
+            },
+            WebhookTarget {
+                url: format!("http://{}/fail", http),
+                keywords: vec!["deploy".to_string()],
+                ..Default::default()
+            },
+        ],
+        max_attempts: 3,
+        retry_backoff_ms: 10,
+        timeout_secs: 5,
+        dead_letter: dead_letter.clone(),
+    };
+
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.port = server.local_addr().unwrap().port();
+    config.client.id = "alice".to_string();
+    config.client.room = "ops".to_string();
+    tokio::spawn({
+        let server = Arc::clone(&server);
+        async move { server.run().await }
+    });
+
+    let (client, _incoming) = Client::connect(&config).await.unwrap();
+    client.send_text("hello").await.unwrap();
+    client.send_text("Deploy finished").await.unwrap();
+
+    // 不含关键字的 "hello" 不推送
+    let (headers, _, event) = next_event(&mut received).await;
+    assert_eq!(headers[EVENT_HEADER], "join");
+    assert_eq!(event["message"]["sender_id"], "alice");
+
+    let (headers, body, event) = next_event(&mut received).await;
+    assert_eq!(headers[EVENT_HEADER], "message");
+    assert_eq!(event["message"]["message_type"]["Text"]["content"], "Deploy finished");
+    assert_eq!(
+        headers[SIGNATURE_HEADER].to_str().unwrap(),
+        webhooks::sign(SECRET, &body).unwrap()
+    );
+
+    let line = time::timeout(TIMEOUT, async {
+        loop {
+            if let Ok(text) = tokio::fs::read_to_string(&dead_letter).await {
+                if let Some(line) = text.lines().next() {
+                    return line.to_string();
+                }
+            }
+            time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("dead letter not written");
+    let entry: serde_json::Value = serde_json::from_str(&line).unwrap();
+    assert_eq!(entry["url"], format!("http://{}/fail", http));
+    assert_eq!(entry["attempts"], 3);
+    assert_eq!(entry["payload"]["message"]["message_type"]["Text"]["content"], "Deploy finished");
+
+    client.disconnect().await;
+}
@@ -0,0 +1,391 @@
+//! WebTransport：按网关提供的哈希固定证书，用 HTTP/3 扩展 CONNECT 建立会话，
+//! 消息走 WebTransport 单向流，信号走数据报，与 QUIC 客户端互通。
+//! 配置的证书能按哈希固定或由 CA 签发时直接使用，开发用的自签名证书换成短期证书；
+//! 不合法的 SETTINGS 关闭连接，不合法的请求得到 404。
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener},
+    path::PathBuf,
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+use t3xt::{
+    client::Client,
+    config::Config,
+    message::{Message, MessageType, PresenceEvent, Signal, SignalKind},
+    server::Server,
+};
+use tokio::time;
+use tokio_stream::StreamExt;
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 只接受 SHA-256 与网关提供的哈希一致的证书，与浏览器的 `serverCertificateHashes` 相同
+struct PinnedHash(String);
+
+impl rustls::client::ServerCertVerifier for PinnedHash {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        let digest = ring::digest::digest(&ring::digest::SHA256, &end_entity.0);
+        let hash: String = digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect();
+        if hash == self.0 {
+            Ok(rustls::client::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::General("certificate hash mismatch".to_string()))
+        }
+    }
+}
+
+fn sha256(der: &[u8]) -> String {
+    let digest = ring::digest::digest(&ring::digest::SHA256, der);
+    digest.as_ref().iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+fn varint(value: u64, out: &mut Vec<u8>) {
+    match value {
+        0..=0x3f => out.push(value as u8),
+        0x40..=0x3fff => out.extend_from_slice(&(value as u16 | 0x4000).to_be_bytes()),
+        _ => panic!("varint too large for the test"),
+    }
+}
+
+fn frame(frame_type: u64, payload: &[u8], out: &mut Vec<u8>) {
+    varint(frame_type, out);
+    varint(payload.len() as u64, out);
+    out.extend_from_slice(payload);
+}
+
+/// 浏览器发出的扩展 CONNECT，字面字段用 Huffman 编码
+fn connect_request() -> Vec<u8> {
+    let mut fields = vec![0x00, 0x00];
+    fields.push(0xc0 | 15); // :method CONNECT
+    fields.push(0xc0 | 23); // :scheme https
+    fields.push(0x50); // :authority = localhost
+    fields.push(0x80 | 6);
+    fields.extend_from_slice(&[0xa0, 0xe4, 0x1d, 0x13, 0x9d, 0x09]);
+    fields.extend_from_slice(&[0x51, 0x01, b'/']); // :path = /
+    fields.extend_from_slice(&[0x2f, 0x00]); // :protocol = webtransport
+    fields.extend_from_slice(&[0xb9, 0x5d, 0x87, 0x49, 0xc8, 0x7a, 0x3f]);
+    fields.push(0x80 | 9);
+    fields.extend_from_slice(&[0xf0, 0x58, 0xd3, 0x60, 0xea, 0x45, 0x67, 0xb1, 0x3f]);
+    let mut data = Vec::new();
+    frame(0x01, &fields, &mut data);
+    data
+}
+
+/// 由字段行组成的 HEADERS 帧，必需插入数和基址都为 0
+fn headers(lines: &[&[u8]]) -> Vec<u8> {
+    let mut fields = vec![0x00, 0x00];
+    for line in lines {
+        fields.extend_from_slice(line);
+    }
+    let mut data = Vec::new();
+    frame(0x01, &fields, &mut data);
+    data
+}
+
+/// 引用静态表名称（:authority 为 0，:path 为 1）的字面字段行，不使用 Huffman 编码
+fn named(index: u8, value: &str) -> Vec<u8> {
+    let mut line = vec![0x50 | index, value.len() as u8];
+    line.extend_from_slice(value.as_bytes());
+    line
+}
+
+/// :protocol 字段，名称和值都是不使用 Huffman 编码的字面值
+fn protocol(value: &str) -> Vec<u8> {
+    // 名称长度 9 超出 3 位前缀，后面再跟一个字节 9 - 7
+    let mut line = vec![0x20 | 7, 2];
+    line.extend_from_slice(b":protocol");
+    line.push(value.len() as u8);
+    line.extend_from_slice(value.as_bytes());
+    line
+}
+
+/// 把证书和私钥写入临时目录，返回配置中使用的路径和证书的 SHA-256
+fn write_certificate(name: &str, cert_pem: &str, key_pem: &str) -> (PathBuf, PathBuf, String) {
+    let directory = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("webtransport-{}", name));
+    fs::create_dir_all(&directory).unwrap();
+    let (cert, key) = (directory.join("server.crt"), directory.join("server.key"));
+    fs::write(&cert, cert_pem).unwrap();
+    fs::write(&key, key_pem).unwrap();
+    let der = rustls_pemfile::certs(&mut cert_pem.as_bytes()).unwrap().remove(0);
+    (cert, key, sha256(&der))
+}
+
+fn configured(cert: PathBuf, key: PathBuf) -> Config {
+    let mut config = Config::default();
+    config.tls.cert = cert;
+    config.tls.key = key;
+    config
+}
+
+/// 启动开启网关和 WebTransport 的服务器，返回客户端配置和网关的 `/webtransport` 信息
+async fn start(mut config: Config) -> (Config, serde_json::Value) {
+    config.server.bind = IpAddr::V4(Ipv4Addr::LOCALHOST);
+    config.server.port = 0;
+    config.gateway.enabled = true;
+    config.gateway.listen = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+    config.webtransport.enabled = true;
+    config.webtransport.listen = "127.0.0.1:0".parse().unwrap();
+    let server = Arc::new(Server::builder(config.clone()).build().unwrap());
+    config.client.target = "127.0.0.1".to_string();
+    config.client.port = server.local_addr().unwrap().port();
+    tokio::spawn(async move { server.run().await });
+    let info = gateway_info(config.gateway.listen).await;
+    (config, info)
+}
+
+/// 以 h3 ALPN 连接 WebTransport 端口，只接受 SHA-256 为 `hash` 的证书
+async fn connect(info: &serde_json::Value, hash: &str) -> quinn::Connection {
+    let port = info["port"].as_u64().unwrap() as u16;
+    let mut tls = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(PinnedHash(hash.to_string())))
+        .with_no_client_auth();
+    tls.alpn_protocols = vec![b"h3".to_vec()];
+    let mut endpoint = quinn::Endpoint::client("127.0.0.1:0".parse().unwrap()).unwrap();
+    endpoint.set_default_client_config(quinn::ClientConfig::new(Arc::new(tls)));
+    endpoint
+        .connect(SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), port), "localhost")
+        .unwrap()
+        .await
+        .unwrap()
+}
+
+/// 在新的请求流上发送 `request`，返回响应 HEADERS 帧中的状态码
+async fn request_status(connection: &quinn::Connection, request: &[u8]) -> u16 {
+    let (mut send, mut recv) = connection.open_bi().await.unwrap();
+    send.write_all(request).await.unwrap();
+    let mut head = [0u8; 5];
+    time::timeout(TIMEOUT, recv.read_exact(&mut head)).await.unwrap().unwrap();
+    assert_eq!(&head[..4], &[0x01, head[1], 0x00, 0x00], "expected HEADERS");
+    // 静态表中的 :status 200 和 404
+    match head[4] & 0x3f {
+        25 => 200,
+        27 => 404,
+        other => panic!("unexpected status index {}", other),
+    }
+}
+
+/// 等待服务器关闭连接，返回应用错误码
+async fn close_code(connection: &quinn::Connection) -> u64 {
+    match time::timeout(TIMEOUT, connection.closed()).await.unwrap() {
+        quinn::ConnectionError::ApplicationClosed(close) => close.error_code.into_inner(),
+        other => panic!("unexpected close: {}", other),
+    }
+}
+
+async fn gateway_info(gateway: SocketAddr) -> serde_json::Value {
+    let url = format!("http://{}/webtransport", gateway);
+    for _ in 0..50 {
+        if let Ok(response) = reqwest::get(&url).await {
+            if response.status().is_success() {
+                return serde_json::from_str(&response.text().await.unwrap()).unwrap();
+            }
+        }
+        time::sleep(Duration::from_millis(100)).await;
+    }
+    panic!("WebTransport did not start");
+}
+
+async fn read_varint(recv: &mut quinn::RecvStream) -> u64 {
+    let mut buf = [0u8; 8];
+    recv.read_exact(&mut buf[..1]).await.unwrap();
+    let len = 1 << (buf[0] >> 6);
+    recv.read_exact(&mut buf[1..len]).await.unwrap();
+    buf[1..len].iter().fold(u64::from(buf[0] & 0x3f), |value, byte| (value << 8) | u64::from(*byte))
+}
+
+/// 读取服务器打开的下一个 WebTransport 单向流中的消息，跳过控制流等其他流
+async fn next_message(connection: &quinn::Connection) -> Message {
+    loop {
+        let mut recv = connection.accept_uni().await.unwrap();
+        if read_varint(&mut recv).await != 0x54 {
+            continue;
+        }
+        assert_eq!(read_varint(&mut recv).await, 0, "unexpected session ID");
+        let data = recv.read_to_end(8192).await.unwrap();
+        return Message::from_bytes(&data).unwrap();
+    }
+}
+
+#[tokio::test]
+async fn browser_session_talks_to_quic_clients() {
+    let (config, info) = start(Config::default()).await;
+    let connection = connect(&info, info["certificate_hash"].as_str().unwrap()).await;
+
+    // 控制流和空的 SETTINGS
+    let mut control = connection.open_uni().await.unwrap();
+    control.write_all(&[0x00, 0x04, 0x00]).await.unwrap();
+
+    let (mut request, mut response) = connection.open_bi().await.unwrap();
+    request.write_all(&connect_request()).await.unwrap();
+    let mut head = [0u8; 5];
+    response.read_exact(&mut head).await.unwrap();
+    assert_eq!(head[0], 0x01, "expected HEADERS");
+    assert_eq!(&head[2..5], &[0x00, 0x00, 0xc0 | 25], "expected :status 200");
+
+    let send = |message: Message| {
+        let connection = connection.clone();
+        async move {
+            let mut stream = connection.open_uni().await.unwrap();
+            stream.write_all(&[0x40, 0x54, 0x00]).await.unwrap();
+            stream.write_all(&message.to_bytes().unwrap()).await.unwrap();
+            stream.finish().await.unwrap();
+        }
+    };
+    let mut join = Message::new_presence("carol".to_string(), PresenceEvent::Join, None);
+    join.room = "lobby".to_string();
+    send(join).await;
+
+    let mut bob_config = config.clone();
+    bob_config.client.id = "bob".to_string();
+    let (bob, mut bob_incoming) = Client::connect(&bob_config).await.unwrap();
+
+    send(Message::new_text("carol".to_string(), "hello from the browser".to_string())).await;
+    let message = time::timeout(TIMEOUT, async {
+        loop {
+            let message = bob_incoming.next().await.expect("connection closed");
+            if matches!(message.message_type, MessageType::Text { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(message.sender_id, "carol");
+
+    bob.send_text("hello from quic").await.unwrap();
+    let message = time::timeout(TIMEOUT, async {
+        loop {
+            let message = next_message(&connection).await;
+            if matches!(message.message_type, MessageType::Text { .. }) {
+                return message;
+            }
+        }
+    })
+    .await
+    .unwrap();
+    assert_eq!(message.sender_id, "bob");
+
+    // 数据报以 quarter stream ID（会话 0）开头
+    let mut datagram = vec![0x00];
+    datagram.extend_from_slice(&Signal::new("carol".to_string(), SignalKind::Typing).to_bytes().unwrap());
+    connection.send_datagram(datagram.into()).unwrap();
+    let signal = time::timeout(TIMEOUT, bob_incoming.next_signal()).await.unwrap().unwrap();
+    assert_eq!(signal.sender_id, "carol");
+
+    bob.disconnect().await;
+    connection.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn pinnable_certificate_is_served_as_configured() {
+    use chrono::{Datelike, Utc};
+
+    let today = Utc::now();
+    let mut params = rcgen::CertificateParams::new(vec!["localhost".to_string()]);
+    params.alg = &rcgen::PKCS_ECDSA_P256_SHA256;
+    params.not_before = rcgen::date_time_ymd(today.year(), today.month() as u8, today.day() as u8)
+        - Duration::from_secs(24 * 3600);
+    params.not_after = params.not_before + Duration::from_secs(10 * 24 * 3600);
+    let cert = rcgen::Certificate::from_params(params).unwrap();
+    let (cert_path, key_path, hash) =
+        write_certificate("pinnable", &cert.serialize_pem().unwrap(), &cert.serialize_private_key_pem());
+
+    let (_, info) = start(configured(cert_path, key_path)).await;
+    assert_eq!(info["certificate_hash"].as_str(), Some(hash.as_str()));
+    connect(&info, &hash).await.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn ca_issued_certificate_is_served_without_a_hash() {
+    let mut ca_params = rcgen::CertificateParams::new(Vec::new());
+    ca_params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    ca_params.distinguished_name.push(rcgen::DnType::CommonName, "Test CA");
+    let ca = rcgen::Certificate::from_params(ca_params).unwrap();
+    // 默认有效期很长，不能按哈希固定，浏览器按 CA 验证
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let (cert_path, key_path, hash) = write_certificate(
+        "ca-issued",
+        &cert.serialize_pem_with_signer(&ca).unwrap(),
+        &cert.serialize_private_key_pem(),
+    );
+
+    let (_, info) = start(configured(cert_path, key_path)).await;
+    assert!(info["certificate_hash"].is_null(), "{}", info);
+    connect(&info, &hash).await.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn self_signed_certificate_falls_back_to_a_short_lived_one() {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let (cert_path, key_path, configured_hash) =
+        write_certificate("self-signed", &cert.serialize_pem().unwrap(), &cert.serialize_private_key_pem());
+
+    let (_, info) = start(configured(cert_path, key_path)).await;
+    let hash = info["certificate_hash"].as_str().unwrap();
+    assert_ne!(hash, configured_hash);
+    connect(&info, hash).await.close(0u32.into(), b"done");
+}
+
+#[tokio::test]
+async fn malformed_settings_close_the_connection() {
+    let (_, info) = start(Config::default()).await;
+    let hash = info["certificate_hash"].as_str().unwrap();
+    let cases: [(&[u8], u64); 4] = [
+        // 第一个帧不是 SETTINGS：H3_MISSING_SETTINGS
+        (&[0x00, 0x01, 0x00], 0x10a),
+        // 设置只有ID没有值：H3_FRAME_ERROR
+        (&[0x00, 0x04, 0x01, 0x08], 0x106),
+        // 重复的设置：H3_SETTINGS_ERROR
+        (&[0x00, 0x04, 0x04, 0x08, 0x01, 0x08, 0x01], 0x109),
+        // HTTP/2 的 MAX_CONCURRENT_STREAMS：H3_SETTINGS_ERROR
+        (&[0x00, 0x04, 0x02, 0x03, 0x10], 0x109),
+    ];
+    for (control_stream, code) in cases {
+        let connection = connect(&info, hash).await;
+        let mut control = connection.open_uni().await.unwrap();
+        control.write_all(control_stream).await.unwrap();
+        assert_eq!(close_code(&connection).await, code, "control stream {:02x?}", control_stream);
+    }
+}
+
+#[tokio::test]
+async fn malformed_requests_are_refused_and_the_connection_stays_usable() {
+    let (_, info) = start(Config::default()).await;
+    let connection = connect(&info, info["certificate_hash"].as_str().unwrap()).await;
+    let mut control = connection.open_uni().await.unwrap();
+    control.write_all(&[0x00, 0x04, 0x00]).await.unwrap();
+
+    const CONNECT: u8 = 0xc0 | 15;
+    const GET: u8 = 0xc0 | 17;
+    const HTTPS: u8 = 0xc0 | 23;
+    let authority = named(0, "localhost");
+    let path = named(1, "/");
+    let webtransport = protocol("webtransport");
+    let cases: [(&str, Vec<u8>); 7] = [
+        ("dynamic table reference", headers(&[&[0x01, 0x00]])),
+        ("invalid Huffman string", headers(&[&[0x51, 0x81, 0x00]])),
+        ("truncated field line", headers(&[&[CONNECT, HTTPS, 0x50, 0x09, b'l']])),
+        ("CONNECT without :protocol", headers(&[&[CONNECT, HTTPS], &authority, &path])),
+        ("CONNECT for WebSocket", headers(&[&[CONNECT, HTTPS], &authority, &path, &protocol("websocket")])),
+        ("GET with :protocol", headers(&[&[GET, HTTPS], &authority, &path, &webtransport])),
+        ("CONNECT without :path", headers(&[&[CONNECT, HTTPS], &authority, &webtransport])),
+    ];
+    for (case, request) in cases {
+        assert_eq!(request_status(&connection, &request).await, 404, "{}", case);
+    }
+
+    let request = headers(&[&[CONNECT, HTTPS], &authority, &path, &webtransport]);
+    assert_eq!(request_status(&connection, &request).await, 200);
+    connection.close(0u32.into(), b"done");
+}